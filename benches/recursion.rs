@@ -0,0 +1,60 @@
+//! Deep recursion on both engines, run with `cargo bench`.
+//!
+//! Prints the best time of a few runs of each script, the scripts mostly
+//! call functions and look up local variables.
+
+use std::time::{Duration, Instant};
+
+use monki::eval::{MAX_DEPTH, STACK_PER_CALL};
+use monki::Interpreter;
+
+const RUNS: usize = 5;
+
+const BENCHES: [(&str, &str); 3] = [
+    (
+        "sum",
+        "let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } };
+        let go = fn(i) { if (i == 0) { 0 } else { sum(990); go(i - 1) } };
+        go(200)",
+    ),
+    (
+        "fib",
+        "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+        fib(22)",
+    ),
+    (
+        "closures",
+        "let count = fn(n) {
+            let down = fn(k, acc) { if (k == 0) { acc } else { down(k - 1, acc + step) } };
+            let step = 1;
+            down(n, 0)
+        };
+        count(200000)",
+    ),
+];
+
+fn best(interpreter: &mut Interpreter, src: &str) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            interpreter.eval_str(src).unwrap();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    // The evaluator needs the native stack for the deepest recursion
+    let thread = std::thread::Builder::new()
+        .stack_size((MAX_DEPTH + 1) * STACK_PER_CALL)
+        .spawn(|| {
+            for (name, src) in BENCHES {
+                let eval = best(&mut Interpreter::new(), src);
+                let vm = best(&mut Interpreter::with_vm(), src);
+                println!("{:<10} eval {:>10.2?}  vm {:>10.2?}", name, eval, vm);
+            }
+        })
+        .unwrap();
+    thread.join().unwrap();
+}
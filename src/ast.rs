@@ -4,6 +4,26 @@ use crate::lexer::Token;
 pub struct Identifier {
     pub token: Token,
     pub value: String,
+    /// Where the variable lives, filled in by the [`crate::resolver`]
+    pub slot: Option<Slot>,
+}
+
+impl Identifier {
+    pub fn new(token: Token) -> Self {
+        Self {
+            value: token.literal.clone(),
+            token,
+            slot: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slot {
+    /// Local of the function `depth` levels out from the current one
+    Local { depth: usize, index: usize },
+    /// Global of the current file, or a native
+    Global,
 }
 
 impl std::fmt::Display for Identifier {
@@ -17,6 +37,7 @@ pub enum Literal {
     Integer(i64),
     Boolean(bool),
     String(String),
+    Interpolated(Vec<Expression>),
     Array(Vec<Expression>),
     Hash(Vec<(Expression, Expression)>),
 }
@@ -27,6 +48,15 @@ impl std::fmt::Display for Literal {
             Literal::Integer(int) => write!(f, "{}", int),
             Literal::Boolean(bool) => write!(f, "{}", bool),
             Literal::String(string) => write!(f, "{}", string),
+            Literal::Interpolated(parts) => {
+                for part in parts {
+                    match part {
+                        Expression::Literal(Literal::String(string)) => write!(f, "{}", string)?,
+                        _ => write!(f, "${{{}}}", part)?,
+                    }
+                }
+                Ok(())
+            }
             Literal::Array(array) => {
                 write!(f, "[")?;
                 for (i, expr) in array.iter().enumerate() {
@@ -53,12 +83,13 @@ impl std::fmt::Display for Literal {
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, PartialEq, Eq)]
 pub enum Expression {
     Identifier(Identifier),
     Literal(Literal),
     Prefix {
-        token: Token,
+        token: Token, // Operator tok
         operator: String,
         right: Box<Expression>,
     },
@@ -78,6 +109,7 @@ pub enum Expression {
         token: Token, // fn tok
         parameters: Vec<Identifier>,
         body: Box<BlockStatement>,
+        locals: usize, // slots needed by a call, parameters first
     },
     FunctionCall {
         token: Token,              // (
@@ -156,26 +188,56 @@ impl std::fmt::Display for Expression {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImportPath {
+    File(String), // "path/to/file.joe"
+    Std(String),  // std.math without the std
+}
+
+impl std::fmt::Display for ImportPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ImportPath::File(path) => write!(f, "{:?}", path),
+            ImportPath::Std(name) => write!(f, "std.{}", name),
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub enum Statement {
     Let {
-        token: Token,
+        token: Token, // let tok, fn for `export fn name() {}`
         name: Identifier,
         value: Expression,
     },
     ReAssign {
-        token: Token,
+        token: Token, // = tok
         name: Identifier,
         value: Expression,
     },
     Return {
-        token: Token,
+        token: Token, // return tok
         value: Expression,
     },
     Expression {
-        token: Token,
+        token: Token, // First tok of the expression
         value: Expression,
     },
+    Import {
+        token: Token, // import tok
+        path: ImportPath,
+        alias: Identifier,
+    },
+    // import { name, name as alias } from "path"
+    ImportNames {
+        token: Token, // import tok
+        path: ImportPath,
+        names: Vec<(Identifier, Identifier)>,
+    },
+    Export {
+        token: Token,              // export tok
+        statement: Box<Statement>, // Let
+    },
 }
 
 impl std::fmt::Debug for Statement {
@@ -189,6 +251,17 @@ impl std::fmt::Debug for Statement {
             Statement::ReAssign { name, value, .. } => {
                 write!(f, "reassign {} = {}", name, value)
             }
+            Statement::Import { path, alias, .. } => {
+                write!(f, "import {} as {}", path, alias)
+            }
+            Statement::ImportNames { path, names, .. } => {
+                let names: Vec<String> = names
+                    .iter()
+                    .map(|(name, alias)| format!("{} as {}", name, alias))
+                    .collect();
+                write!(f, "import {{ {} }} from {}", names.join(", "), path)
+            }
+            Statement::Export { statement, .. } => write!(f, "export {:?}", statement),
         }
     }
 }
@@ -0,0 +1,78 @@
+//! Fuel, deadline and value size limit for running untrusted scripts, shared
+//! by the [`crate::eval::Evaluator`] and the [`crate::vm::Vm`].
+//!
+//! Each step spends one unit of fuel, a step being an expression or
+//! statement for the evaluator and an instruction for the VM. The clock is
+//! only read every [`CLOCK_INTERVAL`] steps.
+//!
+//! The size limit bounds every single array, string and hash, nested
+//! values included, as measured by [`Object::size`]. Values are checked
+//! when they are created. Joined strings and natives that could build a
+//! large value check the size they need up front, the latter with
+//! [`crate::native::Ctx::reserve`].
+
+use std::time::Instant;
+
+use crate::error::{Resource, RuntimeError};
+use crate::object::Object;
+
+const CLOCK_INTERVAL: u32 = 1024;
+
+#[derive(Default)]
+pub(crate) struct Budget {
+    // Steps left, unlimited if `None`
+    pub(crate) fuel: Option<u64>,
+    deadline: Option<Instant>,
+    // Bytes a single value may take
+    pub(crate) value_size: Option<usize>,
+    // Steps until the clock is read again
+    until_clock: u32,
+}
+
+impl Budget {
+    pub(crate) fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+        self.until_clock = 0;
+    }
+
+    /// Spends one step
+    #[inline]
+    pub(crate) fn step(&mut self) -> Result<(), RuntimeError> {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(RuntimeError::ResourceExhausted(Resource::Fuel));
+            }
+            *fuel -= 1;
+        }
+
+        if let Some(deadline) = self.deadline {
+            if self.until_clock == 0 {
+                self.until_clock = CLOCK_INTERVAL;
+                if Instant::now() >= deadline {
+                    return Err(RuntimeError::ResourceExhausted(Resource::Time));
+                }
+            }
+            self.until_clock -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Fails if a value of `bytes` would go over the size limit
+    pub(crate) fn reserve(&self, bytes: usize) -> Result<(), RuntimeError> {
+        match self.value_size {
+            Some(limit) if bytes > limit => {
+                Err(RuntimeError::ResourceExhausted(Resource::ValueSize))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Passes on a newly created `value` that fits the size limit
+    pub(crate) fn check(&self, value: Object) -> Result<Object, RuntimeError> {
+        if self.value_size.is_some() {
+            self.reserve(value.size())?;
+        }
+        Ok(value)
+    }
+}
@@ -1,5 +1,23 @@
+//! Builtin functions and string properties.
+//!
+//! Strings are sequences of Unicode code points: `len`, `.length` and
+//! indexing (`s[i]`) all count code points, never bytes. Other views are
+//! explicit: `.bytes` gives the UTF-8 encoding as integers, `.chars` gives
+//! one string per code point and `.graphemes` gives one string per
+//! user-perceived character.
+
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::error::RuntimeError;
+use crate::format;
+use crate::json;
+use crate::native::{function, native, Ctx};
 use crate::object::Object;
 
 // will implement functions later
@@ -10,7 +28,7 @@ pub enum DotBuiltinKind {
 pub fn dot_str_builtins(string: &str, kind: DotBuiltinKind) -> Option<Object> {
     match kind {
         DotBuiltinKind::Property(name) => match name.as_str() {
-            "length" => Some(Object::Integer(string.len() as i64)),
+            "length" => Some(Object::Integer(char_len(string))),
             "chars" => Some(Object::Array(
                 string
                     .chars()
@@ -18,7 +36,10 @@ pub fn dot_str_builtins(string: &str, kind: DotBuiltinKind) -> Option<Object> {
                     .collect(),
             )),
             "bytes" => Some(Object::Array(
-                string.chars().map(|c| Object::Integer(c as i64)).collect(),
+                string.bytes().map(|b| Object::Integer(b as i64)).collect(),
+            )),
+            "graphemes" => Some(Object::Array(
+                graphemes(string).into_iter().map(Object::String).collect(),
             )),
             "is_empty" => Some(Object::Boolean(string.is_empty())),
             "is_numeric" => Some(Object::Boolean(
@@ -68,92 +89,512 @@ pub fn dot_str_builtins(string: &str, kind: DotBuiltinKind) -> Option<Object> {
     }
 }
 
+/// Number of code points in `string`.
+pub fn char_len(string: &str) -> i64 {
+    string.chars().count() as i64
+}
+
+/// Code point at `index`, counting from the end when negative.
+pub fn char_at(string: &str, index: i64) -> Option<char> {
+    if index < 0 {
+        string.chars().nth_back((index.unsigned_abs() - 1) as usize)
+    } else {
+        string.chars().nth(index as usize)
+    }
+}
+
+/// Splits `string` into grapheme clusters.
+///
+/// This is a simplified version of the extended grapheme cluster rules: it
+/// keeps `\r\n` together, attaches combining marks, variation selectors,
+/// emoji modifiers and tag characters to the preceding character, joins
+/// across zero width joiners and pairs up regional indicators (flags).
+pub fn graphemes(string: &str) -> Vec<String> {
+    let mut clusters: Vec<String> = Vec::new();
+    let mut prev: Option<char> = None;
+    let mut regional_run = 0;
+
+    for c in string.chars() {
+        let extend = match prev {
+            None => false,
+            Some('\r') => c == '\n',
+            Some('\u{200D}') => true,
+            Some(p) if is_regional_indicator(p) && is_regional_indicator(c) => {
+                regional_run % 2 == 1
+            }
+            Some(_) => is_grapheme_extend(c),
+        };
+
+        if is_regional_indicator(c) {
+            regional_run += 1;
+        } else {
+            regional_run = 0;
+        }
+
+        match clusters.last_mut() {
+            Some(cluster) if extend => cluster.push(c),
+            _ => clusters.push(c.to_string()),
+        }
+        prev = Some(c);
+    }
+
+    clusters
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+fn is_grapheme_extend(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'
+        | '\u{0483}'..='\u{0489}'
+        | '\u{0591}'..='\u{05BD}'
+        | '\u{05BF}' | '\u{05C1}'..='\u{05C2}' | '\u{05C4}'..='\u{05C5}' | '\u{05C7}'
+        | '\u{0610}'..='\u{061A}'
+        | '\u{064B}'..='\u{065F}'
+        | '\u{0670}'
+        | '\u{06D6}'..='\u{06DC}'
+        | '\u{06DF}'..='\u{06E4}'
+        | '\u{06E7}'..='\u{06E8}'
+        | '\u{06EA}'..='\u{06ED}'
+        | '\u{0900}'..='\u{0903}'
+        | '\u{093A}'..='\u{094F}'
+        | '\u{0951}'..='\u{0957}'
+        | '\u{0962}'..='\u{0963}'
+        | '\u{0E31}' | '\u{0E34}'..='\u{0E3A}' | '\u{0E47}'..='\u{0E4E}'
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{200C}'..='\u{200D}'
+        | '\u{20D0}'..='\u{20FF}'
+        | '\u{3099}'..='\u{309A}'
+        | '\u{FE00}'..='\u{FE0F}'
+        | '\u{FE20}'..='\u{FE2F}'
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        | '\u{E0020}'..='\u{E007F}'
+        | '\u{E0100}'..='\u{E01EF}'
+    )
+}
+
 pub fn builtins() -> HashMap<String, Object> {
     let mut map = HashMap::new();
 
     // Len
     map.insert(
         "len".to_string(),
-        Object::BuiltinFunction(|args| {
-            if args.len() != 1 {
-                return Object::Error(format!(
-                    "Wrong number of arguments. Got {}, expected 1",
-                    args.len()
-                ));
-            }
+        native("len", |_, args| {
+            args.expect_len(1)?;
 
             match &args[0] {
-                Object::String(string) => Object::Integer(string.len() as i64),
-                Object::Array(array) => Object::Integer(array.len() as i64),
-                _ => Object::Error(format!("Argument to `len` not supported, got {}", args[0])),
+                Object::String(string) => Ok(Object::Integer(char_len(string))),
+                Object::Array(array) => Ok(Object::Integer(array.len() as i64)),
+                _ => Err(RuntimeError::new(&format!(
+                    "Argument to `len` not supported, got {}",
+                    args[0].type_name()
+                ))),
             }
         }),
     );
 
     map.insert(
         "first".to_string(),
-        Object::BuiltinFunction(|args| {
-            if args.len() != 1 {
-                return Object::Error(format!(
-                    "Wrong number of arguments. Got {}, expected 1",
-                    args.len()
-                ));
-            }
+        native("first", |_, args| {
+            args.expect_len(1)?;
 
             match &args[0] {
-                Object::Array(array) => {
-                    if !array.is_empty() {
-                        return array[0].clone();
-                    }
-                    Object::Null
-                }
-                Object::String(string) => {
-                    if !string.is_empty() {
-                        return Object::String(string.chars().take(1).collect());
-                    }
-                    Object::Null
-                }
-                _ => Object::Error(format!(
-                    "Argument to `first` must be ARRAY, got {}",
-                    args[0]
-                )),
+                Object::Array(array) => Ok(array.first().cloned().unwrap_or(Object::Null)),
+                Object::String(string) => Ok(match string.chars().next() {
+                    Some(c) => Object::String(c.to_string()),
+                    None => Object::Null,
+                }),
+                _ => Err(args.type_error(0, "Array or String")),
             }
         }),
     );
 
     map.insert(
         "print".to_string(),
-        Object::BuiltinFunction(|args| {
-            println!(
-                "{}",
-                args.iter()
-                    .map(|arg| format!("{} ", arg))
-                    .collect::<String>()
-            );
-            Object::Empty
+        native("print", |ctx, args| {
+            ctx.permissions().check_stdout()?;
+            let line: String = args
+                .into_vec()
+                .iter()
+                .map(|arg| format!("{} ", arg))
+                .collect();
+            output("stdout", writeln!(io::stdout().lock(), "{}", line))?;
+            Ok(Object::Empty)
         }),
     );
 
     map.insert(
-        "push".to_string(),
-        Object::BuiltinFunction(|args| {
-            if args.len() != 2 {
-                return Object::Error(format!(
-                    "Wrong number of arguments. Got {}, expected 2",
-                    args.len()
-                ));
+        "eprint".to_string(),
+        native("eprint", |ctx, args| {
+            ctx.permissions().check_stderr()?;
+            let line: String = args
+                .into_vec()
+                .iter()
+                .map(|arg| format!("{} ", arg))
+                .collect();
+            output("stderr", writeln!(io::stderr().lock(), "{}", line))?;
+            Ok(Object::Empty)
+        }),
+    );
+
+    map.insert(
+        "format".to_string(),
+        native("format", |ctx, args| {
+            args.expect_min_len(1)?;
+            let template: String = args.arg(0)?;
+            ctx.reserve(format::padding(&template))?;
+            let values = args.into_vec();
+
+            match format::format(&template, &values[1..]) {
+                Ok(string) => Ok(Object::String(string)),
+                Err(msg) => Err(RuntimeError::new(&msg)),
             }
+        }),
+    );
 
-            match &args[0] {
-                Object::Array(array) => {
-                    let mut new_array = array.clone();
-                    new_array.push(args[1].clone());
-                    Object::Array(new_array)
+    map.insert(
+        "printf".to_string(),
+        native("printf", |ctx, args| {
+            args.expect_min_len(1)?;
+            let template: String = args.arg(0)?;
+            ctx.permissions().check_stdout()?;
+            ctx.reserve(format::padding(&template))?;
+            let values = args.into_vec();
+
+            match format::format(&template, &values[1..]) {
+                Ok(string) => {
+                    output("stdout", write!(io::stdout().lock(), "{}", string))?;
+                    Ok(Object::Empty)
                 }
-                _ => Object::Error(format!("Argument to `push` must be ARRAY, got {}", args[0])),
+                Err(msg) => Err(RuntimeError::new(&msg)),
             }
         }),
     );
 
+    map.insert(
+        "push".to_string(),
+        native("push", |ctx, args| {
+            args.expect_len(2)?;
+            let mut array: Vec<Object> = args.arg(0)?;
+            ctx.reserve(args[0].size() + args[1].size())?;
+            array.push(args[1].clone());
+            Ok(Object::Array(array))
+        }),
+    );
+
+    map.insert(
+        "json_parse".to_string(),
+        function("json_parse", |src: String| {
+            json::parse(&src).map_err(|err| RuntimeError::new(&err.to_string()))
+        }),
+    );
+
+    map.insert(
+        "json_stringify".to_string(),
+        native("json_stringify", |ctx, args| {
+            if args.is_empty() || args.len() > 2 {
+                return Err(args.arity_error("1 or 2"));
+            }
+            let indent = match args.arg::<Option<i64>>(1)? {
+                Some(indent) => match usize::try_from(indent) {
+                    Ok(indent) if indent <= json::MAX_INDENT => Some(indent),
+                    _ => {
+                        return Err(RuntimeError::new(&format!(
+                            "Argument 2 to `json_stringify` must be from 0 to {}, got {}",
+                            json::MAX_INDENT,
+                            indent
+                        )))
+                    }
+                },
+                None => None,
+            };
+
+            let len =
+                json::stringified_len(&args[0], indent).map_err(|msg| RuntimeError::new(&msg))?;
+            ctx.reserve(std::mem::size_of::<Object>().saturating_add(len))?;
+            match json::stringify(&args[0], indent) {
+                Ok(string) => Ok(Object::String(string)),
+                Err(msg) => Err(RuntimeError::new(&msg)),
+            }
+        }),
+    );
+
+    // Unset variables are null
+    map.insert(
+        "env_var".to_string(),
+        native("env_var", |ctx, args| {
+            args.expect_len(1)?;
+            let name: String = args.arg(0)?;
+            ctx.permissions().check_env(&name)?;
+
+            Ok(match std::env::var(&name) {
+                Ok(value) => Object::String(value),
+                Err(_) => Object::Null,
+            })
+        }),
+    );
+
+    // Stops the script, the host decides what exiting means
+    map.insert(
+        "exit".to_string(),
+        native("exit", |_, args| {
+            if args.len() > 1 {
+                return Err(args.arity_error("0 or 1"));
+            }
+            let code = args.arg::<Option<i64>>(0)?.unwrap_or(0);
+
+            match i32::try_from(code) {
+                Ok(code) => Err(RuntimeError::Exit(code)),
+                Err(_) => Err(RuntimeError::new(&format!("Invalid exit status: {}", code))),
+            }
+        }),
+    );
+
+    stdin_builtins(&mut map);
+    file_builtins(&mut map);
+    clock_builtins(&mut map);
+
     map
 }
+
+// Lines come without their line ending, the end of input is null
+fn stdin_builtins(map: &mut HashMap<String, Object>) {
+    map.insert(
+        "read_line".to_string(),
+        native("read_line", |ctx, args| {
+            args.expect_len(0)?;
+            ctx.permissions().check_stdin()?;
+
+            read_line()
+        }),
+    );
+
+    // Like `read_line` after printing a prompt
+    map.insert(
+        "input".to_string(),
+        native("input", |ctx, args| {
+            if args.len() > 1 {
+                return Err(args.arity_error("0 or 1"));
+            }
+            let prompt = args.arg::<Option<String>>(0)?;
+            ctx.permissions().check_stdin()?;
+
+            if let Some(prompt) = prompt {
+                ctx.permissions().check_stdout()?;
+                let mut stdout = io::stdout().lock();
+                output(
+                    "stdout",
+                    write!(stdout, "{}", prompt).and_then(|_| stdout.flush()),
+                )?;
+            }
+            read_line()
+        }),
+    );
+
+    map.insert(
+        "read_all".to_string(),
+        native("read_all", |ctx, args| {
+            args.expect_len(0)?;
+            ctx.permissions().check_stdin()?;
+
+            let input = read_to_end(ctx)?;
+            match input.is_empty() {
+                true => Ok(Object::Null),
+                false => Ok(Object::String(input)),
+            }
+        }),
+    );
+
+    // All lines left
+    map.insert(
+        "lines".to_string(),
+        native("lines", |ctx, args| {
+            args.expect_len(0)?;
+            ctx.permissions().check_stdin()?;
+
+            let input = read_to_end(ctx)?;
+            let lines = input.lines().count();
+            ctx.reserve((lines + 1) * std::mem::size_of::<Object>() + input.len())?;
+            let lines = input.lines().map(|line| Object::String(line.to_string()));
+            Ok(Object::Array(lines.collect()))
+        }),
+    );
+}
+
+fn read_line() -> Result<Object, RuntimeError> {
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) => Ok(Object::Null),
+        Ok(_) => {
+            let end = line.trim_end_matches('\n').trim_end_matches('\r').len();
+            line.truncate(end);
+            Ok(Object::String(line))
+        }
+        Err(err) => Err(stdin_error(&err)),
+    }
+}
+
+// The error for a failed write to the standard `stream`
+fn output(stream: &'static str, result: io::Result<()>) -> Result<(), RuntimeError> {
+    result.map_err(|err| match err.kind() {
+        io::ErrorKind::BrokenPipe => RuntimeError::StreamClosed(stream),
+        _ => RuntimeError::new(&format!("Cannot write to {}: {}", stream, err)),
+    })
+}
+
+// Reads what is left of stdin, each chunk is reserved before it is kept
+fn read_to_end(ctx: &Ctx) -> Result<String, RuntimeError> {
+    let mut stdin = io::stdin().lock();
+    let mut input = Vec::new();
+    loop {
+        let chunk = match stdin.fill_buf() {
+            Ok([]) => break,
+            Ok(chunk) => chunk,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(stdin_error(&err)),
+        };
+        ctx.reserve(std::mem::size_of::<Object>() + input.len() + chunk.len())?;
+        input.extend_from_slice(chunk);
+        let read = chunk.len();
+        stdin.consume(read);
+    }
+
+    String::from_utf8(input).map_err(|_| {
+        let err = io::Error::new(
+            io::ErrorKind::InvalidData,
+            "stream did not contain valid UTF-8",
+        );
+        stdin_error(&err)
+    })
+}
+
+fn stdin_error(err: &io::Error) -> RuntimeError {
+    RuntimeError::new(&format!("Cannot read from stdin: {}", err))
+}
+
+// Both need the clock permission, random numbers would let scripts time
+// things as well
+fn clock_builtins(map: &mut HashMap<String, Object>) {
+    // Milliseconds since the Unix epoch
+    map.insert(
+        "time".to_string(),
+        native("time", |ctx, args| {
+            args.expect_len(0)?;
+            ctx.permissions().check_clock()?;
+
+            let millis = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis());
+            Ok(Object::Integer(millis as i64))
+        }),
+    );
+
+    // An integer from 0 up to but not including `n`, not for cryptography
+    map.insert(
+        "random".to_string(),
+        native("random", |ctx, args| {
+            args.expect_len(1)?;
+            let n: i64 = args.arg(0)?;
+            ctx.permissions().check_clock()?;
+
+            if n <= 0 {
+                return Err(RuntimeError::new(&format!(
+                    "Argument to `random` must be positive, got {}",
+                    n
+                )));
+            }
+            // Every `RandomState` hashes with new keys
+            let random = RandomState::new().build_hasher().finish();
+            Ok(Object::Integer((random % n as u64) as i64))
+        }),
+    );
+}
+
+// Paths are relative to the working directory of the process
+fn file_builtins(map: &mut HashMap<String, Object>) {
+    map.insert(
+        "read_file".to_string(),
+        native("read_file", |ctx, args| {
+            args.expect_len(1)?;
+            let path: String = args.arg(0)?;
+            ctx.permissions().check_read(Path::new(&path))?;
+
+            let size = fs::metadata(&path).map_err(|err| io_error("read", &path, err))?;
+            ctx.reserve(size.len() as usize)?;
+            fs::read_to_string(&path)
+                .map(Object::String)
+                .map_err(|err| io_error("read", &path, err))
+        }),
+    );
+
+    map.insert(
+        "write_file".to_string(),
+        native("write_file", |ctx, args| {
+            args.expect_len(2)?;
+            let path: String = args.arg(0)?;
+            let contents: String = args.arg(1)?;
+            ctx.permissions().check_write(Path::new(&path))?;
+
+            fs::write(&path, contents).map_err(|err| io_error("write", &path, err))?;
+            Ok(Object::Empty)
+        }),
+    );
+
+    map.insert(
+        "append_file".to_string(),
+        native("append_file", |ctx, args| {
+            args.expect_len(2)?;
+            let path: String = args.arg(0)?;
+            let contents: String = args.arg(1)?;
+            ctx.permissions().check_write(Path::new(&path))?;
+
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(contents.as_bytes()))
+                .map_err(|err| io_error("append to", &path, err))?;
+            Ok(Object::Empty)
+        }),
+    );
+
+    // Names of the entries, sorted
+    map.insert(
+        "list_dir".to_string(),
+        native("list_dir", |ctx, args| {
+            args.expect_len(1)?;
+            let path: String = args.arg(0)?;
+            ctx.permissions().check_read(Path::new(&path))?;
+
+            let mut names = fs::read_dir(&path)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                        .collect::<std::io::Result<Vec<_>>>()
+                })
+                .map_err(|err| io_error("list", &path, err))?;
+            names.sort();
+            Ok(Object::Array(
+                names.into_iter().map(Object::String).collect(),
+            ))
+        }),
+    );
+
+    map.insert(
+        "exists".to_string(),
+        native("exists", |ctx, args| {
+            args.expect_len(1)?;
+            let path: String = args.arg(0)?;
+            ctx.permissions().check_read(Path::new(&path))?;
+
+            Ok(Object::Boolean(Path::new(&path).exists()))
+        }),
+    );
+}
+
+fn io_error(action: &str, path: &str, err: std::io::Error) -> RuntimeError {
+    RuntimeError::new(&format!("Cannot {} {:?}: {}", action, path, err))
+}
@@ -0,0 +1,575 @@
+//! Compiles a resolved [`Program`] to bytecode for the [`crate::vm::Vm`].
+//!
+//! Each function literal becomes a [`Function`] holding its instructions,
+//! its constant pool and the functions it creates. Locals are addressed by
+//! the slots the [`crate::resolver`] assigned, locals of enclosing
+//! functions through upvalues captured when the closure is created, and
+//! globals by name.
+
+use std::rc::Rc;
+
+use crate::ast::{Expression, Identifier, ImportPath, Literal, Program, Slot, Statement};
+use crate::lexer::Token;
+use crate::object::Object;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Pushes `constants[i]`
+    Constant(usize),
+    Null,
+    /// The value of statements without one, like `let`
+    Empty,
+    Pop,
+    DefineLocal(usize),
+    GetLocal(usize),
+    SetLocal(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    /// Globals are addressed by `names[i]`
+    DefineGlobal(usize),
+    GetGlobal(usize),
+    SetGlobal(usize),
+    Export(usize),
+    Prefix(&'static str),
+    Infix(&'static str),
+    /// Builds an array of the top `n` values
+    Array(usize),
+    /// Builds a hash of the top `n` key value pairs
+    Hash(usize),
+    /// Checks that the key on top of the stack can be used in a hash
+    HashKey,
+    /// Concatenates the top `n` values
+    Interpolate(usize),
+    Index,
+    GetProperty(usize),
+    SetProperty(usize),
+    /// Like `GetProperty`, but native objects dispatch `names[i]` as a method
+    GetMethod(usize),
+    /// Calls the function below the top `n` arguments
+    Call(usize),
+    /// Like `Call` followed by `Return`, but a closure reuses the frame
+    TailCall(usize),
+    /// Creates a closure of `functions[i]`
+    Closure(usize),
+    Jump(usize),
+    /// Pops a boolean and jumps if it is false
+    JumpIfFalse(usize),
+    Return,
+    /// Pushes the module `imports[i]`
+    Import(usize),
+    /// Pushes the member `names[i]` of the module on top of the stack
+    ImportMember(usize),
+    /// Raises `names[i]` as an error
+    Fail(usize),
+}
+
+/// Where a closure gets an upvalue from when it is created
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capture {
+    /// A local of the function creating the closure
+    Local(usize),
+    /// An upvalue of the function creating the closure
+    Upvalue(usize),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Function {
+    pub arity: usize,
+    /// Number of local slots, parameters included
+    pub locals: usize,
+    pub code: Vec<Op>,
+    pub constants: Vec<Object>,
+    pub names: Vec<String>,
+    pub functions: Vec<Rc<Function>>,
+    pub imports: Vec<ImportPath>,
+    pub upvalues: Vec<(Capture, String)>,
+    /// Names of the local slots, for errors
+    pub local_names: Vec<String>,
+    /// `callee at line:col` of the calls by index into `code`, for stack
+    /// overflow traces
+    pub call_sites: Vec<(usize, String)>,
+    /// Shown when the function is printed, the same as a tree-walked function
+    pub display: String,
+}
+
+impl Function {
+    fn emit(&mut self, op: Op) -> usize {
+        self.code.push(op);
+        self.code.len() - 1
+    }
+
+    fn patch(&mut self, jump: usize) {
+        let target = self.code.len();
+        match &mut self.code[jump] {
+            Op::Jump(to) | Op::JumpIfFalse(to) => *to = target,
+            op => unreachable!("Not a jump: {:?}", op),
+        }
+    }
+
+    fn constant(&mut self, obj: Object) -> usize {
+        self.constants.push(obj);
+        self.constants.len() - 1
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        if let Some(index) = self.names.iter().position(|other| other == name) {
+            return index;
+        }
+
+        self.names.push(name.to_string());
+        self.names.len() - 1
+    }
+
+    fn local_name(&mut self, index: usize, name: &str) {
+        if index >= self.local_names.len() {
+            self.local_names.resize(index + 1, String::new());
+        }
+        self.local_names[index] = name.to_string();
+    }
+}
+
+/// Compiles a program that went through the resolver, the result runs
+/// as a function without parameters
+pub fn compile(program: &Program) -> Rc<Function> {
+    let mut compiler = Compiler {
+        functions: vec![Function::default()],
+        statements: Vec::new(),
+    };
+
+    if program.is_empty() {
+        compiler.emit(Op::Null);
+    }
+    compiler.compile_block(program, false);
+    compiler.emit(Op::Return);
+
+    Rc::new(compiler.functions.pop().unwrap_or_default())
+}
+
+struct Compiler<'a> {
+    // Functions being compiled, innermost last, the program itself first
+    functions: Vec<Function>,
+    // Statements being compiled, innermost last
+    statements: Vec<&'a Statement>,
+}
+
+impl<'a> Compiler<'a> {
+    fn function(&mut self) -> &mut Function {
+        self.functions
+            .last_mut()
+            .expect("no function being compiled")
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.function().emit(op)
+    }
+
+    /// Leaves the value of the last statement on the stack. In a `tail`
+    /// block the last statement is what the function returns.
+    fn compile_block(&mut self, block: &'a [Statement], tail: bool) {
+        for (i, stmt) in block.iter().enumerate() {
+            let last = i == block.len() - 1;
+            self.compile_statement(stmt, last, tail && last);
+        }
+    }
+
+    /// Blocks of `if`s need a value, an empty one fails the statement it is in
+    fn compile_branch(&mut self, block: &'a [Statement], tail: bool) {
+        if !block.is_empty() {
+            return self.compile_block(block, tail);
+        }
+
+        let msg = match self.statements.last() {
+            Some(stmt) => format!("Could not evaluate statement: {:?}", stmt),
+            None => "Could not evaluate statement".to_string(),
+        };
+        let index = self.function().name(&msg);
+        self.emit(Op::Fail(index));
+    }
+
+    fn compile_statement(&mut self, stmt: &'a Statement, keep: bool, tail: bool) {
+        self.statements.push(stmt);
+
+        match stmt {
+            Statement::Expression { value, .. } if tail => self.compile_tail(value),
+            Statement::Expression { value, .. } => {
+                self.compile_expression(value);
+                if !keep {
+                    self.emit(Op::Pop);
+                }
+            }
+            // Only inside a function, a `return` at the top level ends the program
+            Statement::Return { value, .. } if self.functions.len() > 1 => {
+                self.compile_tail(value);
+                self.emit(Op::Return);
+            }
+            Statement::Return { value, .. } => {
+                self.compile_expression(value);
+                self.emit(Op::Return);
+            }
+            Statement::Let { name, value, .. } => {
+                self.compile_expression(value);
+                self.define(name);
+                self.empty(keep);
+            }
+            Statement::ReAssign { name, value, .. } => {
+                self.compile_expression(value);
+                self.set(name);
+                self.empty(keep);
+            }
+            Statement::Import { path, alias, .. } => {
+                self.import(path);
+                self.define(alias);
+                self.empty(keep);
+            }
+            Statement::ImportNames { path, names, .. } => {
+                self.import(path);
+                for (name, alias) in names {
+                    let index = self.function().name(&name.value);
+                    self.emit(Op::ImportMember(index));
+                    self.define(alias);
+                }
+                self.emit(Op::Pop);
+                self.empty(keep);
+            }
+            Statement::Export { statement, .. } => {
+                self.compile_statement(statement, false, false);
+                if let Statement::Let { name, .. } = statement.as_ref() {
+                    let index = self.function().name(&name.value);
+                    self.emit(Op::Export(index));
+                }
+                self.empty(keep);
+            }
+        }
+
+        self.statements.pop();
+    }
+
+    fn empty(&mut self, keep: bool) {
+        if keep {
+            self.emit(Op::Empty);
+        }
+    }
+
+    fn import(&mut self, path: &ImportPath) {
+        let function = self.function();
+        function.imports.push(path.clone());
+        let index = function.imports.len() - 1;
+        self.emit(Op::Import(index));
+    }
+
+    fn define(&mut self, name: &Identifier) {
+        match name.slot {
+            Some(Slot::Local { index, .. }) => {
+                self.function().local_name(index, &name.value);
+                self.emit(Op::DefineLocal(index));
+            }
+            Some(Slot::Global) | None => {
+                let index = self.function().name(&name.value);
+                self.emit(Op::DefineGlobal(index));
+            }
+        }
+    }
+
+    fn get(&mut self, name: &Identifier) {
+        let op = match name.slot {
+            Some(Slot::Local { depth: 0, index }) => Op::GetLocal(index),
+            Some(Slot::Local { depth, index }) => Op::GetUpvalue(self.upvalue(depth, index, name)),
+            Some(Slot::Global) | None => Op::GetGlobal(self.function().name(&name.value)),
+        };
+        self.emit(op);
+    }
+
+    fn set(&mut self, name: &Identifier) {
+        let op = match name.slot {
+            Some(Slot::Local { depth: 0, index }) => Op::SetLocal(index),
+            Some(Slot::Local { depth, index }) => Op::SetUpvalue(self.upvalue(depth, index, name)),
+            Some(Slot::Global) | None => Op::SetGlobal(self.function().name(&name.value)),
+        };
+        self.emit(op);
+    }
+
+    /// Index of the upvalue of the current function for the local `index`
+    /// of the function `depth` levels out, captured through every function
+    /// in between
+    fn upvalue(&mut self, depth: usize, index: usize, name: &Identifier) -> usize {
+        let level = self.functions.len() - 1;
+        self.capture(level, depth, index, &name.value)
+    }
+
+    fn capture(&mut self, level: usize, depth: usize, index: usize, name: &str) -> usize {
+        let capture = if depth == 1 {
+            Capture::Local(index)
+        } else {
+            Capture::Upvalue(self.capture(level - 1, depth - 1, index, name))
+        };
+
+        let upvalues = &mut self.functions[level].upvalues;
+        if let Some(i) = upvalues.iter().position(|(other, _)| *other == capture) {
+            return i;
+        }
+        upvalues.push((capture, name.to_string()));
+        upvalues.len() - 1
+    }
+
+    fn compile_expression(&mut self, expr: &'a Expression) {
+        match expr {
+            Expression::Identifier(iden) => self.get(iden),
+            Expression::Literal(lit) => self.compile_literal(lit),
+            Expression::Prefix {
+                operator, right, ..
+            } => {
+                self.compile_expression(right);
+                self.emit(Op::Prefix(operator_str(operator)));
+            }
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                ..
+            } => match (left.as_ref(), operator.as_str()) {
+                (
+                    Expression::DotNotation {
+                        left, right: name, ..
+                    },
+                    "=",
+                ) => {
+                    self.compile_expression(left);
+                    self.compile_expression(right);
+                    let index = self.function().name(&name.to_string());
+                    self.emit(Op::SetProperty(index));
+                }
+                _ => {
+                    self.compile_expression(left);
+                    self.compile_expression(right);
+                    self.emit(Op::Infix(operator_str(operator)));
+                }
+            },
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => self.compile_if(condition, consequence, alternative, false),
+            Expression::FunctionLiteral {
+                parameters,
+                body,
+                locals,
+                ..
+            } => {
+                let params: String = parameters.iter().map(|p| format!("{}, ", p)).collect();
+                let mut function = Function {
+                    arity: parameters.len(),
+                    locals: *locals,
+                    display: format!("fn({}) {{\n{:?}\n}}", params, body),
+                    ..Function::default()
+                };
+                for (index, param) in parameters.iter().enumerate() {
+                    function.local_name(index, &param.value);
+                }
+
+                self.functions.push(function);
+                if body.is_empty() {
+                    self.emit(Op::Null);
+                }
+                self.compile_block(body, true);
+                self.emit(Op::Return);
+                let function = self.functions.pop().unwrap_or_default();
+
+                let functions = &mut self.function().functions;
+                functions.push(Rc::new(function));
+                let index = functions.len() - 1;
+                self.emit(Op::Closure(index));
+            }
+            Expression::FunctionCall {
+                token,
+                function,
+                arguments,
+            } => self.compile_call(token, function, arguments, false),
+            Expression::IndexExpression { left, index, .. } => {
+                self.compile_expression(left);
+                self.compile_expression(index);
+                self.emit(Op::Index);
+            }
+            Expression::DotNotation { left, right, .. } => {
+                self.compile_expression(left);
+                let index = self.function().name(&right.to_string());
+                self.emit(Op::GetProperty(index));
+            }
+        }
+    }
+
+    /// Compiles the value a function returns, calls become tail calls
+    fn compile_tail(&mut self, expr: &'a Expression) {
+        match expr {
+            Expression::FunctionCall {
+                token,
+                function,
+                arguments,
+            } => self.compile_call(token, function, arguments, true),
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => self.compile_if(condition, consequence, alternative, true),
+            expr => self.compile_expression(expr),
+        }
+    }
+
+    fn compile_if(
+        &mut self,
+        condition: &'a Expression,
+        consequence: &'a [Statement],
+        alternative: &'a Option<Box<Program>>,
+        tail: bool,
+    ) {
+        self.compile_expression(condition);
+        let to_alternative = self.emit(Op::JumpIfFalse(0));
+        self.compile_branch(consequence, tail);
+        let to_end = self.emit(Op::Jump(0));
+
+        self.function().patch(to_alternative);
+        match alternative {
+            Some(alternative) => self.compile_branch(alternative, tail),
+            None => {
+                self.emit(Op::Null);
+            }
+        }
+        self.function().patch(to_end);
+    }
+
+    // Pushes the function and arguments of a call
+    fn compile_call(
+        &mut self,
+        token: &Token,
+        function: &'a Expression,
+        arguments: &'a [Expression],
+        tail: bool,
+    ) {
+        match function {
+            Expression::DotNotation { left, right, .. } => {
+                self.compile_expression(left);
+                let index = self.function().name(&right.to_string());
+                self.emit(Op::GetMethod(index));
+            }
+            function => self.compile_expression(function),
+        }
+        for arg in arguments {
+            self.compile_expression(arg);
+        }
+
+        let call = self.emit(match tail {
+            true => Op::TailCall(arguments.len()),
+            false => Op::Call(arguments.len()),
+        });
+        let site = format!("{} at {}", function, token.position);
+        self.function().call_sites.push((call, site));
+    }
+
+    fn compile_literal(&mut self, lit: &'a Literal) {
+        match lit {
+            Literal::Integer(int) => self.constant(Object::Integer(*int)),
+            Literal::Boolean(bool) => self.constant(Object::Boolean(*bool)),
+            Literal::String(string) => self.constant(Object::String(string.clone())),
+            Literal::Interpolated(parts) => {
+                for part in parts {
+                    self.compile_expression(part);
+                }
+                self.emit(Op::Interpolate(parts.len()));
+            }
+            Literal::Array(array) => {
+                for expr in array {
+                    self.compile_expression(expr);
+                }
+                self.emit(Op::Array(array.len()));
+            }
+            Literal::Hash(pairs) => {
+                for (key, value) in pairs {
+                    self.compile_expression(key);
+                    self.emit(Op::HashKey);
+                    self.compile_expression(value);
+                }
+                self.emit(Op::Hash(pairs.len()));
+            }
+        }
+    }
+
+    fn constant(&mut self, obj: Object) {
+        let index = self.function().constant(obj);
+        self.emit(Op::Constant(index));
+    }
+}
+
+// Operators are kept as static strings so `Op` stays `Copy`
+const OPERATORS: [&str; 16] = [
+    "+", "-", "*", "/", "<", ">", "==", "!=", "&", "|", "^", "<<", ">>", "!", "~", "=",
+];
+
+fn operator_str(operator: &str) -> &'static str {
+    OPERATORS
+        .iter()
+        .find(|other| **other == operator)
+        .copied()
+        .unwrap_or("?")
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{compile, Capture, Op};
+    use crate::lexer::Lexer;
+    use crate::object::Object;
+    use crate::parser::Parser;
+    use crate::resolver::resolve;
+
+    #[test]
+    fn test_compile() {
+        let input = "let a = 1; let f = fn(x) { fn() { x + a } }; f(2)()";
+
+        let mut l = Lexer::new(input.to_string());
+        let mut p = Parser::new(l.gen_tokens());
+        let mut program = p.parse_program().unwrap();
+        resolve(&mut program, HashSet::new()).unwrap();
+
+        let main = compile(&program);
+        assert_eq!(
+            main.code,
+            vec![
+                Op::Constant(0),
+                Op::DefineGlobal(0),
+                Op::Closure(0),
+                Op::DefineGlobal(1),
+                Op::GetGlobal(1),
+                Op::Constant(1),
+                Op::Call(1),
+                Op::Call(0),
+                Op::Return,
+            ]
+        );
+        assert_eq!(main.constants, vec![Object::Integer(1), Object::Integer(2)]);
+        assert_eq!(
+            main.call_sites,
+            vec![
+                (6, "f at 1:47".to_string()),
+                (7, "f(2) at 1:50".to_string())
+            ]
+        );
+
+        let f = &main.functions[0];
+        assert_eq!((f.arity, f.locals), (1, 1));
+        assert_eq!(f.code, vec![Op::Closure(0), Op::Return]);
+
+        let inner = &f.functions[0];
+        assert_eq!(inner.upvalues, vec![(Capture::Local(0), "x".to_string())]);
+        assert_eq!(
+            inner.code,
+            vec![
+                Op::GetUpvalue(0),
+                Op::GetGlobal(0),
+                Op::Infix("+"),
+                Op::Return
+            ]
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! Conversions between [`Object`] and Rust values, used for native function
+//! arguments and return values and for passing data in and out of scripts.
+//!
+//! Scalars map onto their variants, `Vec` onto arrays, `HashMap` and
+//! `BTreeMap` onto hashes and `Option` onto `null`. Conversions nest, a
+//! `Vec<HashMap<String, i64>>` reads an array of hashes of integers, and a
+//! mismatch reports where in the value it happened.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::rc::Rc;
+
+use crate::native::NativeObject;
+use crate::object::Object;
+
+/// A value did not have the shape a conversion expected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionError {
+    pub expected: String,
+    pub got: &'static str,
+    /// Location of the mismatch inside the converted value, e.g. `[1]["port"]`
+    pub path: String,
+}
+
+impl ConversionError {
+    pub fn new(expected: &str, got: &Object) -> Self {
+        Self {
+            expected: expected.to_string(),
+            got: got.type_name(),
+            path: String::new(),
+        }
+    }
+
+    fn at_index(mut self, index: usize) -> Self {
+        self.path = format!("[{}]{}", index, self.path);
+        self
+    }
+
+    fn at_key(mut self, key: &Object) -> Self {
+        self.path = format!("[{}]{}", crate::format::debug(key), self.path);
+        self
+    }
+}
+
+impl std::fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "Expected {}, got {}", self.expected, self.got)
+        } else {
+            write!(
+                f,
+                "Expected {} at {}, got {}",
+                self.expected, self.path, self.got
+            )
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+pub trait FromObject: Sized {
+    fn from_object(obj: Object) -> Result<Self, ConversionError>;
+}
+
+pub trait ToObject {
+    fn to_object(&self) -> Object;
+}
+
+impl FromObject for Object {
+    fn from_object(obj: Object) -> Result<Self, ConversionError> {
+        Ok(obj)
+    }
+}
+
+impl FromObject for i64 {
+    fn from_object(obj: Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Integer(int) => Ok(int),
+            _ => Err(ConversionError::new("Integer", &obj)),
+        }
+    }
+}
+
+// Narrower integers check the range, the error names the Rust type
+macro_rules! impl_integer {
+    ($($int:ty),*) => {
+        $(
+            impl FromObject for $int {
+                fn from_object(obj: Object) -> Result<Self, ConversionError> {
+                    match obj {
+                        Object::Integer(int) => <$int>::try_from(int).map_err(|_| {
+                            ConversionError::new(
+                                concat!("Integer in range of ", stringify!($int)),
+                                &obj,
+                            )
+                        }),
+                        _ => Err(ConversionError::new("Integer", &obj)),
+                    }
+                }
+            }
+
+            impl ToObject for $int {
+                fn to_object(&self) -> Object {
+                    Object::Integer(*self as i64)
+                }
+            }
+        )*
+    };
+}
+
+impl_integer!(i8, i16, i32, u8, u16, u32, usize);
+
+impl FromObject for bool {
+    fn from_object(obj: Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Boolean(bool) => Ok(bool),
+            _ => Err(ConversionError::new("Boolean", &obj)),
+        }
+    }
+}
+
+impl FromObject for String {
+    fn from_object(obj: Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::String(string) => Ok(string),
+            _ => Err(ConversionError::new("String", &obj)),
+        }
+    }
+}
+
+impl FromObject for Rc<dyn NativeObject> {
+    fn from_object(obj: Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Native(native) => Ok(native),
+            _ => Err(ConversionError::new("Native", &obj)),
+        }
+    }
+}
+
+impl<T: FromObject> FromObject for Vec<T> {
+    fn from_object(obj: Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Array(array) => array
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| T::from_object(item).map_err(|err| err.at_index(i)))
+                .collect(),
+            _ => Err(ConversionError::new("Array", &obj)),
+        }
+    }
+}
+
+/// `null` converts to `None`, anything else must convert to `T`
+impl<T: FromObject> FromObject for Option<T> {
+    fn from_object(obj: Object) -> Result<Self, ConversionError> {
+        match obj {
+            Object::Null => Ok(None),
+            _ => T::from_object(obj).map(Some),
+        }
+    }
+}
+
+fn hash_entries<K, V>(obj: Object) -> Result<Vec<(K, V)>, ConversionError>
+where
+    K: FromObject,
+    V: FromObject,
+{
+    match obj {
+        Object::Hash(hash) => hash
+            .into_iter()
+            .map(|(k, v)| {
+                let key = K::from_object(k.clone()).map_err(|err| err.at_key(&k))?;
+                let value = V::from_object(v).map_err(|err| err.at_key(&k))?;
+                Ok((key, value))
+            })
+            .collect(),
+        _ => Err(ConversionError::new("Hash", &obj)),
+    }
+}
+
+impl<K, V> FromObject for HashMap<K, V>
+where
+    K: FromObject + Eq + Hash,
+    V: FromObject,
+{
+    fn from_object(obj: Object) -> Result<Self, ConversionError> {
+        Ok(hash_entries(obj)?.into_iter().collect())
+    }
+}
+
+impl<K, V> FromObject for BTreeMap<K, V>
+where
+    K: FromObject + Ord,
+    V: FromObject,
+{
+    fn from_object(obj: Object) -> Result<Self, ConversionError> {
+        Ok(hash_entries(obj)?.into_iter().collect())
+    }
+}
+
+impl ToObject for Object {
+    fn to_object(&self) -> Object {
+        self.clone()
+    }
+}
+
+impl ToObject for i64 {
+    fn to_object(&self) -> Object {
+        Object::Integer(*self)
+    }
+}
+
+impl ToObject for bool {
+    fn to_object(&self) -> Object {
+        Object::Boolean(*self)
+    }
+}
+
+impl ToObject for String {
+    fn to_object(&self) -> Object {
+        Object::String(self.clone())
+    }
+}
+
+impl ToObject for str {
+    fn to_object(&self) -> Object {
+        Object::String(self.to_string())
+    }
+}
+
+impl ToObject for () {
+    fn to_object(&self) -> Object {
+        Object::Null
+    }
+}
+
+impl ToObject for Rc<dyn NativeObject> {
+    fn to_object(&self) -> Object {
+        Object::Native(Rc::clone(self))
+    }
+}
+
+impl<T: ToObject + ?Sized> ToObject for &T {
+    fn to_object(&self) -> Object {
+        (**self).to_object()
+    }
+}
+
+impl<T: ToObject> ToObject for [T] {
+    fn to_object(&self) -> Object {
+        Object::Array(self.iter().map(T::to_object).collect())
+    }
+}
+
+impl<T: ToObject> ToObject for Vec<T> {
+    fn to_object(&self) -> Object {
+        self.as_slice().to_object()
+    }
+}
+
+impl<T: ToObject> ToObject for Option<T> {
+    fn to_object(&self) -> Object {
+        match self {
+            Some(value) => value.to_object(),
+            None => Object::Null,
+        }
+    }
+}
+
+impl<K: ToObject, V: ToObject> ToObject for HashMap<K, V> {
+    fn to_object(&self) -> Object {
+        Object::Hash(
+            self.iter()
+                .map(|(k, v)| (k.to_object(), v.to_object()))
+                .collect(),
+        )
+    }
+}
+
+impl<K: ToObject, V: ToObject> ToObject for BTreeMap<K, V> {
+    fn to_object(&self) -> Object {
+        Object::Hash(
+            self.iter()
+                .map(|(k, v)| (k.to_object(), v.to_object()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap};
+
+    use super::{ConversionError, FromObject, ToObject};
+    use crate::object::Object;
+
+    fn string(s: &str) -> Object {
+        Object::String(s.to_string())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut config = BTreeMap::new();
+        config.insert("ports".to_string(), vec![80, 443]);
+        config.insert("empty".to_string(), vec![]);
+
+        let obj = config.to_object();
+        assert_eq!(
+            obj,
+            Object::Hash(vec![
+                (string("empty"), Object::Array(vec![])),
+                (
+                    string("ports"),
+                    Object::Array(vec![Object::Integer(80), Object::Integer(443)])
+                ),
+            ])
+        );
+        assert_eq!(BTreeMap::<String, Vec<u16>>::from_object(obj), Ok(config));
+
+        let hosts = vec![Some("a".to_string()), None];
+        assert_eq!(
+            Vec::<Option<String>>::from_object(hosts.to_object()),
+            Ok(hosts)
+        );
+
+        let mut ids = HashMap::new();
+        ids.insert(1, true);
+        assert_eq!(HashMap::<i64, bool>::from_object(ids.to_object()), Ok(ids));
+    }
+
+    #[test]
+    fn test_shape_mismatch() {
+        let obj = Object::Array(vec![
+            Object::Hash(vec![(string("port"), Object::Integer(80))]),
+            Object::Hash(vec![(string("port"), string("http"))]),
+        ]);
+        let err = Vec::<HashMap<String, i64>>::from_object(obj).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected Integer at [1][\"port\"], got String"
+        );
+
+        let tests = vec![
+            (
+                u8::from_object(Object::Integer(256)),
+                "Expected Integer in range of u8, got Integer",
+            ),
+            (u8::from_object(Object::Null), "Expected Integer, got Null"),
+        ];
+        for (result, expected) in tests {
+            assert_eq!(result.unwrap_err().to_string(), expected);
+        }
+
+        assert_eq!(
+            BTreeMap::<String, i64>::from_object(Object::Array(vec![])),
+            Err(ConversionError::new("Hash", &Object::Array(vec![])))
+        );
+    }
+}
@@ -0,0 +1,334 @@
+//! Tokens and syntax trees as [`Object`]s for [`crate::json::stringify`],
+//! used by `monki tokens --json` and `monki ast --json`.
+//!
+//! A token is `{"type", "literal", "position"}`, interpolated strings add
+//! `"parts"`, each `{"literal": text}` or `{"code": [tokens]}`. A position
+//! is `{"line", "col", "index"}`, all 0-based and counted in code points.
+//!
+//! Every node of a tree is a hash whose `"kind"` is the name of its
+//! statement, expression or literal variant, such as `"Let"`, `"Infix"` or
+//! `"Integer"`, followed by its fields. Nodes that hold a token also have
+//! its `"position"`, literals have none.
+
+use crate::ast::{BlockStatement, Expression, Identifier, ImportPath, Literal, Statement};
+use crate::lexer::{Position, StringPart, Token, TokenType};
+use crate::object::Object;
+
+pub fn tokens(tokens: &[Token]) -> Object {
+    Object::Array(tokens.iter().map(token).collect())
+}
+
+pub fn program(program: &BlockStatement) -> Object {
+    Object::Array(program.iter().map(statement).collect())
+}
+
+fn token(token: &Token) -> Object {
+    let ttype = match &token.ttype {
+        TokenType::Keyword(_) => String::from("Keyword"),
+        TokenType::Interpolated(_) => String::from("Interpolated"),
+        ttype => format!("{:?}", ttype),
+    };
+
+    let mut fields = vec![
+        ("type", string(&ttype)),
+        ("literal", string(&token.literal)),
+        ("position", position(&token.position)),
+    ];
+    if let TokenType::Keyword(keyword) = &token.ttype {
+        fields.push(("keyword", string(&format!("{:?}", keyword))));
+    }
+    if let TokenType::Interpolated(parts) = &token.ttype {
+        let parts = parts
+            .iter()
+            .map(|part| match part {
+                StringPart::Literal(text) => hash(vec![("literal", string(text))]),
+                StringPart::Code(code) => hash(vec![("code", tokens(code))]),
+            })
+            .collect();
+        fields.push(("parts", Object::Array(parts)));
+    }
+    hash(fields)
+}
+
+fn position(position: &Position) -> Object {
+    hash(vec![
+        ("line", integer(position.line())),
+        ("col", integer(position.col())),
+        ("index", integer(position.index())),
+    ])
+}
+
+fn statement(stmt: &Statement) -> Object {
+    match stmt {
+        Statement::Let { token, name, value } => node(
+            "Let",
+            token,
+            vec![("name", identifier(name)), ("value", expression(value))],
+        ),
+        Statement::ReAssign { token, name, value } => node(
+            "ReAssign",
+            token,
+            vec![("name", identifier(name)), ("value", expression(value))],
+        ),
+        Statement::Return { token, value } => {
+            node("Return", token, vec![("value", expression(value))])
+        }
+        Statement::Expression { token, value } => {
+            node("Expression", token, vec![("value", expression(value))])
+        }
+        Statement::Import { token, path, alias } => node(
+            "Import",
+            token,
+            vec![("path", import_path(path)), ("alias", identifier(alias))],
+        ),
+        Statement::ImportNames { token, path, names } => {
+            let names = names
+                .iter()
+                .map(|(name, alias)| {
+                    hash(vec![
+                        ("name", identifier(name)),
+                        ("alias", identifier(alias)),
+                    ])
+                })
+                .collect();
+            node(
+                "ImportNames",
+                token,
+                vec![("path", import_path(path)), ("names", Object::Array(names))],
+            )
+        }
+        Statement::Export {
+            token,
+            statement: exported,
+        } => node("Export", token, vec![("statement", statement(exported))]),
+    }
+}
+
+fn import_path(path: &ImportPath) -> Object {
+    match path {
+        ImportPath::File(path) => hash(vec![("kind", string("File")), ("path", string(path))]),
+        ImportPath::Std(name) => hash(vec![("kind", string("Std")), ("module", string(name))]),
+    }
+}
+
+fn identifier(ident: &Identifier) -> Object {
+    node(
+        "Identifier",
+        &ident.token,
+        vec![("name", string(&ident.value))],
+    )
+}
+
+fn expression(expr: &Expression) -> Object {
+    match expr {
+        Expression::Identifier(ident) => identifier(ident),
+        Expression::Literal(literal) => match literal {
+            Literal::Integer(int) => leaf("Integer", Object::Integer(*int)),
+            Literal::Boolean(bool) => leaf("Boolean", Object::Boolean(*bool)),
+            Literal::String(text) => leaf("String", string(text)),
+            Literal::Interpolated(parts) => hash(vec![
+                ("kind", string("Interpolated")),
+                ("parts", expressions(parts)),
+            ]),
+            Literal::Array(elements) => hash(vec![
+                ("kind", string("Array")),
+                ("elements", expressions(elements)),
+            ]),
+            Literal::Hash(pairs) => {
+                let pairs = pairs
+                    .iter()
+                    .map(|(key, value)| {
+                        hash(vec![("key", expression(key)), ("value", expression(value))])
+                    })
+                    .collect();
+                hash(vec![
+                    ("kind", string("Hash")),
+                    ("pairs", Object::Array(pairs)),
+                ])
+            }
+        },
+        Expression::Prefix {
+            token,
+            operator,
+            right,
+        } => node(
+            "Prefix",
+            token,
+            vec![("operator", string(operator)), ("right", expression(right))],
+        ),
+        Expression::Infix {
+            token,
+            left,
+            operator,
+            right,
+        } => node(
+            "Infix",
+            token,
+            vec![
+                ("operator", string(operator)),
+                ("left", expression(left)),
+                ("right", expression(right)),
+            ],
+        ),
+        Expression::If {
+            token,
+            condition,
+            consequence,
+            alternative,
+        } => node(
+            "If",
+            token,
+            vec![
+                ("condition", expression(condition)),
+                ("consequence", program(consequence)),
+                (
+                    "alternative",
+                    alternative.as_deref().map_or(Object::Null, program),
+                ),
+            ],
+        ),
+        Expression::FunctionLiteral {
+            token,
+            parameters,
+            body,
+            ..
+        } => node(
+            "FunctionLiteral",
+            token,
+            vec![
+                (
+                    "parameters",
+                    Object::Array(parameters.iter().map(identifier).collect()),
+                ),
+                ("body", program(body)),
+            ],
+        ),
+        Expression::FunctionCall {
+            token,
+            function,
+            arguments,
+        } => node(
+            "FunctionCall",
+            token,
+            vec![
+                ("function", expression(function)),
+                ("arguments", expressions(arguments)),
+            ],
+        ),
+        Expression::IndexExpression { token, left, index } => node(
+            "IndexExpression",
+            token,
+            vec![("left", expression(left)), ("index", expression(index))],
+        ),
+        Expression::DotNotation { token, left, right } => node(
+            "DotNotation",
+            token,
+            vec![("left", expression(left)), ("right", expression(right))],
+        ),
+    }
+}
+
+fn expressions(exprs: &[Expression]) -> Object {
+    Object::Array(exprs.iter().map(expression).collect())
+}
+
+// `{"kind", "position", fields...}`
+fn node(kind: &str, token: &Token, fields: Vec<(&str, Object)>) -> Object {
+    let mut pairs = vec![
+        ("kind", string(kind)),
+        ("position", position(&token.position)),
+    ];
+    pairs.extend(fields);
+    hash(pairs)
+}
+
+// A literal, `{"kind", "value"}`
+fn leaf(kind: &str, value: Object) -> Object {
+    hash(vec![("kind", string(kind)), ("value", value)])
+}
+
+fn hash(pairs: Vec<(&str, Object)>) -> Object {
+    Object::Hash(
+        pairs
+            .into_iter()
+            .map(|(key, value)| (string(key), value))
+            .collect(),
+    )
+}
+
+fn string(text: &str) -> Object {
+    Object::String(text.to_string())
+}
+
+fn integer(n: usize) -> Object {
+    Object::Integer(n as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::json::stringify;
+    use crate::lexer::Lexer;
+
+    #[test]
+    fn test_tokens() {
+        let tokens = Lexer::new(String::from("let s = \"a${x}\";")).gen_tokens();
+        let json = stringify(&super::tokens(&tokens[..4]), None).unwrap();
+
+        assert_eq!(
+            json,
+            concat!(
+                r#"[{"type":"Keyword","literal":"let","position":{"line":0,"col":0,"index":0},"#,
+                r#""keyword":"Let"},"#,
+                r#"{"type":"Ident","literal":"s","position":{"line":0,"col":4,"index":4}},"#,
+                r#"{"type":"Assign","literal":"=","position":{"line":0,"col":6,"index":6}},"#,
+                r#"{"type":"Interpolated","literal":"\"","position":{"line":0,"col":8,"index":8},"#,
+                r#""parts":[{"literal":"a"},{"code":["#,
+                r#"{"type":"Ident","literal":"x","position":{"line":0,"col":12,"index":12}},"#,
+                r#"{"type":"Eof","literal":"","position":{"line":0,"col":13,"index":13}}]}]}]"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_program() {
+        let program = crate::parse("let a = -b[0] + 1;\nif (a) { f(\"x\") }").unwrap();
+        let json = stringify(&super::program(&program), None).unwrap();
+
+        let pos = |line: usize, col: usize, index: usize| {
+            format!(
+                r#""position":{{"line":{},"col":{},"index":{}}}"#,
+                line, col, index
+            )
+        };
+        let expected = format!(
+            concat!(
+                r#"[{{"kind":"Let",{},"name":{{"kind":"Identifier",{},"name":"a"}},"#,
+                r#""value":{{"kind":"Infix",{},"operator":"+","#,
+                r#""left":{{"kind":"Prefix",{},"operator":"-","#,
+                r#""right":{{"kind":"IndexExpression",{},"#,
+                r#""left":{{"kind":"Identifier",{},"name":"b"}},"#,
+                r#""index":{{"kind":"Integer","value":0}}}}}},"#,
+                r#""right":{{"kind":"Integer","value":1}}}}}},"#,
+                r#"{{"kind":"Expression",{},"value":{{"kind":"If",{},"#,
+                r#""condition":{{"kind":"Identifier",{},"name":"a"}},"#,
+                r#""consequence":[{{"kind":"Expression",{},"value":{{"kind":"FunctionCall",{},"#,
+                r#""function":{{"kind":"Identifier",{},"name":"f"}},"#,
+                r#""arguments":[{{"kind":"String","value":"x"}}]}}}}],"#,
+                r#""alternative":null}}}}]"#,
+            ),
+            pos(0, 0, 0),
+            pos(0, 4, 4),
+            pos(0, 14, 14),
+            pos(0, 8, 8),
+            pos(0, 10, 10),
+            pos(0, 9, 9),
+            pos(1, 0, 19),
+            pos(1, 0, 19),
+            pos(1, 4, 23),
+            pos(1, 9, 28),
+            pos(1, 10, 29),
+            pos(1, 9, 28),
+        );
+        assert_eq!(json, expected);
+    }
+}
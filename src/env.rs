@@ -1,11 +1,21 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::object::Object;
 
+/// A scope. The global scope of a file keeps its variables by name in
+/// `store`, function calls keep theirs in `slots` numbered by the
+/// [`crate::resolver`].
 #[derive(Clone, PartialEq, Eq)]
 pub struct Env {
     store: HashMap<String, Object>,
+    slots: Vec<Option<Object>>,
     parent: Option<Rc<RefCell<Env>>>,
+    // Names marked with `export`, see `is_public`
+    exports: HashSet<String>,
 }
 
 impl Env {
@@ -13,7 +23,9 @@ impl Env {
         // TODO: If builtins dont work, look at this
         Self {
             store: HashMap::new(),
+            slots: Vec::new(),
             parent: None,
+            exports: HashSet::new(),
         }
     }
 
@@ -31,12 +43,88 @@ impl Env {
         self.store.insert(k.to_string(), v);
     }
 
-    pub fn extend(parent: Rc<RefCell<Env>>) -> Self {
+    /// Scope of a function call with `count` empty slots
+    pub fn with_slots(parent: Rc<RefCell<Env>>, count: usize) -> Self {
         Self {
             store: HashMap::new(),
+            slots: vec![None; count],
             parent: Some(parent),
+            exports: HashSet::new(),
         }
     }
+
+    /// The slot `index` of the scope `depth` levels up, `None` if not set yet
+    pub fn get_slot(&self, depth: usize, index: usize) -> Option<Object> {
+        if depth == 0 {
+            return self.slots.get(index).cloned().flatten();
+        }
+
+        self.parent
+            .as_ref()
+            .and_then(|parent| parent.borrow().get_slot(depth - 1, index))
+    }
+
+    pub fn set_slot(&mut self, depth: usize, index: usize, v: Object) {
+        if depth == 0 {
+            if index >= self.slots.len() {
+                self.slots.resize(index + 1, None);
+            }
+            self.slots[index] = Some(v);
+        } else if let Some(parent) = &self.parent {
+            parent.borrow_mut().set_slot(depth - 1, index, v);
+        }
+    }
+
+    /// A variable of the outermost (global) scope
+    pub fn get_global(&self, key: &str) -> Option<Object> {
+        match &self.parent {
+            Some(parent) => parent.borrow().get_global(key),
+            None => self.store.get(key).cloned(),
+        }
+    }
+
+    pub fn set_global(&mut self, k: &str, v: Object) {
+        match &self.parent {
+            Some(parent) => parent.borrow_mut().set_global(k, v),
+            None => self.set(k, v),
+        }
+    }
+
+    /// Names defined by name in this scope
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.store.keys()
+    }
+
+    pub fn export(&mut self, k: &str) {
+        self.exports.insert(k.to_string());
+    }
+
+    /// Whether importers may see `k`. Without any `export` in a module
+    /// all its bindings are public.
+    pub fn is_public(&self, k: &str) -> bool {
+        self.exports.is_empty() || self.exports.contains(k)
+    }
+
+    /// A public binding of this scope, used for module members
+    pub fn get_public(&self, k: &str) -> Result<Object, MemberError> {
+        match self.store.get(k) {
+            Some(obj) if self.is_public(k) => Ok(obj.clone()),
+            Some(_) => Err(MemberError::Private),
+            None => Err(MemberError::Missing),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberError {
+    Missing,
+    Private,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl std::fmt::Debug for Env {
@@ -0,0 +1,110 @@
+/// An error raised while evaluating a script.
+///
+/// Inside the evaluator errors travel as [`crate::object::Object::Error`]
+/// holding the `Display` text of this type, the structured value is kept
+/// alongside so embedders can match on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    Message(String),
+    Arity {
+        function: String,
+        expected: String,
+        got: usize,
+    },
+    Type {
+        function: String,
+        position: usize, // 1-based
+        expected: String,
+        got: &'static str,
+    },
+    /// More than `limit` script function calls were active at once, or
+    /// expressions and callbacks from natives nested deeper than the native
+    /// stack allows for that many
+    StackOverflow {
+        limit: usize,
+        /// Script function calls active, with the one that failed
+        calls: usize,
+        /// `callee at line:col` of the innermost calls, innermost first
+        trace: Vec<String>,
+    },
+    /// A limit set by the host was reached, see [`Resource`]
+    ResourceExhausted(Resource),
+    /// The host does not allow the effect described, see
+    /// [`crate::permissions::Permissions`]
+    PermissionDenied(String),
+    /// The script called `exit(code)`, it stops like on an error
+    Exit(i32),
+    /// The standard stream named was closed while the script wrote to it,
+    /// such as stdout piped into a program that stopped reading
+    StreamClosed(&'static str),
+}
+
+/// What a script ran out of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// The number of evaluation steps it was allowed
+    Fuel,
+    /// Time, its deadline passed
+    Time,
+    /// The size a single value may take, one grew over it
+    ValueSize,
+}
+
+/// How many calls a [`RuntimeError::StackOverflow`] trace shows
+pub(crate) const TRACE_LEN: usize = 10;
+
+impl RuntimeError {
+    pub fn new(msg: &str) -> Self {
+        RuntimeError::Message(msg.to_string())
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuntimeError::Message(msg) => write!(f, "{}", msg),
+            RuntimeError::Arity { expected, got, .. } => write!(
+                f,
+                "Wrong number of arguments. Got {}, expected {}",
+                got, expected
+            ),
+            RuntimeError::Type {
+                function,
+                position,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Argument {} to `{}` must be {}, got {}",
+                position, function, expected, got
+            ),
+            RuntimeError::StackOverflow {
+                limit,
+                calls,
+                trace,
+            } => {
+                match calls > limit {
+                    true => write!(f, "Stack overflow: more than {} nested calls", limit)?,
+                    false => write!(f, "Stack overflow: nested too deeply")?,
+                }
+                for call in trace {
+                    write!(f, "\n    in {}", call)?;
+                }
+                if *calls > trace.len() {
+                    write!(f, "\n    and {} more", calls - trace.len())?;
+                }
+                Ok(())
+            }
+            RuntimeError::ResourceExhausted(Resource::Fuel) => write!(f, "Out of fuel"),
+            RuntimeError::ResourceExhausted(Resource::Time) => write!(f, "Deadline exceeded"),
+            RuntimeError::ResourceExhausted(Resource::ValueSize) => write!(f, "Value too large"),
+            RuntimeError::PermissionDenied(effect) => write!(f, "Permission denied: {}", effect),
+            RuntimeError::Exit(code) => write!(f, "Exited with status {}", code),
+            RuntimeError::StreamClosed(stream) => {
+                write!(f, "Cannot write to {}, it was closed", stream)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
@@ -1,30 +1,155 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
 use std::rc::Rc;
+use std::time::Instant;
 
-use crate::ast::{BlockStatement, Expression, Identifier, Literal, Program, Statement};
+use crate::ast::{
+    BlockStatement, Expression, Identifier, ImportPath, Literal, Program, Slot, Statement,
+};
+use crate::budget::Budget;
 use crate::builtin::{self, DotBuiltinKind};
-use crate::env::Env;
+use crate::env::{Env, MemberError};
+use crate::error::{RuntimeError, TRACE_LEN};
 use crate::lexer::Token;
+use crate::loader::{Import, Loader};
+use crate::native::{self, Args, Caller, Ctx, IntoNative, NativeModule, NativeObject};
 use crate::object::Object;
+use crate::permissions::Permissions;
+use crate::resolver;
+
+/// Default limit on nested script function calls
+pub const MAX_DEPTH: usize = 1000;
+
+/// How many levels of nested expressions the evaluator allows per call of
+/// its depth limit, going over them is a [`RuntimeError::StackOverflow`]
+/// as well
+pub const NESTING_PER_CALL: usize = 8;
+
+/// Native stack the evaluator may need per call of its depth limit in a
+/// debug build, release builds need about a third of it. Run it on a thread
+/// with `(max_depth + 1) * STACK_PER_CALL` bytes of stack, the default limit
+/// needs far more than the 8 MiB of a main thread.
+pub const STACK_PER_CALL: usize = NESTING_PER_CALL * 16 * 1024;
 
 pub struct Evaluator {
     env: Rc<RefCell<Env>>,
+    // Builtins and host registered natives, looked up after the environment
+    natives: HashMap<String, Object>,
+    // Structured form of the error object currently being propagated
+    error: Option<RuntimeError>,
+    loader: Loader,
+    // Whether a `return f(x)` may leave the call to the trampoline in
+    // `apply_function`, only inside a function body
+    tail_calls: bool,
+    // Call deferred by `eval_tail_call`
+    tail_call: Option<(Object, Vec<Object>)>,
+    // Active script function calls, a tail call replaces the one it is in
+    depth: usize,
+    max_depth: usize,
+    // Expressions being evaluated, they use the native stack like calls
+    nesting: usize,
+    budget: Budget,
+    permissions: Permissions,
 }
 
 impl Evaluator {
     pub fn new() -> Self {
         Self {
             env: Rc::new(RefCell::new(Env::new())),
+            natives: builtin::builtins(),
+            error: None,
+            loader: Loader::default(),
+            tail_calls: false,
+            tail_call: None,
+            depth: 0,
+            max_depth: MAX_DEPTH,
+            nesting: 0,
+            budget: Budget::default(),
+            permissions: Permissions::default(),
+        }
+    }
+
+    /// Evaluates the resolved `program` as the contents of the file at
+    /// `path`, imports in it are resolved relative to the file
+    pub fn eval_file(&mut self, program: &Program, path: &Path) -> Option<Object> {
+        let path = path.canonicalize().unwrap_or(path.to_path_buf());
+
+        self.loader.files.push(path);
+        let result = self.eval_resolved(program);
+        self.loader.files.pop();
+
+        result
+    }
+
+    /// Runs the [`crate::resolver`] on `program` against the current globals
+    pub fn resolve(&self, program: &mut Program) -> Result<(), Vec<String>> {
+        let globals = self
+            .env
+            .borrow()
+            .names()
+            .chain(self.natives.keys())
+            .cloned()
+            .collect();
+
+        resolver::resolve(program, globals)
+    }
+
+    pub fn register_native<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&mut Ctx<'_>, Args) -> Result<Object, RuntimeError> + 'static,
+    {
+        self.natives
+            .insert(name.to_string(), native::native(name, func));
+    }
+
+    pub fn register_fn<F, Marker>(&mut self, name: &str, func: F)
+    where
+        F: IntoNative<Marker>,
+    {
+        self.natives
+            .insert(name.to_string(), native::function(name, func));
+    }
+
+    pub fn register_module(&mut self, module: NativeModule) {
+        self.natives
+            .insert(module.name().to_string(), module.into_object());
+    }
+
+    /// Global variable or native called `name`
+    pub fn lookup(&self, name: &str) -> Option<Object> {
+        self.env
+            .borrow()
+            .get(name)
+            .or_else(|| self.natives.get(name).cloned())
+    }
+
+    /// Structured error for an error object's message, see [`RuntimeError`]
+    pub fn take_error(&mut self, msg: String) -> RuntimeError {
+        match self.error.take() {
+            Some(error) if error.to_string() == msg => error,
+            _ => RuntimeError::Message(msg),
         }
     }
 
+    /// Resolves and evaluates `program`, undefined variables come back as an error object
     pub fn eval(&mut self, program: &Program) -> Option<Object> {
+        let mut program = program.clone();
+        if let Err(errors) = self.resolve(&mut program) {
+            return Some(self.new_error(&errors.join("\n")));
+        }
+
+        self.eval_resolved(&program)
+    }
+
+    /// Evaluates a program that went through [`Evaluator::resolve`]
+    pub fn eval_resolved(&mut self, program: &Program) -> Option<Object> {
         let mut result: Option<Object> = None;
 
         for stmt in program {
             match self.eval_statement(stmt) {
                 Some(Object::Return(obj)) => return Some(*obj),
-                Some(Object::Error(msg)) => println!("{}", msg),
+                Some(Object::Error(msg)) => return Some(Object::Error(msg)),
                 Some(obj) => result = Some(obj),
                 None => {
                     return Some(
@@ -37,8 +162,120 @@ impl Evaluator {
         result
     }
 
-    fn new_error(&self, msg: &str) -> Object {
-        Object::Error(msg.to_string())
+    /// Looks up a variable in the global scope
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        self.env.borrow().get(name)
+    }
+
+    /// Defines (or overwrites) a variable in the global scope
+    pub fn set_global(&mut self, name: &str, value: Object) {
+        self.env.borrow_mut().set(name, value);
+    }
+
+    /// Limits how many script function calls may be active at once, going
+    /// over it raises [`RuntimeError::StackOverflow`]. Every call uses the
+    /// native stack, see [`STACK_PER_CALL`] for how much.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Limits how many more expressions and statements scripts may
+    /// evaluate, see [`crate::Interpreter::set_fuel`]
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.budget.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.budget.fuel
+    }
+
+    /// Stops scripts still running at `deadline`
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.budget.set_deadline(deadline);
+    }
+
+    /// Limits the bytes any single array, string or hash may take, see
+    /// [`crate::Interpreter::set_value_size_limit`]
+    pub fn set_value_size_limit(&mut self, bytes: Option<usize>) {
+        self.budget.value_size = bytes;
+    }
+
+    /// Sets which effects on the host scripts may have
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+    }
+
+    /// Calls a function object with already evaluated arguments
+    pub fn apply_function(&mut self, mut function: Object, mut arguments: Vec<Object>) -> Object {
+        // Calls in tail position come back here instead of nesting
+        loop {
+            match function {
+                Object::Function {
+                    parameters,
+                    body,
+                    env,
+                    locals,
+                } => {
+                    if arguments.len() != parameters.len() {
+                        return self.raise(RuntimeError::Arity {
+                            function: "fn".to_string(),
+                            expected: parameters.len().to_string(),
+                            got: arguments.len(),
+                        });
+                    }
+
+                    let old_env = Rc::clone(&self.env);
+                    let mut new_env = Env::with_slots(Rc::clone(&env), locals);
+                    for (index, arg) in arguments.into_iter().enumerate() {
+                        new_env.set_slot(0, index, arg);
+                    }
+
+                    self.env = Rc::new(RefCell::new(new_env));
+                    let old_tail_calls = std::mem::replace(&mut self.tail_calls, true);
+                    let object = self.eval_tail_block(&body);
+                    self.tail_calls = old_tail_calls;
+                    self.env = old_env;
+
+                    if let (Some(Object::Return(_)), Some((next, args))) =
+                        (&object, self.tail_call.take())
+                    {
+                        function = next;
+                        arguments = args;
+                        continue;
+                    }
+
+                    // A `return` only leaves the function it is in
+                    return match object {
+                        Some(Object::Return(obj)) => *obj,
+                        Some(obj) => obj,
+                        None => Object::Null,
+                    };
+                }
+                Object::BuiltinFunction(func) => {
+                    let result = func.call(&mut Ctx { caller: self }, arguments);
+                    let result = result.and_then(|obj| self.budget.check(obj));
+                    return self.result(result);
+                }
+                _ => return self.new_error(&format!("Not a function: {}", function)),
+            }
+        }
+    }
+
+    fn new_error(&mut self, msg: &str) -> Object {
+        self.raise(RuntimeError::new(msg))
+    }
+
+    fn result(&mut self, result: Result<Object, RuntimeError>) -> Object {
+        match result {
+            Ok(obj) => obj,
+            Err(error) => self.raise(error),
+        }
+    }
+
+    fn raise(&mut self, error: RuntimeError) -> Object {
+        let obj = Object::Error(error.to_string());
+        self.error = Some(error);
+        obj
     }
 
     fn eval_block_statement(&mut self, stmts: BlockStatement) -> Option<Object> {
@@ -47,7 +284,35 @@ impl Evaluator {
         for stmt in stmts {
             match self.eval_statement(&stmt) {
                 Some(Object::Return(obj)) => return Some(Object::Return(obj)),
-                Some(Object::Error(msg)) => println!("{}", msg),
+                Some(Object::Error(msg)) => return Some(Object::Error(msg)),
+                Some(obj) => result = Some(obj),
+                None => {
+                    return Some(
+                        self.new_error(&format!("Could not evaluate statement: {:?}", stmt)),
+                    )
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Like `eval_block_statement` for a function body, its last statement
+    /// is in tail position
+    fn eval_tail_block(&mut self, stmts: &[Statement]) -> Option<Object> {
+        let mut result: Option<Object> = None;
+
+        for (i, stmt) in stmts.iter().enumerate() {
+            let value = match stmt {
+                Statement::Expression { value, .. } if i == stmts.len() - 1 => {
+                    self.eval_tail_expression(value)
+                }
+                stmt => self.eval_statement(stmt),
+            };
+
+            match value {
+                Some(Object::Return(obj)) => return Some(Object::Return(obj)),
+                Some(Object::Error(msg)) => return Some(Object::Error(msg)),
                 Some(obj) => result = Some(obj),
                 None => {
                     return Some(
@@ -60,7 +325,54 @@ impl Evaluator {
         result
     }
 
+    fn eval_tail_expression(&mut self, value: &Expression) -> Option<Object> {
+        match value {
+            Expression::FunctionCall {
+                function,
+                arguments,
+                ..
+            } => self.eval_tail_call(function, arguments),
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => match self.eval_expression(condition)? {
+                Object::Error(msg) => Some(Object::Error(msg)),
+                Object::Boolean(true) => self.eval_tail_block(consequence),
+                Object::Boolean(false) => match alternative {
+                    Some(alt) => self.eval_tail_block(alt),
+                    None => Some(Object::Null),
+                },
+                _ => Some(self.new_error("Use if conditionals on booleans")),
+            },
+            value => self.eval_expression(value),
+        }
+    }
+
+    /// Evaluates the function and arguments of a call in tail position and
+    /// leaves calling a script function to `apply_function`, which it
+    /// returns to as if the current function returned
+    fn eval_tail_call(
+        &mut self,
+        function: &Expression,
+        arguments: &[Expression],
+    ) -> Option<Object> {
+        match self.eval_call_parts(function, arguments) {
+            Ok((function @ Object::Function { .. }, arguments)) => {
+                self.tail_call = Some((function, arguments));
+                Some(Object::Return(Box::new(Object::Null)))
+            }
+            Ok((function, arguments)) => Some(self.apply_function(function, arguments)),
+            Err(result) => result,
+        }
+    }
+
     fn eval_statement(&mut self, stmt: &Statement) -> Option<Object> {
+        if let Err(error) = self.budget.step() {
+            return Some(self.raise(error));
+        }
+
         match stmt {
             Statement::Expression { token: _, value } => self.eval_expression(value),
             Statement::Return { token: _, value } => self.eval_return(value),
@@ -70,7 +382,11 @@ impl Evaluator {
                 value,
             } => {
                 let value = self.eval_expression(value)?;
-                self.env.borrow_mut().set(&name.value, value);
+                if let Object::Error(_) = value {
+                    return Some(value);
+                }
+
+                self.define(name, value);
                 Some(Object::Empty)
             }
             Statement::ReAssign {
@@ -78,32 +394,137 @@ impl Evaluator {
                 name,
                 value,
             } => self.eval_reassign(name, value),
+            Statement::Import {
+                token: _,
+                path,
+                alias,
+            } => {
+                let module = self.eval_import(path);
+                if let Object::Error(_) = module {
+                    return Some(module);
+                }
+
+                self.define(alias, module);
+                Some(Object::Empty)
+            }
+            Statement::ImportNames {
+                token: _,
+                path,
+                names,
+            } => {
+                let (path, env) = match self.eval_import(path) {
+                    Object::Module { path, env } => (path, env),
+                    error => return Some(error),
+                };
+
+                for (name, alias) in names {
+                    let value = env.borrow().get_public(&name.value);
+                    match value {
+                        Ok(value) => self.define(alias, value),
+                        Err(err) => return Some(self.raise(member_error(err, &path, &name.value))),
+                    }
+                }
+                Some(Object::Empty)
+            }
+            Statement::Export {
+                token: _,
+                statement,
+            } => {
+                let result = self.eval_statement(statement);
+                if let Statement::Let { name, .. } = statement.as_ref() {
+                    self.env.borrow_mut().export(&name.value);
+                }
+                result
+            }
+        }
+    }
+
+    fn define(&mut self, name: &Identifier, value: Object) {
+        match name.slot {
+            Some(Slot::Local { depth, index }) => {
+                self.env.borrow_mut().set_slot(depth, index, value)
+            }
+            Some(Slot::Global) | None => self.env.borrow_mut().set(&name.value, value),
+        }
+    }
+
+    fn eval_import(&mut self, path: &ImportPath) -> Object {
+        let load = match self.loader.import(path, &self.permissions) {
+            Ok(Import::Loaded(module)) => return module,
+            Ok(Import::Load(load)) => load,
+            Err(error) => return self.raise(error),
+        };
+        let program = match load.parse(&self.natives) {
+            Ok(program) => program,
+            Err(error) => return self.raise(error),
+        };
+
+        let old_env = std::mem::replace(&mut self.env, Rc::clone(&load.env));
+        let old_tail_calls = std::mem::replace(&mut self.tail_calls, false);
+        let result = match &load.file {
+            Some(file) => self.eval_file(&program, file),
+            None => self.eval_resolved(&program),
+        };
+        self.tail_calls = old_tail_calls;
+        self.env = old_env;
+
+        if let Some(Object::Error(msg)) = result {
+            return Object::Error(msg);
         }
+        self.loader.finish(load)
     }
 
     fn eval_reassign(&mut self, name: &Identifier, value: &Expression) -> Option<Object> {
         let value = self.eval_expression(value)?;
+        if let Object::Error(_) = value {
+            return Some(value);
+        }
 
-        if self.env.borrow_mut().get(&name.value).is_some() {
-            self.env.borrow_mut().set(&name.value, value);
-            return Some(Object::Empty);
+        let mut env = self.env.borrow_mut();
+        match name.slot {
+            Some(Slot::Local { depth, index }) if env.get_slot(depth, index).is_some() => {
+                env.set_slot(depth, index, value);
+                return Some(Object::Empty);
+            }
+            Some(Slot::Global) if env.get_global(&name.value).is_some() => {
+                env.set_global(&name.value, value);
+                return Some(Object::Empty);
+            }
+            None if env.get(&name.value).is_some() => {
+                env.set(&name.value, value);
+                return Some(Object::Empty);
+            }
+            _ => {}
         }
+        drop(env);
 
         Some(self.new_error(&format!("Identifier not found: {}", name.value)))
     }
 
     fn eval_return(&mut self, value: &Expression) -> Option<Object> {
-        let value = self.eval_expression(value);
+        let value = match value {
+            Expression::FunctionCall {
+                function,
+                arguments,
+                ..
+            } if self.tail_calls => self.eval_tail_call(function, arguments)?,
+            value => self.eval_expression(value)?,
+        };
 
-        if let Some(value) = value {
-            return Some(Object::Return(Box::new(value)));
+        match value {
+            Object::Error(_) | Object::Return(_) => Some(value),
+            value => Some(Object::Return(Box::new(value))),
         }
-
-        None
     }
 
     fn eval_expression(&mut self, value: &Expression) -> Option<Object> {
-        match value {
+        if let Err(error) = self.budget.step() {
+            return Some(self.raise(error));
+        }
+        if let Err(error) = self.nest() {
+            return Some(self.raise(error));
+        }
+        let result = match value {
             Expression::Literal(lit) => self.eval_literal(lit),
             Expression::Prefix {
                 token: _,
@@ -124,18 +545,20 @@ impl Evaluator {
             } => self.eval_if_expression(token, condition, consequence, alternative),
             Expression::Identifier(iden) => self.eval_identifier(iden),
             Expression::FunctionCall {
-                token: _,
+                token,
                 function,
                 arguments,
-            } => self.eval_function_call(function, arguments),
+            } => self.eval_function_call(token, function, arguments),
             Expression::FunctionLiteral {
                 token: _,
                 parameters,
                 body,
+                locals,
             } => Some(Object::Function {
                 parameters: parameters.clone(),
-                body: *body.clone(),
+                body: Rc::new(*body.clone()),
                 env: Rc::clone(&self.env),
+                locals: *locals,
             }),
             Expression::IndexExpression {
                 token: _,
@@ -147,7 +570,29 @@ impl Evaluator {
                 left,
                 right,
             } => self.eval_dot_notation(left, right),
+        };
+        self.nesting -= 1;
+        result
+    }
+
+    // Counts one more level of nesting on the native stack, expressions and
+    // calls made by natives may not go over `NESTING_PER_CALL` levels for
+    // each call the depth limit allows
+    fn nest(&mut self) -> Result<(), RuntimeError> {
+        let max_nesting = self
+            .max_depth
+            .saturating_add(1)
+            .saturating_mul(NESTING_PER_CALL);
+        if self.nesting >= max_nesting {
+            return Err(RuntimeError::StackOverflow {
+                limit: self.max_depth,
+                calls: self.depth,
+                trace: Vec::new(),
+            });
         }
+
+        self.nesting += 1;
+        Ok(())
     }
 
     fn eval_dot_expr(
@@ -170,164 +615,184 @@ impl Evaluator {
     }
 
     fn eval_dot_notation(&mut self, left: &Expression, right: &Expression) -> Option<Object> {
-        let left = self.eval_expression(left);
-
-        if let Some(left) = left {
-            match left {
-                Object::Hash(hash) => {
-                    for (k, v) in hash {
-                        if let Object::String(k) = k {
-                            if k == right.to_string() {
-                                return Some(v);
-                            }
-                        }
-                    }
-
-                    return Some(Object::Null);
-                }
-                Object::String(string) => {
-                    let right = self.eval_dot_expr(right);
-
-                    if right.is_none() {
-                        return Some(self.new_error("Use dot notation on strings"));
-                    }
+        let left = self.eval_expression(left)?;
 
-                    let (name, _func, _args) = right.unwrap();
+        self.eval_dot_property(left, right)
+    }
 
-                    // Is property
-                    return builtin::dot_str_builtins(&string, DotBuiltinKind::Property(name));
-                }
-                _ => return Some(self.new_error("Use dot notation properly")),
+    fn eval_dot_property(&mut self, left: Object, right: &Expression) -> Option<Object> {
+        match left {
+            Object::Error(_) => Some(left),
+            Object::String(_) if self.eval_dot_expr(right).is_none() => {
+                Some(self.new_error("Use dot notation on strings"))
+            }
+            left => {
+                let result = property(left, &right.to_string());
+                let result = result.and_then(|obj| self.budget.check(obj));
+                Some(self.result(result))
             }
         }
-
-        None
     }
 
-    fn eval_index_expression(&mut self, left: &Expression, index: &Expression) -> Option<Object> {
-        let left = self.eval_expression(left);
-        let index = self.eval_expression(index);
-
-        if let Some(left) = left {
-            if let Some(index) = index {
-                match (left, index) {
-                    (Object::Array(arr), Object::Integer(int)) => {
-                        if int <= -1 {
-                            if let Some(item) =
-                                arr.iter().nth_back((int.unsigned_abs() - 1) as usize)
-                            {
-                                return Some(item.clone());
-                            }
-                        }
+    fn eval_dot_assign(
+        &mut self,
+        left: &Expression,
+        right: &Expression,
+        value: &Expression,
+    ) -> Option<Object> {
+        let left = self.eval_expression(left)?;
+        let value = self.eval_expression(value)?;
 
-                        if int >= arr.len() as i64 {
-                            return Some(Object::Null);
-                        }
+        match (left, value) {
+            (Object::Error(msg), _) | (_, Object::Error(msg)) => Some(Object::Error(msg)),
+            (left, value) => match set_property(left, &right.to_string(), value) {
+                Ok(()) => Some(Object::Empty),
+                Err(error) => Some(self.raise(error)),
+            },
+        }
+    }
 
-                        return Some(arr[int as usize].clone());
-                    }
-                    (Object::String(str), Object::Integer(int)) => {
-                        // Is negative, go backwards. i.e -1
-                        if int <= -1 {
-                            if let Some(char) =
-                                str.chars().nth_back((int.unsigned_abs() - 1) as usize)
-                            {
-                                return Some(Object::String(char.to_string()));
-                            }
-                        }
+    fn eval_method_call(
+        &mut self,
+        native: Rc<dyn NativeObject>,
+        name: &str,
+        arguments: &[Expression],
+    ) -> Option<Object> {
+        let arguments = self.eval_expressions(arguments)?;
+        if let Some(error) = arguments.iter().find(|arg| matches!(arg, Object::Error(_))) {
+            return Some(error.clone());
+        }
 
-                        if int >= str.len() as i64 {
-                            return Some(Object::Null);
-                        }
+        let args = Args::new(format!("{}.{}", native.type_name(), name), arguments);
+        let result = native.call_method(&mut Ctx { caller: self }, name, args);
+        let result = result.and_then(|obj| self.budget.check(obj));
+        Some(self.result(result))
+    }
 
-                        if let Some(char) = str.chars().nth(int as usize) {
-                            return Some(Object::String(char.to_string()));
-                        }
-                    }
-                    (Object::Hash(hash), Object::String(key)) => {
-                        for (k, v) in hash {
-                            if let Object::String(k) = k {
-                                if k == key {
-                                    return Some(v);
-                                }
-                            }
-                        }
+    fn eval_index_expression(&mut self, left: &Expression, index: &Expression) -> Option<Object> {
+        let left = self.eval_expression(left)?;
+        let index = self.eval_expression(index)?;
 
-                        return Some(Object::Null);
-                    }
-                    _ => return Some(self.new_error("Use index expression on arrays or strings")),
-                }
+        match (left, index) {
+            (Object::Error(msg), _) | (_, Object::Error(msg)) => Some(Object::Error(msg)),
+            (left, index) => {
+                let result = self::index(left, index);
+                Some(self.result(result))
             }
         }
-
-        None
     }
 
     fn eval_function_call(
         &mut self,
-        function: &Expression,
+        token: &Token,
+        callee: &Expression,
         arguments: &[Expression],
     ) -> Option<Object> {
-        let function = self.eval_expression(function)?;
-
-        let arguments = self.eval_expressions(arguments)?;
-
-        match function {
-            Object::Function {
-                parameters,
-                body,
-                env,
-            } => {
-                if arguments.len() != parameters.len() {
-                    Some(self.new_error(&format!(
-                        "Wrong number of arguments. Expected {}, got {}",
-                        parameters.len(),
-                        arguments.len()
-                    )))
+        match self.eval_call_parts(callee, arguments) {
+            Ok((function @ Object::Function { .. }, arguments)) => {
+                let result = if self.depth < self.max_depth {
+                    self.depth += 1;
+                    let result = self.apply_function(function, arguments);
+                    self.depth -= 1;
+                    result
                 } else {
-                    let old_env = Rc::clone(&self.env);
-                    let mut new_env = Env::extend(Rc::clone(&env));
-                    let zipped = parameters.iter().zip(arguments);
-                    for (_, (Identifier { token: _, value }, o)) in zipped.enumerate() {
-                        new_env.set(value, o);
-                    }
+                    self.raise(RuntimeError::StackOverflow {
+                        limit: self.max_depth,
+                        calls: self.depth + 1,
+                        trace: Vec::new(),
+                    })
+                };
+
+                Some(self.trace_call(result, token, callee))
+            }
+            Ok((function, arguments)) => Some(self.apply_function(function, arguments)),
+            Err(result) => result,
+        }
+    }
 
-                    self.env = Rc::new(RefCell::new(new_env));
-                    let object = self.eval_block_statement(body);
-                    self.env = old_env;
+    // Stack overflow traces are filled in while the error leaves the calls
+    fn trace_call(&mut self, result: Object, token: &Token, callee: &Expression) -> Object {
+        let error = match (&result, &self.error) {
+            (Object::Error(msg), Some(error)) if error.to_string() == *msg => &mut self.error,
+            _ => return result,
+        };
+
+        match error {
+            Some(RuntimeError::StackOverflow {
+                limit,
+                calls,
+                trace,
+            }) if trace.len() < TRACE_LEN => {
+                trace.push(format!("{} at {}", callee, token.position));
+                let error = RuntimeError::StackOverflow {
+                    limit: *limit,
+                    calls: *calls,
+                    trace: std::mem::take(trace),
+                };
+                self.raise(error)
+            }
+            _ => result,
+        }
+    }
 
-                    object
+    // The function and arguments to apply for a call, or its result if
+    // there is nothing left to apply
+    fn eval_call_parts(
+        &mut self,
+        function: &Expression,
+        arguments: &[Expression],
+    ) -> Result<(Object, Vec<Object>), Option<Object>> {
+        // Methods of native objects are dispatched by name instead of
+        // looking up a property holding a function
+        let function = match function {
+            Expression::DotNotation { left, right, .. } => match self.eval_expression(left) {
+                Some(Object::Native(native)) => {
+                    return Err(self.eval_method_call(native, &right.to_string(), arguments))
                 }
-            }
-            Object::BuiltinFunction(func) => Some(func(arguments)),
-            _ => Some(self.new_error(&format!("Not a function: {}", function))),
+                Some(left) => self.eval_dot_property(left, right),
+                None => None,
+            },
+            _ => self.eval_expression(function),
+        };
+        let function = match function {
+            Some(Object::Error(msg)) => return Err(Some(Object::Error(msg))),
+            Some(function) => function,
+            None => return Err(None),
+        };
+
+        let arguments = self.eval_expressions(arguments).ok_or(None)?;
+        if let Some(error) = arguments.iter().find(|arg| matches!(arg, Object::Error(_))) {
+            return Err(Some(error.clone()));
         }
+
+        Ok((function, arguments))
     }
 
     fn eval_expressions(&mut self, expressions: &[Expression]) -> Option<Vec<Object>> {
         Some(
             expressions
                 .iter()
-                .map(|expr| self.eval_expression(&expr.clone()).unwrap_or(Object::Null))
+                .map(|expr| self.eval_expression(expr).unwrap_or(Object::Null))
                 .collect::<Vec<_>>(),
         )
     }
 
     fn eval_identifier(&mut self, iden: &Identifier) -> Option<Object> {
-        let value = self.env.borrow_mut().get(&iden.value);
+        let value = match iden.slot {
+            Some(Slot::Local { depth, index }) => self.env.borrow().get_slot(depth, index),
+            Some(Slot::Global) => self.env.borrow().get_global(&iden.value),
+            None => self.env.borrow().get(&iden.value),
+        };
 
         if let Some(value) = value {
             return Some(value);
         }
 
-        if builtin::builtins().contains_key(&iden.value) {
-            return Some(builtin::builtins()[&iden.value].clone());
+        if let Some(native) = self.natives.get(&iden.value) {
+            return Some(native.clone());
         }
 
-        Some(self.new_error(&format!(
-            "Identifier not found (eval_identifier): {}",
-            iden.value
-        )))
+        Some(self.new_error(&format!("Identifier not found: {}", iden.value)))
     }
 
     fn eval_if_expression(
@@ -340,6 +805,7 @@ impl Evaluator {
         let condition = self.eval_expression(condition)?;
 
         match condition {
+            Object::Error(_) => Some(condition),
             Object::Boolean(bool) => {
                 if bool {
                     self.eval_block_statement(consequence.to_vec())
@@ -359,91 +825,36 @@ impl Evaluator {
         operator: &str,
         right: &Expression,
     ) -> Option<Object> {
+        if let (
+            Expression::DotNotation {
+                left, right: name, ..
+            },
+            "=",
+        ) = (left, operator)
+        {
+            return self.eval_dot_assign(left, name, right);
+        }
+
         let left = self.eval_expression(left)?;
         let right = self.eval_expression(right)?;
 
         match (right, left) {
-            (Object::Integer(right_value), Object::Integer(left_value)) => {
-                self.eval_integer_infix_expression(&left_value, operator, &right_value)
-            }
-            (Object::Boolean(right_value), Object::Boolean(left_value)) => {
-                self.eval_boolean_infix_expression(&left_value, operator, &right_value)
+            (Object::Error(msg), _) | (_, Object::Error(msg)) => Some(Object::Error(msg)),
+            (right, left) => {
+                let result = infix(&self.budget, operator, left, right);
+                Some(self.result(result))
             }
-            (Object::String(right_value), Object::String(left_value)) => {
-                self.eval_string_infix_expression(&left_value, operator, &right_value)
-            }
-            _ => Some(self.new_error("Use infix operators on integers")),
-        }
-    }
-
-    fn eval_string_infix_expression(
-        &mut self,
-        left: &str,
-        operator: &str,
-        right: &str,
-    ) -> Option<Object> {
-        match operator {
-            "+" => Some(Object::String(format!("{}{}", left, right))),
-            "==" => Some(Object::Boolean(left == right)),
-            "!=" => Some(Object::Boolean(left != right)),
-            _ => Some(self.new_error(&format!("Invalid operator: {}", operator))),
-        }
-    }
-
-    fn eval_boolean_infix_expression(
-        &mut self,
-        left: &bool,
-        operator: &str,
-        right: &bool,
-    ) -> Option<Object> {
-        match operator {
-            "==" => Some(Object::Boolean(left == right)),
-            "!=" => Some(Object::Boolean(left != right)),
-            _ => Some(self.new_error(&format!("Invalid operator: {}", operator))),
-        }
-    }
-
-    fn eval_integer_infix_expression(
-        &mut self,
-        left: &i64,
-        operator: &str,
-        right: &i64,
-    ) -> Option<Object> {
-        match operator {
-            "+" => Some(Object::Integer(left + right)),
-            "-" => Some(Object::Integer(left - right)),
-            "*" => Some(Object::Integer(left * right)),
-            "/" => Some(Object::Integer(left / right)),
-            "<" => Some(Object::Boolean(left < right)),
-            ">" => Some(Object::Boolean(left > right)),
-            "==" => Some(Object::Boolean(left == right)),
-            "!=" => Some(Object::Boolean(left != right)),
-            _ => Some(self.new_error(&format!("Invalid operator: {}", operator))),
         }
     }
 
     fn eval_prefix_expression(&mut self, operator: &str, right: &Expression) -> Option<Object> {
         let right = self.eval_expression(right)?;
-
-        match operator {
-            "!" => self.eval_bang_prefix(right),
-            "-" => self.eval_minus_prefix(right),
-            _ => Some(self.new_error("Invalid prefix operator")),
-        }
-    }
-
-    fn eval_bang_prefix(&mut self, right: Object) -> Option<Object> {
-        match right {
-            Object::Boolean(bool) => Some(Object::Boolean(!bool)),
-            _ => Some(self.new_error("Use ! prefix operator on booleans!")),
+        if let Object::Error(_) = right {
+            return Some(right);
         }
-    }
 
-    fn eval_minus_prefix(&mut self, right: Object) -> Option<Object> {
-        match right {
-            Object::Integer(int) => Some(Object::Integer(-int)),
-            _ => Some(self.new_error("Use - prefix operator on integers or floats")),
-        }
+        let result = prefix(operator, right);
+        Some(self.result(result))
     }
 
     fn eval_literal(&mut self, lit: &Literal) -> Option<Object> {
@@ -451,15 +862,32 @@ impl Evaluator {
             Literal::Integer(int) => Some(Object::Integer(*int)),
             Literal::Boolean(bool) => Some(Object::Boolean(*bool)),
             Literal::String(string) => Some(Object::String(string.clone())),
+            Literal::Interpolated(parts) => {
+                let mut result = String::new();
+
+                for part in parts {
+                    match self.eval_expression(part)? {
+                        Object::Error(msg) => return Some(Object::Error(msg)),
+                        obj => result.push_str(&obj.to_string()),
+                    }
+                }
+
+                let result = self.budget.check(Object::String(result));
+                Some(self.result(result))
+            }
             Literal::Array(array) => {
                 let mut result = Vec::new();
 
                 for expr in array {
                     let evaluated = self.eval_expression(expr)?;
+                    if let Object::Error(_) = evaluated {
+                        return Some(evaluated);
+                    }
                     result.push(evaluated);
                 }
 
-                Some(Object::Array(result))
+                let result = self.budget.check(Object::Array(result));
+                Some(self.result(result))
             }
             Literal::Hash(pairs) => self.eval_hash_literal(pairs.to_vec()),
         }
@@ -473,15 +901,249 @@ impl Evaluator {
 
             match key {
                 Object::String(_) => {}
+                Object::Error(_) => return Some(key),
                 _ => return Some(self.new_error("Hash keys must be strings")),
             };
 
             let value = self.eval_expression(&v)?;
+            if let Object::Error(_) = value {
+                return Some(value);
+            }
 
             hash.push((key, value));
         }
 
-        Some(Object::Hash(hash))
+        let result = self.budget.check(Object::Hash(hash));
+        Some(self.result(result))
+    }
+}
+
+// Operations on already evaluated values, shared with the VM
+
+pub(crate) fn member_error(err: MemberError, path: &str, name: &str) -> RuntimeError {
+    match err {
+        MemberError::Missing => {
+            RuntimeError::new(&format!("Module \"{}\" has no member `{}`", path, name))
+        }
+        MemberError::Private => {
+            RuntimeError::new(&format!("`{}` is not exported by \"{}\"", name, path))
+        }
+    }
+}
+
+pub(crate) fn property(left: Object, name: &str) -> Result<Object, RuntimeError> {
+    match left {
+        Object::Hash(hash) => {
+            for (k, v) in hash {
+                if let Object::String(k) = k {
+                    if k == name {
+                        return Ok(v);
+                    }
+                }
+            }
+
+            Ok(Object::Null)
+        }
+        Object::String(string) => {
+            match builtin::dot_str_builtins(&string, DotBuiltinKind::Property(name.to_string())) {
+                Some(Object::Error(msg)) => Err(RuntimeError::Message(msg)),
+                Some(obj) => Ok(obj),
+                None => Ok(Object::Null),
+            }
+        }
+        Object::Module { path, env } => {
+            let member = env.borrow().get_public(name);
+            member.map_err(|err| member_error(err, &path, name))
+        }
+        Object::Native(native) => match native.get(name) {
+            Some(value) => Ok(value),
+            None => Err(RuntimeError::new(&format!(
+                "No property `{}` on {}",
+                name,
+                native.type_name()
+            ))),
+        },
+        _ => Err(RuntimeError::new("Use dot notation properly")),
+    }
+}
+
+pub(crate) fn set_property(left: Object, name: &str, value: Object) -> Result<(), RuntimeError> {
+    match left {
+        Object::Native(native) => native.set(name, value),
+        Object::Module { path, env } => {
+            let member = env.borrow().get_public(name);
+            if let Err(err) = member {
+                return Err(member_error(err, &path, name));
+            }
+
+            env.borrow_mut().set(name, value);
+            Ok(())
+        }
+        left => Err(RuntimeError::new(&format!(
+            "Cannot set property `{}` on {}",
+            name,
+            left.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn index(left: Object, index: Object) -> Result<Object, RuntimeError> {
+    match (left, index) {
+        // Negative indices count from the end, out of range is null either way
+        (Object::Array(arr), Object::Integer(int)) => {
+            let index = match int < 0 {
+                true => usize::try_from(int.unsigned_abs())
+                    .ok()
+                    .and_then(|back| arr.len().checked_sub(back)),
+                false => usize::try_from(int).ok(),
+            };
+
+            match index.and_then(|index| arr.get(index)) {
+                Some(item) => Ok(item.clone()),
+                None => Ok(Object::Null),
+            }
+        }
+        // Strings are indexed by code point, negative indices count from the end
+        (Object::String(str), Object::Integer(int)) => match builtin::char_at(&str, int) {
+            Some(char) => Ok(Object::String(char.to_string())),
+            None => Ok(Object::Null),
+        },
+        (Object::Hash(hash), Object::String(key)) => {
+            for (k, v) in hash {
+                if let Object::String(k) = k {
+                    if k == key {
+                        return Ok(v);
+                    }
+                }
+            }
+
+            Ok(Object::Null)
+        }
+        _ => Err(RuntimeError::new(
+            "Use index expression on arrays or strings",
+        )),
+    }
+}
+
+/// Applies a binary operator, a joined string is reserved in `budget` first
+pub(crate) fn infix(
+    budget: &Budget,
+    operator: &str,
+    left: Object,
+    right: Object,
+) -> Result<Object, RuntimeError> {
+    match (right, left) {
+        (Object::Integer(right), Object::Integer(left)) => integer_infix(left, operator, right),
+        (Object::Boolean(right), Object::Boolean(left)) => match operator {
+            "==" => Ok(Object::Boolean(left == right)),
+            "!=" => Ok(Object::Boolean(left != right)),
+            _ => Err(invalid_operator(operator)),
+        },
+        (Object::String(right), Object::String(left)) => match operator {
+            "+" => {
+                budget.reserve(std::mem::size_of::<Object>() + left.len() + right.len())?;
+                Ok(Object::String(left + &right))
+            }
+            "==" => Ok(Object::Boolean(left == right)),
+            "!=" => Ok(Object::Boolean(left != right)),
+            _ => Err(invalid_operator(operator)),
+        },
+        (Object::Native(right), Object::Native(left)) => match operator {
+            "==" => Ok(Object::Boolean(left == right)),
+            "!=" => Ok(Object::Boolean(left != right)),
+            _ => Err(invalid_operator(operator)),
+        },
+        _ => Err(RuntimeError::new("Use infix operators on integers")),
+    }
+}
+
+fn integer_infix(left: i64, operator: &str, right: i64) -> Result<Object, RuntimeError> {
+    let checked = |result: Option<i64>| match result {
+        Some(int) => Ok(Object::Integer(int)),
+        None if operator == "/" && right == 0 => Err(RuntimeError::new("Division by zero")),
+        None => Err(RuntimeError::new(&format!(
+            "Integer overflow: {} {} {}",
+            left, operator, right
+        ))),
+    };
+
+    match operator {
+        "+" => checked(left.checked_add(right)),
+        "-" => checked(left.checked_sub(right)),
+        "*" => checked(left.checked_mul(right)),
+        "/" => checked(left.checked_div(right)),
+        "<" => Ok(Object::Boolean(left < right)),
+        ">" => Ok(Object::Boolean(left > right)),
+        "==" => Ok(Object::Boolean(left == right)),
+        "!=" => Ok(Object::Boolean(left != right)),
+        "&" => Ok(Object::Integer(left & right)),
+        "|" => Ok(Object::Integer(left | right)),
+        "^" => Ok(Object::Integer(left ^ right)),
+        "<<" | ">>" => {
+            if !(0..64).contains(&right) {
+                return Err(RuntimeError::new(&format!(
+                    "Shift amount out of range: {}",
+                    right
+                )));
+            }
+
+            match operator {
+                "<<" => Ok(Object::Integer(left << right)),
+                _ => Ok(Object::Integer(left >> right)),
+            }
+        }
+        _ => Err(invalid_operator(operator)),
+    }
+}
+
+fn invalid_operator(operator: &str) -> RuntimeError {
+    RuntimeError::new(&format!("Invalid operator: {}", operator))
+}
+
+pub(crate) fn prefix(operator: &str, right: Object) -> Result<Object, RuntimeError> {
+    match (operator, right) {
+        ("!", Object::Boolean(bool)) => Ok(Object::Boolean(!bool)),
+        ("!", _) => Err(RuntimeError::new("Use ! prefix operator on booleans!")),
+        ("-", Object::Integer(int)) => match int.checked_neg() {
+            Some(int) => Ok(Object::Integer(int)),
+            None => Err(RuntimeError::new(&format!("Integer overflow: -({})", int))),
+        },
+        ("-", _) => Err(RuntimeError::new(
+            "Use - prefix operator on integers or floats",
+        )),
+        ("~", Object::Integer(int)) => Ok(Object::Integer(!int)),
+        ("~", _) => Err(RuntimeError::new("Use ~ prefix operator on integers")),
+        _ => Err(RuntimeError::new("Invalid prefix operator")),
+    }
+}
+
+impl Caller for Evaluator {
+    fn call(&mut self, function: &Object, args: Vec<Object>) -> Result<Object, RuntimeError> {
+        self.nest()?;
+        let result = self.apply_function(function.clone(), args);
+        self.nesting -= 1;
+        match result {
+            Object::Error(msg) => Err(self.take_error(msg)),
+            obj => Ok(obj),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Object> {
+        Evaluator::lookup(self, name)
+    }
+
+    fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -492,7 +1154,43 @@ mod test {
     use crate::object::Object;
     use crate::parser::Parser;
 
-    use super::Evaluator;
+    use crate::vm::Vm;
+
+    /// Runs every program on the evaluator and on the VM, which must agree
+    struct Engines {
+        evaluator: super::Evaluator,
+        vm: Vm,
+    }
+
+    impl Engines {
+        fn new() -> Self {
+            Self {
+                evaluator: super::Evaluator::new(),
+                vm: Vm::new(),
+            }
+        }
+
+        fn eval(&mut self, program: &Program) -> Option<Object> {
+            let expected = self.evaluator.eval(program);
+            let got = self.vm.eval(program);
+
+            match (&expected, &got) {
+                (Some(Object::Error(msg)), Err(err)) => assert_eq!(*msg, err.to_string()),
+                (Some(obj), Ok(got)) => {
+                    assert_eq!(obj.type_name(), got.type_name());
+                    assert_eq!(obj.to_string(), got.to_string());
+                }
+                (None, Ok(Object::Null)) => {}
+                _ => panic!("Evaluator gave {:?}, VM gave {:?}", expected, got),
+            }
+            expected
+        }
+
+        fn set_max_depth(&mut self, depth: usize) {
+            self.evaluator.set_max_depth(depth);
+            self.vm.set_max_depth(depth);
+        }
+    }
 
     #[test]
     fn test_dot_notation() {
@@ -527,7 +1225,7 @@ mod test {
             // if program exists
             if let Some(program) = program {
                 // create new evaluator
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 // evaluate program
                 if let Some(result) = evaluator.eval(&program) {
                     // assert that result is equal to expected
@@ -587,7 +1285,7 @@ mod test {
             // if program exists
             if let Some(program) = program {
                 // create new evaluator
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 // evaluate program
                 if let Some(result) = evaluator.eval(&program) {
                     // assert that result is equal to expected
@@ -622,7 +1320,7 @@ mod test {
             let mut parser = Parser::new(tokens);
             let program: Option<Program> = parser.parse_program();
 
-            let mut evaluator = Evaluator::new();
+            let mut evaluator = Engines::new();
 
             if let Some(program) = program {
                 if let Some(result) = evaluator.eval(&program) {
@@ -662,6 +1360,12 @@ mod test {
             ),
             ("[1, 2, 3][3]", Object::Null),
             ("[1, 2, 3][-1]", Object::Integer(3)),
+            ("[1, 2, 3][-3]", Object::Integer(1)),
+            ("[1, 2, 3][-4]", Object::Null),
+            ("[1, 2, 3][-5]", Object::Null),
+            ("[][-1]", Object::Null),
+            ("[1][-9223372036854775807 - 1]", Object::Null),
+            ("[1][9223372036854775807]", Object::Null),
         ];
 
         for (input, expected) in tests {
@@ -678,7 +1382,7 @@ mod test {
             // if program exists
             if let Some(program) = program {
                 // create new evaluator
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 // evaluate program
                 if let Some(result) = evaluator.eval(&program) {
                     // assert that result is equal to expected
@@ -710,7 +1414,7 @@ mod test {
             let program: Option<Program> = parser.parse_program();
 
             if let Some(program) = program {
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
 
                 if let Some(result) = evaluator.eval(&program) {
                     match result {
@@ -756,7 +1460,7 @@ mod test {
             // if program exists
             if let Some(program) = program {
                 // create new evaluator
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 // evaluate program
                 if let Some(result) = evaluator.eval(&program) {
                     // assert that result is equal to expected
@@ -766,6 +1470,73 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_unicode_strings() {
+        let tests = vec![
+            ("len(\"héllo\")", Object::Integer(5)),
+            ("len(\"日本語\")", Object::Integer(3)),
+            ("\"日本語\".length", Object::Integer(3)),
+            ("\"日本語\"[1]", Object::String("本".to_string())),
+            ("\"日本語\"[-1]", Object::String("語".to_string())),
+            ("\"日本語\"[3]", Object::Null),
+            ("\"日本語\"[-4]", Object::Null),
+            (
+                "\"né\".chars",
+                Object::Array(vec![
+                    Object::String("n".to_string()),
+                    Object::String("é".to_string()),
+                ]),
+            ),
+            (
+                "\"né\".bytes",
+                Object::Array(vec![
+                    Object::Integer(110),
+                    Object::Integer(195),
+                    Object::Integer(169),
+                ]),
+            ),
+            (
+                "\"e\u{301}a\".graphemes",
+                Object::Array(vec![
+                    Object::String("e\u{301}".to_string()),
+                    Object::String("a".to_string()),
+                ]),
+            ),
+            ("len(\"e\u{301}\")", Object::Integer(2)),
+            (
+                "\"🇯🇵🇫🇷\".graphemes",
+                Object::Array(vec![
+                    Object::String("🇯🇵".to_string()),
+                    Object::String("🇫🇷".to_string()),
+                ]),
+            ),
+            (
+                "\"👩\u{200D}💻!\".graphemes",
+                Object::Array(vec![
+                    Object::String("👩\u{200D}💻".to_string()),
+                    Object::String("!".to_string()),
+                ]),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            let mut parser = Parser::new(tokens);
+            let program: Option<Program> = parser.parse_program();
+
+            if let Some(program) = program {
+                let mut evaluator = Engines::new();
+
+                match evaluator.eval(&program) {
+                    Some(result) => assert_eq!(result, expected, "input: {}", input),
+                    None => panic!("No result for {}", input),
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_string_concatenation() {
         let tests = vec![
@@ -793,7 +1564,7 @@ mod test {
             // if program exists
             if let Some(program) = program {
                 // create new evaluator
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 // evaluate program
                 if let Some(result) = evaluator.eval(&program) {
                     // assert that result is equal to expected
@@ -803,6 +1574,131 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_string_interpolation() {
+        let tests = vec![
+            (
+                r#"let name = "Joe"; let age = 41; "Hello ${name}, you are ${age + 1}""#,
+                "Hello Joe, you are 42",
+            ),
+            (r#""${"a" + "b"}!""#, "ab!"),
+            (r#""${[1, 2]} and ${true}""#, "[1, 2] and true"),
+            (r#""${{"a": 1}["a"]}""#, "1"),
+            (r#""cost: \${price}""#, "cost: ${price}"),
+            (r#""$5 {not code}""#, "$5 {not code}"),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            let mut parser = Parser::new(tokens);
+            let program: Option<Program> = parser.parse_program();
+
+            if let Some(program) = program {
+                let mut evaluator = Engines::new();
+
+                match evaluator.eval(&program) {
+                    Some(result) => assert_eq!(result, Object::String(expected.to_string())),
+                    None => panic!("No result for {}", input),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_builtin_format() {
+        let tests = vec![
+            (
+                r#"let name = "pen"; format("{:>8}|{:<4}|", name, 42)"#,
+                Object::String("     pen|42  |".to_string()),
+            ),
+            (
+                r#"format("{:?}", ["a", 1])"#,
+                Object::String("[\"a\", 1]".to_string()),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            let mut parser = Parser::new(tokens);
+            let program: Option<Program> = parser.parse_program();
+
+            if let Some(program) = program {
+                let mut evaluator = Engines::new();
+
+                match evaluator.eval(&program) {
+                    Some(result) => assert_eq!(result, expected),
+                    None => panic!("No result for {}", input),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_builtin_json() {
+        let tests = vec![
+            (
+                r#"let config = json_parse("{\"name\": \"pen\", \"sizes\": [1, 2]}"); config.sizes"#,
+                Object::Array(vec![Object::Integer(1), Object::Integer(2)]),
+            ),
+            (
+                r#"json_stringify({"a": [1, true], "b": "x"})"#,
+                Object::String(r#"{"a":[1,true],"b":"x"}"#.to_string()),
+            ),
+            (
+                r#"json_stringify([1], 2)"#,
+                Object::String("[\n  1\n]".to_string()),
+            ),
+            (
+                r#"json_parse("[1,\n  2 3]")"#,
+                Object::Error("Invalid JSON at 2:5: Unexpected `3`".to_string()),
+            ),
+            (
+                r#"json_stringify(len)"#,
+                Object::Error("Cannot convert BuiltinFunction to JSON".to_string()),
+            ),
+            (
+                r#"json_stringify([1], -1)"#,
+                Object::Error(
+                    "Argument 2 to `json_stringify` must be from 0 to 10, got -1".to_string(),
+                ),
+            ),
+            (
+                r#"json_stringify([1], 9223372036854775807)"#,
+                Object::Error(
+                    "Argument 2 to `json_stringify` must be from 0 to 10, got 9223372036854775807"
+                        .to_string(),
+                ),
+            ),
+            (
+                r#"json_stringify([[1]], 10)"#,
+                Object::String(format!("[\n{0}[\n{0}{0}1\n{0}]\n]", " ".repeat(10))),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            let mut parser = Parser::new(tokens);
+            let program: Option<Program> = parser.parse_program();
+
+            if let Some(program) = program {
+                let mut evaluator = Engines::new();
+
+                match evaluator.eval(&program) {
+                    Some(result) => assert_eq!(result, expected),
+                    None => panic!("No result for {}", input),
+                }
+            } else {
+                panic!("Could not parse {}", input);
+            }
+        }
+    }
+
     #[test]
     fn test_string_literal() {
         let test = "\"Hello World!\"";
@@ -813,7 +1709,7 @@ mod test {
         let mut parser = Parser::new(tokens);
         let program: Option<Program> = parser.parse_program();
 
-        let mut evaluator = Evaluator::new();
+        let mut evaluator = Engines::new();
 
         if let Some(program) = program {
             if let Some(result) = evaluator.eval(&program) {
@@ -862,7 +1758,7 @@ mod test {
             // if program exists
             if let Some(program) = program {
                 // create new evaluator
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 // evaluate program
                 if let Some(result) = evaluator.eval(&program) {
                     // assert that result is equal to expected
@@ -898,7 +1794,7 @@ mod test {
             // if program exists
             if let Some(program) = program {
                 // create new evaluator
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 // evaluate program
                 if let Some(result) = evaluator.eval(&program) {
                     // assert that result is equal to expected
@@ -940,7 +1836,7 @@ mod test {
             // if program exists
             if let Some(program) = program {
                 // create new evaluator
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 // evaluate program
                 if let Some(result) = evaluator.eval(&program) {
                     // assert that result is equal to expected
@@ -954,6 +1850,67 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_tail_calls() {
+        let tests = vec![
+            (
+                "let count = fn(n, acc) { if (n == 0) { acc } else { count(n - 1, acc + 1) } };
+                count(1000000, 0)",
+                Object::Integer(1000000),
+            ),
+            (
+                "let even = fn(n) { if (n == 0) { return true; } return odd(n - 1); };
+                let odd = fn(n) { if (n == 0) { return false; } return even(n - 1); };
+                odd(100001)",
+                Object::Boolean(true),
+            ),
+            (
+                "let last = fn(arr) { if (len(arr) == 1) { first(arr) } else { last([arr[1]]) } };
+                last([1, 2])",
+                Object::Integer(2),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let mut parser = Parser::new(l.gen_tokens());
+            let program = parser.parse_program().unwrap();
+
+            let mut evaluator = Engines::new();
+            assert_eq!(evaluator.eval(&program), Some(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let sum = "let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } };";
+        let tests = vec![
+            (format!("{} sum(4)", sum), Object::Integer(10)),
+            (
+                format!("{} sum(5)", sum),
+                Object::Error(format!(
+                    "Stack overflow: more than 5 nested calls{}\n    in sum at 1:67",
+                    "\n    in sum at 1:51".repeat(5)
+                )),
+            ),
+            (
+                "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(100)"
+                    .to_string(),
+                Object::Integer(0),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let mut parser = Parser::new(l.gen_tokens());
+            let program = parser.parse_program().unwrap();
+
+            let mut evaluator = Engines::new();
+            evaluator.set_max_depth(5);
+            assert_eq!(evaluator.eval(&program), Some(expected), "{}", input);
+        }
+    }
+
     #[test]
     fn test_conditionals() {
         let tests = vec![
@@ -979,7 +1936,7 @@ mod test {
             // if program exists
             if let Some(program) = program {
                 // create new evaluator
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 // evaluate program
                 if let Some(result) = evaluator.eval(&program) {
                     // assert that result is equal to expected
@@ -1014,13 +1971,92 @@ mod test {
             let program: Option<Program> = parser.parse_program();
 
             if let Some(program) = program {
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
                 if let Some(result) = evaluator.eval(&program) {
                     assert_eq!(result, Object::Boolean(expected));
                 }
             }
         }
     }
+    #[test]
+    fn test_bitwise_operators() {
+        let tests = vec![
+            ("0xF0 | 0x0F", 255),
+            ("0b1100 & 0b1010", 8),
+            ("0b1100 ^ 0b1010", 6),
+            ("1 << 10", 1024),
+            ("-16 >> 2", -4),
+            ("~0", -1),
+            ("~5 & 0xFF", 250),
+            ("1_000 + 0o10", 1008),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            let mut parser = Parser::new(tokens);
+            let program: Option<Program> = parser.parse_program();
+
+            if let Some(program) = program {
+                let mut evaluator = Engines::new();
+
+                match evaluator.eval(&program) {
+                    Some(result) => assert_eq!(result, Object::Integer(expected), "{}", input),
+                    None => panic!("No result for {}", input),
+                }
+            } else {
+                panic!("Parse program returned None for {}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let min = "(-9223372036854775807 - 1)";
+        let tests = vec![
+            ("5 / 0".to_string(), "Division by zero".to_string()),
+            (
+                "9223372036854775807 + 1".to_string(),
+                "Integer overflow: 9223372036854775807 + 1".to_string(),
+            ),
+            (
+                format!("{} - 1", min),
+                "Integer overflow: -9223372036854775808 - 1".to_string(),
+            ),
+            (
+                "4611686018427387904 * 2".to_string(),
+                "Integer overflow: 4611686018427387904 * 2".to_string(),
+            ),
+            (
+                format!("{} / -1", min),
+                "Integer overflow: -9223372036854775808 / -1".to_string(),
+            ),
+            (
+                format!("-{}", min),
+                "Integer overflow: -(-9223372036854775808)".to_string(),
+            ),
+            (
+                format!("import std.math; math.abs({})", min),
+                "Integer overflow: -(-9223372036854775808)".to_string(),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let mut parser = Parser::new(l.gen_tokens());
+            let program = parser.parse_program().unwrap();
+
+            let mut evaluator = Engines::new();
+            assert_eq!(
+                evaluator.eval(&program),
+                Some(Object::Error(expected)),
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_prefix_bang_minus() {
         let tests = vec![
@@ -1038,7 +2074,7 @@ mod test {
             let program: Option<Program> = parser.parse_program();
 
             if let Some(program) = program {
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
 
                 if let Some(result) = evaluator.eval(&program) {
                     assert_eq!(result, Object::Boolean(expected));
@@ -1070,7 +2106,7 @@ mod test {
             let program: Option<Program> = parser.parse_program();
 
             if let Some(program) = program {
-                let mut evaluator = Evaluator::new();
+                let mut evaluator = Engines::new();
 
                 if let Some(result) = evaluator.eval(&program) {
                     assert_eq!(result, Object::Integer(expected));
@@ -1089,7 +2125,7 @@ mod test {
         let program: Option<Program> = parser.parse_program();
 
         if let Some(program) = program {
-            let mut evaluator = Evaluator::new();
+            let mut evaluator = Engines::new();
 
             if let Some(result) = evaluator.eval(&program) {
                 assert_eq!(result, Object::Boolean(false));
@@ -1107,7 +2143,7 @@ mod test {
         let program: Option<Program> = parser.parse_program();
 
         if let Some(program) = program {
-            let mut evaluator = Evaluator::new();
+            let mut evaluator = Engines::new();
 
             if let Some(result) = evaluator.eval(&program) {
                 assert_eq!(result, Object::Integer(5));
@@ -1125,7 +2161,7 @@ mod test {
         let program: Option<Program> = parser.parse_program();
 
         if let Some(program) = program {
-            let mut evaluator = Evaluator::new();
+            let mut evaluator = Engines::new();
 
             if let Some(result) = evaluator.eval(&program) {
                 assert_eq!(result, Object::Boolean(true));
@@ -0,0 +1,404 @@
+//! The format mini-language used by the `format` and `printf` builtins.
+//!
+//! It follows Rust's `format!` syntax: `{}` takes the next argument, `{0}`
+//! an argument by position and `{name}` a key of a trailing hash argument.
+//! After a `:` comes `[[fill]align][+][#][0][width][.precision][type]`
+//! where align is one of `<`, `^`, `>` and type is one of `?` (debug),
+//! `x`, `X`, `o` or `b`. `{{` and `}}` are literal braces.
+//!
+//! Width and precision count code points and are at most 65535, like in
+//! Rust. Precision truncates strings and is ignored for other values.
+//! Negative integers show their two's complement in hex, octal and binary,
+//! as Rust does for `i64`.
+
+use crate::object::Object;
+
+/// The largest width or precision, the padding it asks for is allocated
+const MAX_WIDTH: usize = u16::MAX as usize;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    sign: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+pub fn format(template: &str, args: &[Object]) -> Result<String, String> {
+    let chars: Vec<char> = template.chars().collect();
+    let mut out = String::new();
+    let mut next_arg = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '{' if chars.get(i + 1) == Some(&'{') => {
+                out.push('{');
+                i += 2;
+            }
+            '}' if chars.get(i + 1) == Some(&'}') => {
+                out.push('}');
+                i += 2;
+            }
+            '}' => return Err("Unmatched `}` in format string".to_string()),
+            '{' => {
+                let close = chars[i..]
+                    .iter()
+                    .position(|c| *c == '}')
+                    .ok_or("Unclosed `{` in format string")?;
+                let placeholder: String = chars[i + 1..i + close].iter().collect();
+                let (name, spec) = match placeholder.split_once(':') {
+                    Some((name, spec)) => (name, parse_spec(spec)?),
+                    None => (placeholder.as_str(), parse_spec("")?),
+                };
+
+                let arg = if name.is_empty() {
+                    next_arg += 1;
+                    positional(args, next_arg - 1)?
+                } else if let Ok(index) = name.parse::<usize>() {
+                    positional(args, index)?
+                } else {
+                    named(args, name)?
+                };
+
+                out.push_str(&apply(arg, &spec)?);
+                i += close + 1;
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Most bytes the padding of the template's fields can take, for checking
+/// size limits before formatting
+pub fn padding(template: &str) -> usize {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|field| field.split_once('}'))
+        .filter_map(|(placeholder, _)| placeholder.split_once(':'))
+        .filter_map(|(_, spec)| parse_spec(spec).ok())
+        .map(|spec| spec.width.saturating_mul(spec.fill.len_utf8()))
+        .fold(0, usize::saturating_add)
+}
+
+fn positional(args: &[Object], index: usize) -> Result<&Object, String> {
+    args.get(index).ok_or(format!(
+        "Format string references argument {}, but only {} given",
+        index,
+        args.len()
+    ))
+}
+
+fn named<'a>(args: &'a [Object], name: &str) -> Result<&'a Object, String> {
+    if let Some(Object::Hash(hash)) = args.last() {
+        for (k, v) in hash {
+            if let Object::String(k) = k {
+                if k == name {
+                    return Ok(v);
+                }
+            }
+        }
+    }
+
+    Err(format!("No named argument `{}` for format string", name))
+}
+
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let chars: Vec<char> = spec.chars().collect();
+    let mut result = Spec {
+        fill: ' ',
+        align: None,
+        sign: false,
+        alternate: false,
+        zero: false,
+        width: 0,
+        precision: None,
+        kind: None,
+    };
+    let mut i = 0;
+
+    let align = |c: Option<&char>| match c {
+        Some('<') => Some(Align::Left),
+        Some('^') => Some(Align::Center),
+        Some('>') => Some(Align::Right),
+        _ => None,
+    };
+
+    if let Some(a) = align(chars.get(1)) {
+        result.fill = chars[0];
+        result.align = Some(a);
+        i = 2;
+    } else if let Some(a) = align(chars.first()) {
+        result.align = Some(a);
+        i = 1;
+    }
+
+    if chars.get(i) == Some(&'+') {
+        result.sign = true;
+        i += 1;
+    }
+
+    if chars.get(i) == Some(&'#') {
+        result.alternate = true;
+        i += 1;
+    }
+
+    if chars.get(i) == Some(&'0') {
+        result.zero = true;
+        i += 1;
+    }
+
+    let start = i;
+    while i < chars.len() && chars[i].is_ascii_digit() {
+        i += 1;
+    }
+    if i > start {
+        result.width = number(&chars[start..i])?;
+    }
+
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        let start = i;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+        if i == start {
+            return Err(format!("Missing precision in format spec `{}`", spec));
+        }
+        result.precision = Some(number(&chars[start..i])?);
+    }
+
+    match &chars[i..] {
+        [] => {}
+        [kind @ ('?' | 'x' | 'X' | 'o' | 'b')] => result.kind = Some(*kind),
+        _ => return Err(format!("Invalid format spec `{}`", spec)),
+    }
+
+    Ok(result)
+}
+
+fn number(digits: &[char]) -> Result<usize, String> {
+    let digits: String = digits.iter().collect();
+    match digits.parse() {
+        Ok(number) if number <= MAX_WIDTH => Ok(number),
+        _ => Err(format!(
+            "Format width or precision must be at most {}, got {}",
+            MAX_WIDTH, digits
+        )),
+    }
+}
+
+fn apply(arg: &Object, spec: &Spec) -> Result<String, String> {
+    // Sign and radix prefix stay in front of zero padding
+    let (prefix, body) = match (arg, spec.kind) {
+        (Object::Integer(int), kind) => {
+            let digits = match kind {
+                Some('x') => format!("{:x}", int),
+                Some('X') => format!("{:X}", int),
+                Some('o') => format!("{:o}", int),
+                Some('b') => format!("{:b}", int),
+                _ => int.unsigned_abs().to_string(),
+            };
+
+            // Only decimal has a minus sign, the others show all the bits
+            let mut prefix = String::new();
+            if *int < 0 && kind.is_none() {
+                prefix.push('-');
+            } else if spec.sign {
+                prefix.push('+');
+            }
+            if spec.alternate {
+                match kind {
+                    Some('x') | Some('X') => prefix.push_str("0x"),
+                    Some('o') => prefix.push_str("0o"),
+                    Some('b') => prefix.push_str("0b"),
+                    _ => {}
+                }
+            }
+
+            (prefix, digits)
+        }
+        (_, Some('x' | 'X' | 'o' | 'b')) => {
+            return Err(format!(
+                "Format type `{}` needs an integer, got {}",
+                spec.kind.unwrap(),
+                arg
+            ))
+        }
+        (_, Some('?')) => (String::new(), debug(arg)),
+        (_, _) => {
+            let string = arg.to_string();
+            match spec.precision {
+                Some(precision) => (String::new(), string.chars().take(precision).collect()),
+                None => (String::new(), string),
+            }
+        }
+    };
+
+    let len = prefix.chars().count() + body.chars().count();
+    if len >= spec.width {
+        return Ok(prefix + &body);
+    }
+    let padding = spec.width - len;
+
+    if spec.zero && spec.align.is_none() {
+        return Ok(prefix + &"0".repeat(padding) + &body);
+    }
+
+    // Numbers align right by default, everything else left
+    let default = match arg {
+        Object::Integer(_) => Align::Right,
+        _ => Align::Left,
+    };
+    let (before, after) = match spec.align.unwrap_or(default) {
+        Align::Left => (0, padding),
+        Align::Center => (padding / 2, padding - padding / 2),
+        Align::Right => (padding, 0),
+    };
+
+    let fill = spec.fill.to_string();
+    Ok(fill.repeat(before) + &prefix + &body + &fill.repeat(after))
+}
+
+/// Renders `obj` with strings quoted and escaped, like Rust's `{:?}`
+pub fn debug(obj: &Object) -> String {
+    match obj {
+        Object::String(string) => format!("{:?}", string),
+        Object::Array(array) => {
+            let items: Vec<String> = array.iter().map(debug).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Object::Hash(hash) => {
+            let items: Vec<String> = hash
+                .iter()
+                .map(|(k, v)| format!("{}: {}", debug(k), debug(v)))
+                .collect();
+            format!("{{{}}}", items.join(", "))
+        }
+        _ => obj.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format, padding};
+    use crate::object::Object;
+
+    #[test]
+    fn test_format() {
+        let name = Object::String("pen".to_string());
+        let price = Object::Integer(42);
+        let tests = vec![
+            (
+                "{} costs {}",
+                vec![name.clone(), price.clone()],
+                "pen costs 42",
+            ),
+            (
+                "{1} {0} {1}",
+                vec![name.clone(), price.clone()],
+                "42 pen 42",
+            ),
+            (
+                "{:>6}|{:<5}|",
+                vec![name.clone(), price.clone()],
+                "   pen|42   |",
+            ),
+            (
+                "{:6}|{:5}|",
+                vec![name.clone(), price.clone()],
+                "pen   |   42|",
+            ),
+            ("{:*^7}", vec![name.clone()], "**pen**"),
+            ("{:.2}", vec![name.clone()], "pe"),
+            ("{:05}", vec![Object::Integer(-42)], "-0042"),
+            ("{:+}", vec![price.clone()], "+42"),
+            (
+                "{:x} {:#X} {:#b} {:o}",
+                vec![Object::Integer(255); 4],
+                "ff 0xFF 0b11111111 377",
+            ),
+            ("{:#010x}", vec![Object::Integer(255)], "0x000000ff"),
+            (
+                "{:x} {:#X} {:o}",
+                vec![Object::Integer(-1); 3],
+                "ffffffffffffffff 0xFFFFFFFFFFFFFFFF 1777777777777777777777",
+            ),
+            (
+                "{:?}",
+                vec![Object::String("a\"b".to_string())],
+                "\"a\\\"b\"",
+            ),
+            (
+                "{:?}",
+                vec![Object::Array(vec![name.clone(), price.clone()])],
+                "[\"pen\", 42]",
+            ),
+            ("{{{}}}", vec![price.clone()], "{42}"),
+            ("{:>4}", vec![Object::String("日本".to_string())], "  日本"),
+            (
+                "{name} is {age}",
+                vec![Object::Hash(vec![
+                    (Object::String("name".to_string()), name.clone()),
+                    (Object::String("age".to_string()), price.clone()),
+                ])],
+                "pen is 42",
+            ),
+        ];
+
+        for (template, args, expected) in tests {
+            assert_eq!(format(template, &args), Ok(expected.to_string()));
+        }
+
+        let min = format("{:b}", &[Object::Integer(i64::MIN)]);
+        assert_eq!(min, Ok(format!("1{}", "0".repeat(63))));
+        let widest = format("{:>65535}", &[Object::Integer(1)]);
+        assert_eq!(widest, Ok(format!("{}1", " ".repeat(65534))));
+    }
+
+    #[test]
+    fn test_format_errors() {
+        let tests = vec![
+            ("{} {}", vec![Object::Integer(1)]),
+            ("{", vec![]),
+            ("}", vec![]),
+            ("{:x}", vec![Object::String("a".to_string())]),
+            ("{:q}", vec![Object::Integer(1)]),
+            ("{missing}", vec![Object::Integer(1)]),
+            ("{:.65536}", vec![Object::Integer(1)]),
+        ];
+
+        for (template, args) in tests {
+            assert!(format(template, &args).is_err(), "{}", template);
+        }
+
+        assert_eq!(
+            format("{:99999999999}", &[Object::Integer(1)]),
+            Err("Format width or precision must be at most 65535, got 99999999999".to_string())
+        );
+    }
+
+    #[test]
+    fn test_padding() {
+        assert_eq!(padding("{} costs {}"), 0);
+        assert_eq!(padding("{:>6}|{name:*^7}|{:.2}"), 13);
+        assert_eq!(padding("{:é>10}"), 20);
+        assert_eq!(padding("{:99999999999}"), 0);
+    }
+}
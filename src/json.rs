@@ -0,0 +1,623 @@
+//! JSON text to and from [`Object`], used by the `json_parse` and
+//! `json_stringify` builtins.
+//!
+//! JSON null, booleans, strings, arrays and objects map onto the matching
+//! variants. The language only has integers, so numbers with a fraction or
+//! exponent are rejected rather than rounded. Parsed text may nest arrays
+//! and objects at most 128 levels deep, and pretty printing indents by at
+//! most 10 spaces per level like JavaScript does.
+
+use crate::object::Object;
+
+/// How deeply arrays and objects may nest in parsed text, the parser
+/// recurses for each level
+const MAX_DEPTH: usize = 128;
+
+/// The widest indent [`stringify`] takes
+pub const MAX_INDENT: usize = 10;
+
+/// A parse error, `line` and `col` are 1-based like parser errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    pub line: usize,
+    pub col: usize,
+    pub msg: String,
+}
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid JSON at {}:{}: {}",
+            self.line, self.col, self.msg
+        )
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+pub fn parse(src: &str) -> Result<Object, JsonError> {
+    let mut parser = JsonParser {
+        src: src.chars().collect(),
+        index: 0,
+        depth: 0,
+    };
+
+    parser.skip_whitespace();
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+
+    if let Some(c) = parser.peek() {
+        return Err(parser.error(&format!("Unexpected `{}` after value", c)));
+    }
+
+    Ok(value)
+}
+
+struct JsonParser {
+    src: Vec<char>,
+    index: usize,
+    // Arrays and objects being parsed
+    depth: usize,
+}
+
+impl JsonParser {
+    fn peek(&self) -> Option<char> {
+        self.src.get(self.index).copied()
+    }
+
+    fn error(&self, msg: &str) -> JsonError {
+        let before = &self.src[..self.index.min(self.src.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let col = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+
+        JsonError {
+            line,
+            col,
+            msg: msg.to_string(),
+        }
+    }
+
+    fn unexpected(&self) -> JsonError {
+        match self.peek() {
+            Some(c) => self.error(&format!("Unexpected `{}`", c)),
+            None => self.error("Unexpected end of input"),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.index += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        if self.peek() != Some(expected) {
+            return Err(match self.peek() {
+                Some(c) => self.error(&format!("Expected `{}`, got `{}`", expected, c)),
+                None => self.error(&format!("Expected `{}`, got end of input", expected)),
+            });
+        }
+
+        self.index += 1;
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> Result<Object, JsonError> {
+        match self.peek() {
+            Some('{') => self.parse_nested(Self::parse_object),
+            Some('[') => self.parse_nested(Self::parse_array),
+            Some('"') => Ok(Object::String(self.parse_string()?)),
+            Some('-' | '0'..='9') => self.parse_number(),
+            Some('a'..='z') => self.parse_keyword(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn parse_nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Object, JsonError>,
+    ) -> Result<Object, JsonError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(&format!("Nested more than {} levels deep", MAX_DEPTH)));
+        }
+
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_keyword(&mut self) -> Result<Object, JsonError> {
+        let start = self.index;
+        while let Some('a'..='z') = self.peek() {
+            self.index += 1;
+        }
+
+        let word: String = self.src[start..self.index].iter().collect();
+        match word.as_str() {
+            "null" => Ok(Object::Null),
+            "true" => Ok(Object::Boolean(true)),
+            "false" => Ok(Object::Boolean(false)),
+            _ => {
+                self.index = start;
+                Err(self.error(&format!("Unknown literal `{}`", word)))
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Object, JsonError> {
+        let start = self.index;
+        if self.peek() == Some('-') {
+            self.index += 1;
+        }
+
+        let digits = self.index;
+        while let Some('0'..='9') = self.peek() {
+            self.index += 1;
+        }
+        if self.index == digits {
+            return Err(self.unexpected());
+        }
+        if self.src[digits] == '0' && self.index - digits > 1 {
+            self.index = digits;
+            return Err(self.error("Leading zeros are not allowed"));
+        }
+
+        if let Some('.' | 'e' | 'E') = self.peek() {
+            return Err(self.error("Only integer numbers are supported"));
+        }
+
+        let number: String = self.src[start..self.index].iter().collect();
+        match number.parse() {
+            Ok(int) => Ok(Object::Integer(int)),
+            Err(_) => {
+                self.index = start;
+                Err(self.error(&format!("Number out of range: {}", number)))
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut string = String::new();
+
+        loop {
+            match self.peek() {
+                None => return Err(self.error("Unterminated string")),
+                Some('"') => {
+                    self.index += 1;
+                    return Ok(string);
+                }
+                Some('\\') => {
+                    self.index += 1;
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            self.index += 1;
+                            string.push(self.parse_unicode_escape()?);
+                            continue;
+                        }
+                        Some(c) => {
+                            return Err(self.error(&format!("Unknown escape character: {}", c)))
+                        }
+                        None => return Err(self.error("Unterminated string")),
+                    };
+                    string.push(escaped);
+                    self.index += 1;
+                }
+                Some(c) if c < ' ' => {
+                    return Err(self.error("Control characters must be escaped in strings"))
+                }
+                Some(c) => {
+                    string.push(c);
+                    self.index += 1;
+                }
+            }
+        }
+    }
+
+    // The four hex digits after `\u`, joining UTF-16 surrogate pairs
+    fn parse_unicode_escape(&mut self) -> Result<char, JsonError> {
+        let start = self.index;
+        let high = self.parse_hex4()?;
+
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if self.src.get(self.index) != Some(&'\\') || self.src.get(self.index + 1) != Some(&'u')
+            {
+                self.index = start;
+                return Err(self.error("Unpaired surrogate in unicode escape"));
+            }
+            self.index += 2;
+
+            let low = self.parse_hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                self.index = start;
+                return Err(self.error("Unpaired surrogate in unicode escape"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).ok_or_else(|| {
+            self.index = start;
+            self.error("Unpaired surrogate in unicode escape")
+        })
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err(self.error("Expected 4 hex digits in unicode escape")),
+            }
+            self.index += 1;
+        }
+
+        Ok(code)
+    }
+
+    fn parse_array(&mut self) -> Result<Object, JsonError> {
+        self.expect('[')?;
+        self.skip_whitespace();
+
+        let mut array = Vec::new();
+        if self.peek() == Some(']') {
+            self.index += 1;
+            return Ok(Object::Array(array));
+        }
+
+        loop {
+            self.skip_whitespace();
+            array.push(self.parse_value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => self.index += 1,
+                Some(']') => {
+                    self.index += 1;
+                    return Ok(Object::Array(array));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Object, JsonError> {
+        self.expect('{')?;
+        self.skip_whitespace();
+
+        let mut hash: Vec<(Object, Object)> = Vec::new();
+        if self.peek() == Some('}') {
+            self.index += 1;
+            return Ok(Object::Hash(hash));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = Object::String(self.parse_string()?);
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            let value = self.parse_value()?;
+
+            // Later duplicates win, like in most JSON parsers
+            match hash.iter_mut().find(|(k, _)| *k == key) {
+                Some(entry) => entry.1 = value,
+                None => hash.push((key, value)),
+            }
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.index += 1,
+                Some('}') => {
+                    self.index += 1;
+                    return Ok(Object::Hash(hash));
+                }
+                _ => return Err(self.unexpected()),
+            }
+        }
+    }
+}
+
+/// Serializes `obj`, compact without `indent` and pretty printed with
+/// `indent` spaces per level otherwise
+pub fn stringify(obj: &Object, indent: Option<usize>) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(&mut out, obj, check_indent(indent)?, 0)?;
+    Ok(out)
+}
+
+/// The length in bytes of what [`stringify`] makes of `obj`, to check the
+/// size before building it
+pub fn stringified_len(obj: &Object, indent: Option<usize>) -> Result<usize, String> {
+    let mut len = Len(0);
+    write_value(&mut len, obj, check_indent(indent)?, 0)?;
+    Ok(len.0)
+}
+
+fn check_indent(indent: Option<usize>) -> Result<Option<usize>, String> {
+    match indent {
+        Some(indent) if indent > MAX_INDENT => Err(format!(
+            "Indent must be at most {}, got {}",
+            MAX_INDENT, indent
+        )),
+        indent => Ok(indent),
+    }
+}
+
+// Where the text goes, a string or only its length
+trait Out {
+    fn push(&mut self, c: char);
+
+    fn push_str(&mut self, string: &str);
+}
+
+impl Out for String {
+    fn push(&mut self, c: char) {
+        String::push(self, c);
+    }
+
+    fn push_str(&mut self, string: &str) {
+        String::push_str(self, string);
+    }
+}
+
+struct Len(usize);
+
+impl Out for Len {
+    fn push(&mut self, c: char) {
+        self.0 = self.0.saturating_add(c.len_utf8());
+    }
+
+    fn push_str(&mut self, string: &str) {
+        self.0 = self.0.saturating_add(string.len());
+    }
+}
+
+fn write_value(
+    out: &mut impl Out,
+    obj: &Object,
+    indent: Option<usize>,
+    depth: usize,
+) -> Result<(), String> {
+    match obj {
+        Object::Null | Object::Empty => out.push_str("null"),
+        Object::Boolean(bool) => out.push_str(&bool.to_string()),
+        Object::Integer(int) => out.push_str(&int.to_string()),
+        Object::String(string) => write_string(out, string),
+        Object::Return(obj) => write_value(out, obj, indent, depth)?,
+        Object::Array(array) => {
+            let items = array.iter().map(|item| (None, item));
+            write_container(out, ('[', ']'), items, indent, depth)?;
+        }
+        Object::Hash(hash) => {
+            let mut items = Vec::new();
+            for (key, value) in hash {
+                match key {
+                    Object::String(key) => items.push((Some(key.as_str()), value)),
+                    _ => {
+                        return Err(format!(
+                            "JSON object keys must be strings, got {}",
+                            key.type_name()
+                        ))
+                    }
+                }
+            }
+            write_container(out, ('{', '}'), items.into_iter(), indent, depth)?;
+        }
+        _ => return Err(format!("Cannot convert {} to JSON", obj.type_name())),
+    }
+
+    Ok(())
+}
+
+fn write_container<'a>(
+    out: &mut impl Out,
+    (open, close): (char, char),
+    items: impl ExactSizeIterator<Item = (Option<&'a str>, &'a Object)>,
+    indent: Option<usize>,
+    depth: usize,
+) -> Result<(), String> {
+    out.push(open);
+    if items.len() == 0 {
+        out.push(close);
+        return Ok(());
+    }
+
+    for (i, (key, value)) in items.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if let Some(indent) = indent {
+            write_indent(out, indent, depth + 1)?;
+        }
+        if let Some(key) = key {
+            write_string(out, key);
+            out.push(':');
+            if indent.is_some() {
+                out.push(' ');
+            }
+        }
+        write_value(out, value, indent, depth + 1)?;
+    }
+
+    if let Some(indent) = indent {
+        write_indent(out, indent, depth)?;
+    }
+    out.push(close);
+
+    Ok(())
+}
+
+// A line break and the indent of `depth` levels
+fn write_indent(out: &mut impl Out, indent: usize, depth: usize) -> Result<(), String> {
+    let spaces = indent
+        .checked_mul(depth)
+        .ok_or_else(|| String::from("Nested too deeply to indent"))?;
+    out.push('\n');
+    for _ in 0..spaces {
+        out.push(' ');
+    }
+    Ok(())
+}
+
+fn write_string(out: &mut impl Out, string: &str) {
+    out.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, stringified_len, stringify};
+    use crate::object::Object;
+
+    fn string(s: &str) -> Object {
+        Object::String(s.to_string())
+    }
+
+    #[test]
+    fn test_parse() {
+        let tests = vec![
+            ("null", Object::Null),
+            (" true ", Object::Boolean(true)),
+            ("-42", Object::Integer(-42)),
+            (r#""a\"b\né😀""#, string("a\"b\né😀")),
+            (
+                "[1, [], {}]",
+                Object::Array(vec![
+                    Object::Integer(1),
+                    Object::Array(vec![]),
+                    Object::Hash(vec![]),
+                ]),
+            ),
+            (
+                "{\n  \"name\": \"pen\",\n  \"tags\": [\"a\"],\n  \"name\": \"ink\"\n}",
+                Object::Hash(vec![
+                    (string("name"), string("ink")),
+                    (string("tags"), Object::Array(vec![string("a")])),
+                ]),
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(parse(input), Ok(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let tests = vec![
+            ("", "Invalid JSON at 1:1: Unexpected end of input"),
+            ("[1, 2", "Invalid JSON at 1:6: Unexpected end of input"),
+            (
+                "{\n  \"a\" 1\n}",
+                "Invalid JSON at 2:7: Expected `:`, got `1`",
+            ),
+            (
+                "[1.5]",
+                "Invalid JSON at 1:3: Only integer numbers are supported",
+            ),
+            ("[nope]", "Invalid JSON at 1:2: Unknown literal `nope`"),
+            ("01", "Invalid JSON at 1:1: Leading zeros are not allowed"),
+            ("1 2", "Invalid JSON at 1:3: Unexpected `2` after value"),
+            (
+                "99999999999999999999",
+                "Invalid JSON at 1:1: Number out of range: 99999999999999999999",
+            ),
+            (
+                r#""\q""#,
+                "Invalid JSON at 1:3: Unknown escape character: q",
+            ),
+            (
+                r#""\ud83d""#,
+                "Invalid JSON at 1:4: Unpaired surrogate in unicode escape",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            assert_eq!(parse(input).unwrap_err().to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse(&nested(128)).is_ok());
+        assert_eq!(
+            parse(&nested(129)).unwrap_err().to_string(),
+            "Invalid JSON at 1:129: Nested more than 128 levels deep"
+        );
+        assert_eq!(
+            parse(&format!("{}{}", r#"{"a":"#.repeat(200000), "["))
+                .unwrap_err()
+                .msg,
+            "Nested more than 128 levels deep"
+        );
+    }
+
+    #[test]
+    fn test_stringify() {
+        let value = Object::Hash(vec![
+            (string("name"), string("a\"b\n")),
+            (
+                string("list"),
+                Object::Array(vec![
+                    Object::Integer(1),
+                    Object::Null,
+                    Object::Array(vec![]),
+                ]),
+            ),
+            (string("ok"), Object::Boolean(true)),
+        ]);
+
+        assert_eq!(
+            stringify(&value, None),
+            Ok(r#"{"name":"a\"b\n","list":[1,null,[]],"ok":true}"#.to_string())
+        );
+        assert_eq!(
+            stringify(&value, Some(2)),
+            Ok("{\n  \"name\": \"a\\\"b\\n\",\n  \"list\": [\n    1,\n    null,\n    []\n  ],\n  \"ok\": true\n}".to_string())
+        );
+        assert_eq!(
+            parse(&stringify(&value, Some(4)).unwrap()),
+            Ok(value.clone())
+        );
+        for indent in [None, Some(0), Some(10)] {
+            let len = stringify(&value, indent).unwrap().len();
+            assert_eq!(stringified_len(&value, indent), Ok(len));
+        }
+        assert_eq!(
+            stringify(&value, Some(11)),
+            Err("Indent must be at most 10, got 11".to_string())
+        );
+
+        assert_eq!(
+            stringify(
+                &Object::Hash(vec![(Object::Integer(1), Object::Null)]),
+                None
+            ),
+            Err("JSON object keys must be strings, got Integer".to_string())
+        );
+    }
+}
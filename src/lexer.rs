@@ -15,6 +15,30 @@ impl Position {
             index: 0,
         }
     }
+
+    /// 0-based line
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 0-based column, in code points
+    pub fn col(&self) -> usize {
+        self.col
+    }
+
+    /// 0-based offset in the source, in code points
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    // Makes a position relative to `base` absolute, used for nested lexers
+    fn offset(&mut self, base: &Position) {
+        if self.line == 0 {
+            self.col += base.col + 1;
+        }
+        self.line += base.line;
+        self.index += base.index + 1;
+    }
 }
 
 impl fmt::Debug for Position {
@@ -27,6 +51,19 @@ impl fmt::Debug for Position {
     }
 }
 
+impl Default for Position {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 1-based `line:col`, for error messages
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.col + 1)
+    }
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct Token {
     pub ttype: TokenType,
@@ -61,6 +98,12 @@ pub enum TokenType {
     Gt,
     Eq,
     Bang,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Tilde,
     LParen,
     RParen,
     LBrace,
@@ -70,9 +113,18 @@ pub enum TokenType {
     Comma,
     Period,
     String,
+    Interpolated(Vec<StringPart>),
+    Illegal, // Literal holds the error message
     Eof,
 }
 
+/// A piece of an interpolated string literal such as `"Hello ${name}"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StringPart {
+    Literal(String),
+    Code(Vec<Token>),
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeywordType {
     Let,
@@ -82,34 +134,53 @@ pub enum KeywordType {
     If,
     Else,
     Fn,
+    Import,
+    As,
+    Export,
+    From,
 }
 
 pub struct Lexer {
-    src: String,
+    src: Vec<char>,
     position: Position,
     current: char,
 }
 
-const KEYWORDS: &[&str] = &["let", "return", "true", "false", "if", "else", "fn"];
+const KEYWORDS: &[&str] = &[
+    "let", "return", "true", "false", "if", "else", "fn", "import", "as", "export", "from",
+];
 
 impl Lexer {
     pub fn new(src: String) -> Self {
         Self {
-            src: src.clone(),
-            position: Position::new(),
             current: src.chars().next().unwrap_or('\0'),
+            src: src.chars().collect(),
+            position: Position::new(),
         }
     }
 
     pub fn gen_tokens(&mut self) -> Vec<Token> {
         let mut tokens = Vec::new();
 
+        // A `#!` line at the very start is for the OS, as in `#!/usr/bin/env monki`
+        if self.position.index == 0 && self.current == '#' && self.peek() == '!' {
+            while !matches!(self.current, '\n' | '\0') {
+                self.advance();
+            }
+        }
+
         while self.current != '\0' {
             if self.current.is_whitespace() {
                 self.advance();
                 continue;
             }
 
+            if self.is_raw_string_start() {
+                tokens.push(self.gen_raw_string());
+                self.advance();
+                continue;
+            }
+
             if self.current.is_alphabetic() {
                 tokens.push(self.gen_ident());
                 continue;
@@ -128,10 +199,11 @@ impl Lexer {
                 continue;
             }
 
-            panic!(
-                "Unknown token: {} ascci code: {}",
-                self.current, self.current as u8
-            );
+            tokens.push(self.illegal(
+                &format!("Unknown character {:?}", self.current),
+                self.position.clone(),
+            ));
+            self.advance();
         }
 
         tokens.push(Token {
@@ -176,11 +248,12 @@ impl Lexer {
             }),
             '=' => {
                 if self.peek() == '=' {
+                    let position = self.position.clone();
                     self.advance();
                     return Some(Token {
                         ttype: TokenType::Eq,
                         literal: String::from("=="),
-                        position: self.position.clone(),
+                        position,
                     });
                 }
 
@@ -192,11 +265,12 @@ impl Lexer {
             }
             '!' => {
                 if self.peek() == '=' {
+                    let position = self.position.clone();
                     self.advance();
                     return Some(Token {
                         ttype: TokenType::NotEq,
                         literal: String::from("!="),
-                        position: self.position.clone(),
+                        position,
                     });
                 }
 
@@ -206,14 +280,58 @@ impl Lexer {
                     position: self.position.clone(),
                 })
             }
-            '<' => Some(Token {
-                ttype: TokenType::Lt,
-                literal: String::from("<"),
+            '<' => {
+                if self.peek() == '<' {
+                    let position = self.position.clone();
+                    self.advance();
+                    return Some(Token {
+                        ttype: TokenType::ShiftLeft,
+                        literal: String::from("<<"),
+                        position,
+                    });
+                }
+
+                Some(Token {
+                    ttype: TokenType::Lt,
+                    literal: String::from("<"),
+                    position: self.position.clone(),
+                })
+            }
+            '>' => {
+                if self.peek() == '>' {
+                    let position = self.position.clone();
+                    self.advance();
+                    return Some(Token {
+                        ttype: TokenType::ShiftRight,
+                        literal: String::from(">>"),
+                        position,
+                    });
+                }
+
+                Some(Token {
+                    ttype: TokenType::Gt,
+                    literal: String::from(">"),
+                    position: self.position.clone(),
+                })
+            }
+            '&' => Some(Token {
+                ttype: TokenType::BitAnd,
+                literal: String::from("&"),
                 position: self.position.clone(),
             }),
-            '>' => Some(Token {
-                ttype: TokenType::Gt,
-                literal: String::from(">"),
+            '|' => Some(Token {
+                ttype: TokenType::BitOr,
+                literal: String::from("|"),
+                position: self.position.clone(),
+            }),
+            '^' => Some(Token {
+                ttype: TokenType::BitXor,
+                literal: String::from("^"),
+                position: self.position.clone(),
+            }),
+            '~' => Some(Token {
+                ttype: TokenType::Tilde,
+                literal: String::from("~"),
                 position: self.position.clone(),
             }),
             '(' => Some(Token {
@@ -256,45 +374,325 @@ impl Lexer {
                 literal: String::from(":"),
                 position: self.position.clone(),
             }),
-            '"' => {
-                let mut string = String::new();
-                self.advance();
+            '"' => Some(self.gen_string()),
+            _ => None,
+        }
+    }
 
-                while self.current != '"' {
-                    if self.current == '\\' {
-                        self.advance();
-                        match self.current {
-                            'n' => string.push('\n'),
-                            't' => string.push('\t'),
-                            'r' => string.push('\r'),
-                            '\\' => string.push('\\'),
-                            '"' => string.push('"'),
-                            _ => panic!("Unknown escape character: {}", self.current),
+    fn gen_string(&mut self) -> Token {
+        let start = self.position.clone();
+        let triple = self.peek_n(1) == '"' && self.peek_n(2) == '"';
+        let mut indent = 0;
+
+        if triple {
+            self.advance();
+            self.advance();
+            indent = self.triple_quote_indent();
+        }
+
+        let mut parts = Vec::new();
+        let mut string = String::new();
+        let mut error: Option<String> = None;
+        // Where the current line starts in `string` and whether it only holds whitespace so far
+        let mut line_start: usize = 0;
+        let mut line_blank = false;
+        self.advance();
+
+        if triple && self.current == '\r' && self.peek() == '\n' {
+            self.advance();
+        }
+        if triple && self.current == '\n' {
+            self.advance();
+            self.skip_indent(indent);
+            line_blank = true;
+        }
+
+        loop {
+            match self.current {
+                '\0' => {
+                    return self.illegal("Unterminated string", start);
+                }
+                '"' if !triple => break,
+                '"' if self.peek_n(1) == '"' && self.peek_n(2) == '"' => {
+                    self.advance();
+                    self.advance();
+
+                    // The line break before a closing `"""` on its own line is not part of the string
+                    if line_blank {
+                        string.truncate(line_start.saturating_sub(1));
+                    }
+                    break;
+                }
+                '\\' => {
+                    self.advance();
+                    match self.escape() {
+                        Ok(c) => string.push(c),
+                        Err(msg) => {
+                            error.get_or_insert(msg);
                         }
-                    } else {
-                        string.push(self.current);
                     }
+                    line_blank = false;
+                }
+                '$' if self.peek() == '{' => {
+                    if !string.is_empty() {
+                        parts.push(StringPart::Literal(std::mem::take(&mut string)));
+                    }
+                    match self.gen_interpolation() {
+                        Ok(tokens) => parts.push(StringPart::Code(tokens)),
+                        Err(msg) => return self.illegal(&msg, start),
+                    }
+                    line_start = 0;
+                    line_blank = false;
+                }
+                '\n' if triple => {
+                    string.push('\n');
                     self.advance();
+                    self.skip_indent(indent);
+                    line_start = string.len();
+                    line_blank = true;
+                    continue;
                 }
+                c => {
+                    string.push(c);
+                    line_blank = line_blank && c.is_whitespace();
+                }
+            }
+            self.advance();
+        }
 
-                Some(Token {
-                    ttype: TokenType::String,
-                    literal: string,
-                    position: self.position.clone(),
-                })
+        if let Some(msg) = error {
+            return self.illegal(&msg, start);
+        }
+
+        if parts.is_empty() {
+            return Token {
+                ttype: TokenType::String,
+                literal: string,
+                position: start,
+            };
+        }
+
+        if !string.is_empty() {
+            parts.push(StringPart::Literal(string));
+        }
+
+        Token {
+            ttype: TokenType::Interpolated(parts),
+            literal: String::from("\""),
+            position: start,
+        }
+    }
+
+    // Reads the escape sequence after a `\`, leaving `current` on its last character
+    fn escape(&mut self) -> Result<char, String> {
+        match self.current {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '\\' => Ok('\\'),
+            '"' => Ok('"'),
+            '$' => Ok('$'),
+            'x' => {
+                let digits: String = (1..=2).map(|n| self.peek_n(n)).collect();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(byte) if byte <= 0x7F && digits.len() == 2 => {
+                        self.advance();
+                        self.advance();
+                        Ok(byte as char)
+                    }
+                    _ => Err(format!(
+                        "Invalid escape \\x{}, expected two hex digits up to 7F",
+                        digits
+                    )),
+                }
+            }
+            'u' => {
+                if self.peek() != '{' {
+                    return Err(String::from("Invalid escape \\u, expected \\u{...}"));
+                }
+                self.advance();
+
+                let mut digits = String::new();
+                while self.peek().is_ascii_hexdigit() && digits.len() < 6 {
+                    self.advance();
+                    digits.push(self.current);
+                }
+
+                if self.peek() != '}' {
+                    return Err(format!("Unterminated escape \\u{{{}", digits));
+                }
+                self.advance();
+
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or(format!("Invalid unicode escape \\u{{{}}}", digits))
+            }
+            '\0' => Err(String::from("Unterminated string")),
+            c => Err(format!("Unknown escape character: {}", c)),
+        }
+    }
+
+    // Common indentation of a `"""` string's lines, `current` is on the last opening quote
+    fn triple_quote_indent(&self) -> usize {
+        let mut index = self.position.index + 1;
+        let mut indent: Option<usize> = None;
+
+        // Text on the opening line keeps its indentation
+        while !matches!(self.char_at(index), '\n' | '\0') {
+            index += 1;
+        }
+
+        while self.char_at(index) == '\n' {
+            index += 1;
+
+            let mut width = 0;
+            while matches!(self.char_at(index), ' ' | '\t') {
+                width += 1;
+                index += 1;
+            }
+
+            let closing = (0..3).all(|n| self.char_at(index + n) == '"');
+            let blank = matches!(self.char_at(index), '\n' | '\r' | '\0');
+            if closing || !blank {
+                indent = Some(indent.map_or(width, |indent| indent.min(width)));
+            }
+            if closing {
+                break;
+            }
+
+            while !matches!(self.char_at(index), '\n' | '\0') {
+                if (0..3).all(|n| self.char_at(index + n) == '"') {
+                    return indent.unwrap_or(0);
+                }
+                index += 1;
             }
-            _ => None,
+        }
+
+        indent.unwrap_or(0)
+    }
+
+    fn skip_indent(&mut self, indent: usize) {
+        for _ in 0..indent {
+            if !matches!(self.current, ' ' | '\t') {
+                break;
+            }
+            self.advance();
+        }
+    }
+
+    // `r"..."` or `r#"..."#`, `current` is on the `r`
+    fn gen_raw_string(&mut self) -> Token {
+        let start = self.position.clone();
+        let mut hashes = 0;
+        self.advance();
+
+        while self.current == '#' {
+            hashes += 1;
+            self.advance();
+        }
+
+        let mut string = String::new();
+        self.advance();
+
+        loop {
+            match self.current {
+                '\0' => return self.illegal("Unterminated raw string", start),
+                '"' if (1..=hashes).all(|n| self.peek_n(n) == '#') => {
+                    for _ in 0..hashes {
+                        self.advance();
+                    }
+                    break;
+                }
+                c => string.push(c),
+            }
+            self.advance();
+        }
+
+        Token {
+            ttype: TokenType::String,
+            literal: string,
+            position: start,
+        }
+    }
+
+    fn is_raw_string_start(&self) -> bool {
+        if self.current != 'r' {
+            return false;
+        }
+
+        let mut n = 1;
+        while self.peek_n(n) == '#' {
+            n += 1;
+        }
+        self.peek_n(n) == '"'
+    }
+
+    // Lexes the code of a `${...}` segment, leaving `current` on the closing brace
+    fn gen_interpolation(&mut self) -> Result<Vec<Token>, String> {
+        self.advance();
+        let start = self.position.clone();
+        let mut code = String::new();
+        let mut depth = 0;
+
+        loop {
+            self.advance();
+            match self.current {
+                '\0' => return Err(String::from("Unterminated interpolation in string")),
+                '{' => depth += 1,
+                '}' if depth == 0 => break,
+                '}' => depth -= 1,
+                '"' => {
+                    // Nested string literal, copy it verbatim
+                    code.push(self.current);
+                    self.advance();
+                    while self.current != '"' {
+                        if self.current == '\0' {
+                            return Err(String::from("Unterminated string"));
+                        }
+                        if self.current == '\\' {
+                            code.push(self.current);
+                            self.advance();
+                        }
+                        code.push(self.current);
+                        self.advance();
+                    }
+                }
+                _ => {}
+            }
+            code.push(self.current);
+        }
+
+        let mut tokens = Lexer::new(code).gen_tokens();
+        for token in &mut tokens {
+            token.position.offset(&start);
+        }
+        Ok(tokens)
+    }
+
+    fn illegal(&self, msg: &str, position: Position) -> Token {
+        Token {
+            ttype: TokenType::Illegal,
+            literal: msg.to_string(),
+            position,
         }
     }
 
     fn peek(&self) -> char {
-        self.src
-            .chars()
-            .nth(self.position.index + 1)
-            .unwrap_or('\0')
+        self.peek_n(1)
+    }
+
+    fn peek_n(&self, n: usize) -> char {
+        self.char_at(self.position.index + n)
+    }
+
+    fn char_at(&self, index: usize) -> char {
+        self.src.get(index).copied().unwrap_or('\0')
     }
 
     fn gen_ident(&mut self) -> Token {
+        let position = self.position.clone();
         let mut ident = String::new();
 
         while self.current.is_alphabetic() || self.current == '_' {
@@ -311,50 +709,97 @@ impl Lexer {
                 "if" => KeywordType::If,
                 "else" => KeywordType::Else,
                 "fn" => KeywordType::Fn,
+                "import" => KeywordType::Import,
+                "as" => KeywordType::As,
+                "export" => KeywordType::Export,
+                "from" => KeywordType::From,
                 _ => panic!("Unknown Keyword: {}", ident),
             };
 
             return Token {
                 ttype: TokenType::Keyword(keyword),
                 literal: ident,
-                position: self.position.clone(),
+                position,
             };
         }
 
         Token {
             ttype: TokenType::Ident,
             literal: ident,
-            position: self.position.clone(),
+            position,
         }
     }
 
     fn gen_number(&mut self) -> Token {
+        let position = self.position.clone();
         let mut number = String::new();
 
-        while self.current.is_numeric() {
+        let prefixed = self.current == '0' && matches!(self.peek(), 'x' | 'o' | 'b');
+        if prefixed {
+            number.push(self.current);
+            self.advance();
             number.push(self.current);
             self.advance();
         }
 
+        // Prefixed literals take any alphanumerics so `0b102` is one (bad) literal
+        while self.current.is_ascii_digit()
+            || self.current == '_'
+            || (prefixed && self.current.is_alphanumeric())
+        {
+            number.push(self.current);
+            self.advance();
+        }
+
+        if let Err(msg) = parse_integer(&number) {
+            return self.illegal(&msg, position);
+        }
+
         Token {
             ttype: TokenType::Number,
             literal: number,
-            position: self.position.clone(),
+            position,
         }
     }
 
     pub fn advance(&mut self) {
-        self.position.index += 1;
-        self.position.col += 1;
-        self.current = self.src.chars().nth(self.position.index).unwrap_or('\0');
-
         if self.current == '\n' {
             self.position.line += 1;
             self.position.col = 0;
+        } else {
+            self.position.col += 1;
         }
+
+        self.position.index += 1;
+        self.current = self.char_at(self.position.index);
     }
 }
 
+/// Parses an integer literal such as `42`, `1_000`, `0xFF`, `0o755` or `0b1010`
+pub fn parse_integer(literal: &str) -> Result<i64, String> {
+    let (radix, digits) = match literal.get(..2) {
+        Some("0x") => (16, &literal[2..]),
+        Some("0o") => (8, &literal[2..]),
+        Some("0b") => (2, &literal[2..]),
+        _ => (10, literal),
+    };
+
+    let digits: String = digits.chars().filter(|c| *c != '_').collect();
+    if digits.is_empty() {
+        return Err(format!("Integer literal {} has no digits", literal));
+    }
+
+    if let Some(c) = digits.chars().find(|c| !c.is_digit(radix)) {
+        return Err(format!(
+            "Invalid digit {} in base {} literal {}",
+            c, radix, literal
+        ));
+    }
+
+    i64::from_str_radix(&digits, radix)
+        .map_err(|_| format!("Integer literal {} is out of range", literal))
+}
+
 #[cfg(test)]
 mod test {
 
@@ -370,6 +815,213 @@ mod test {
         assert_eq!(tokens[0].literal, String::from("Hello, World!"));
     }
 
+    #[test]
+    fn test_interpolated_string() {
+        use super::{Lexer, StringPart, TokenType};
+
+        let input = String::from(r#""a ${x + 1} b""#);
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        match &tokens[0].ttype {
+            TokenType::Interpolated(parts) => {
+                assert_eq!(parts.len(), 3);
+                assert_eq!(parts[0], StringPart::Literal(String::from("a ")));
+                assert_eq!(parts[2], StringPart::Literal(String::from(" b")));
+
+                match &parts[1] {
+                    StringPart::Code(code) => {
+                        assert_eq!(code[0].ttype, TokenType::Ident);
+                        assert_eq!(code[0].literal, String::from("x"));
+                        assert_eq!(code[0].position.col, 5);
+                        assert_eq!(code[1].ttype, TokenType::Add);
+                        assert_eq!(code[2].ttype, TokenType::Number);
+                        assert_eq!(code[3].ttype, TokenType::Eof);
+                    }
+                    part => panic!("Expected code part, got {:?}", part),
+                }
+            }
+            ttype => panic!("Expected interpolated string, got {:?}", ttype),
+        }
+        assert_eq!(tokens[1].ttype, TokenType::Eof);
+    }
+
+    #[test]
+    fn test_raw_string() {
+        use super::{Lexer, TokenType};
+
+        let tests = vec![
+            (r#"r"C:\path\n""#, r"C:\path\n"),
+            (r###"r#"say "hi""#"###, r#"say "hi""#),
+            (r###"r##"a "# b"##"###, r##"a "# b"##),
+            ("r\"two\nlines ${x}\"", "two\nlines ${x}"),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            assert_eq!(tokens[0].ttype, TokenType::String, "input: {}", input);
+            assert_eq!(tokens[0].literal, expected);
+            assert_eq!(tokens[1].ttype, TokenType::Eof);
+        }
+    }
+
+    #[test]
+    fn test_escapes() {
+        use super::{Lexer, TokenType};
+
+        let input = String::from(r#""\x41\u{1F600}\u{e9}\t\0""#);
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        assert_eq!(tokens[0].ttype, TokenType::String);
+        assert_eq!(tokens[0].literal, String::from("A😀é\t\0"));
+
+        for input in [
+            r#""\q""#,
+            r#""\x80""#,
+            r#""\u{110000}""#,
+            r#""\u{41""#,
+            r#""abc"#,
+        ] {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            assert_eq!(tokens[0].ttype, TokenType::Illegal, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_multiline_string() {
+        use super::{Lexer, TokenType};
+
+        let input =
+            String::from("let s = \"\"\"\n        hello\n          world\n\n        \"\"\"; s");
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        assert_eq!(tokens[3].ttype, TokenType::String);
+        assert_eq!(tokens[3].literal, String::from("hello\n  world\n"));
+        assert_eq!(tokens[4].ttype, TokenType::Semicolon);
+        assert_eq!(tokens[4].position.line, 4);
+        assert_eq!(tokens[5].literal, String::from("s"));
+        assert_eq!(tokens[5].position.line, 4);
+        assert_eq!(tokens[5].position.col, 13);
+
+        let input = String::from("\"\"\"one \"quoted\" ${x}\n  two\"\"\"");
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        match &tokens[0].ttype {
+            TokenType::Interpolated(parts) => assert_eq!(parts.len(), 3),
+            ttype => panic!("Expected interpolated string, got {:?}", ttype),
+        }
+    }
+
+    #[test]
+    fn test_positions() {
+        use super::{Lexer, TokenType};
+
+        let input = String::from("let a = \"x\ny\";\nlet b");
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        assert_eq!(tokens[3].ttype, TokenType::String);
+        assert_eq!((tokens[3].position.line, tokens[3].position.col), (0, 8));
+        assert_eq!(tokens[4].ttype, TokenType::Semicolon);
+        assert_eq!((tokens[4].position.line, tokens[4].position.col), (1, 2));
+        assert_eq!(tokens[6].literal, String::from("b"));
+        assert_eq!((tokens[6].position.line, tokens[6].position.col), (2, 4));
+        assert_eq!(tokens[6].position.to_string(), String::from("3:5"));
+    }
+
+    #[test]
+    fn test_shebang() {
+        use super::{Lexer, TokenType};
+
+        let input = String::from("#!/usr/bin/env monki\nlet a = 1 # 2");
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        assert_eq!(tokens[0].literal, String::from("let"));
+        assert_eq!(tokens[0].position.to_string(), String::from("2:1"));
+        assert_eq!(tokens[4].ttype, TokenType::Illegal);
+        assert_eq!(tokens[4].literal, String::from("Unknown character '#'"));
+        assert_eq!(tokens[5].literal, String::from("2"));
+
+        // Only the first line can be one
+        let mut l = Lexer::new(String::from("1\n#!x"));
+        assert_eq!(l.gen_tokens()[1].ttype, TokenType::Illegal);
+    }
+
+    #[test]
+    fn test_integer_literals() {
+        use super::{parse_integer, Lexer, TokenType};
+
+        let tests = vec![
+            ("0xFF", 255),
+            ("0o755", 493),
+            ("0b1010", 10),
+            ("1_000_000", 1_000_000),
+            ("0x_dead_BEEF", 0xdead_beef),
+            ("9223372036854775807", i64::MAX),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            assert_eq!(tokens[0].ttype, TokenType::Number, "input: {}", input);
+            assert_eq!(tokens[0].literal, input);
+            assert_eq!(parse_integer(&tokens[0].literal), Ok(expected));
+        }
+
+        for input in [
+            "9223372036854775808",
+            "0x1_0000_0000_0000_0000",
+            "0b102",
+            "0x",
+        ] {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            assert_eq!(tokens[0].ttype, TokenType::Illegal, "input: {}", input);
+            assert_eq!(tokens[1].ttype, TokenType::Eof);
+        }
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        use super::{Lexer, TokenType};
+
+        let input = String::from("a & b | c ^ ~d << 1 >> 2 < 3");
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        let expected = vec![
+            TokenType::Ident,
+            TokenType::BitAnd,
+            TokenType::Ident,
+            TokenType::BitOr,
+            TokenType::Ident,
+            TokenType::BitXor,
+            TokenType::Tilde,
+            TokenType::Ident,
+            TokenType::ShiftLeft,
+            TokenType::Number,
+            TokenType::ShiftRight,
+            TokenType::Number,
+            TokenType::Lt,
+            TokenType::Number,
+            TokenType::Eof,
+        ];
+
+        for (token, ttype) in tokens.iter().zip(expected) {
+            assert_eq!(token.ttype, ttype);
+        }
+    }
+
     #[test]
     fn lexer_test() {
         use super::{KeywordType, Lexer, TokenType};
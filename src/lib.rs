@@ -0,0 +1,297 @@
+//! Monki, a small scripting language based on the Monkey language.
+//!
+//! [`Interpreter`] is the entry point for embedding: it runs source code
+//! through the [`lexer::Lexer`], [`parser::Parser`] and [`eval::Evaluator`]
+//! and keeps global state between calls.
+//!
+//! ```
+//! use monki::{Interpreter, object::Object};
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.set_global("base", Object::Integer(40));
+//! interpreter.eval_str("let add = fn(x) { base + x };").unwrap();
+//!
+//! let result = interpreter.call_function("add", vec![Object::Integer(2)]);
+//! assert_eq!(result.unwrap(), Object::Integer(42));
+//! ```
+
+use std::path::Path;
+use std::time::Instant;
+
+use crate::ast::Program;
+use crate::eval::Evaluator;
+use crate::lexer::Lexer;
+use crate::native::{Args, Caller, Ctx, IntoNative, NativeModule};
+use crate::object::Object;
+use crate::parser::Parser;
+use crate::vm::Vm;
+
+pub mod ast;
+mod budget;
+pub mod builtin;
+pub mod compiler;
+pub mod convert;
+pub mod dump;
+pub mod env;
+pub mod error;
+pub mod eval;
+pub mod format;
+pub mod json;
+pub mod lexer;
+mod loader;
+pub mod native;
+pub mod object;
+pub mod parser;
+pub mod permissions;
+pub mod printer;
+pub mod resolver;
+pub mod stdlib;
+pub mod vm;
+
+pub use crate::error::{Resource, RuntimeError};
+pub use crate::permissions::Permissions;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The source file could not be read
+    Io(std::io::Error),
+    /// Lexer, parser and resolver errors, formatted as `line:col: message`
+    Syntax(Vec<String>),
+    /// Evaluation produced an error object
+    Runtime(RuntimeError),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Syntax(errors) => write!(f, "{}", errors.join("\n")),
+            Error::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+pub struct Interpreter {
+    engine: Engine,
+}
+
+// What runs the scripts, both give the same results
+enum Engine {
+    Evaluator(Evaluator),
+    Vm(Vm),
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self {
+            engine: Engine::Evaluator(Evaluator::new()),
+        }
+    }
+
+    /// An interpreter compiling scripts to bytecode for the [`vm::Vm`]
+    /// instead of walking the syntax tree
+    pub fn with_vm() -> Self {
+        Self {
+            engine: Engine::Vm(Vm::new()),
+        }
+    }
+
+    /// Limits how many script function calls may be active at once,
+    /// [`eval::MAX_DEPTH`] by default. Going over it is a
+    /// [`RuntimeError::StackOverflow`]. The tree-walking evaluator uses the
+    /// native stack for every call and nested expression, run it on a
+    /// thread with [`eval::STACK_PER_CALL`] bytes of stack per call allowed.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator.set_max_depth(depth),
+            Engine::Vm(vm) => vm.set_max_depth(depth),
+        }
+    }
+
+    /// Limits how many more steps scripts may take, unlimited if `None`.
+    /// A step is an expression or statement on the tree-walking evaluator
+    /// and an instruction on the VM. Running out is a
+    /// [`RuntimeError::ResourceExhausted`] and stays so until more fuel is
+    /// set.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator.set_fuel(fuel),
+            Engine::Vm(vm) => vm.set_fuel(fuel),
+        }
+    }
+
+    /// Fuel left, see [`Interpreter::set_fuel`]
+    pub fn fuel(&self) -> Option<u64> {
+        match &self.engine {
+            Engine::Evaluator(evaluator) => evaluator.fuel(),
+            Engine::Vm(vm) => vm.fuel(),
+        }
+    }
+
+    /// Stops scripts still running at `deadline` with a
+    /// [`RuntimeError::ResourceExhausted`], `None` lets them run
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator.set_deadline(deadline),
+            Engine::Vm(vm) => vm.set_deadline(deadline),
+        }
+    }
+
+    /// Limits the bytes any single array, string or hash may take, nested
+    /// values included, unlimited if `None`. Creating a larger one is a
+    /// [`RuntimeError::ResourceExhausted`] error. The limit is for each
+    /// value on its own, not for all the values a script keeps.
+    pub fn set_value_size_limit(&mut self, bytes: Option<usize>) {
+        match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator.set_value_size_limit(bytes),
+            Engine::Vm(vm) => vm.set_value_size_limit(bytes),
+        }
+    }
+
+    /// Sets which effects on the host scripts may have, everything is
+    /// allowed by default
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator.set_permissions(permissions),
+            Engine::Vm(vm) => vm.set_permissions(permissions),
+        }
+    }
+
+    /// Evaluates `src` in the global scope and returns the value of its last statement
+    pub fn eval_str(&mut self, src: &str) -> Result<Object, Error> {
+        let program = self.parse(src)?;
+
+        let result = match &mut self.engine {
+            Engine::Evaluator(evaluator) => {
+                let result = evaluator.eval_resolved(&program);
+                result_of(evaluator, result)
+            }
+            Engine::Vm(vm) => vm.eval_resolved(&program),
+        };
+        result.map_err(Error::Runtime)
+    }
+
+    /// Reads and evaluates a source file, see [`Interpreter::eval_str`].
+    /// Imports in the file are resolved relative to its directory.
+    pub fn eval_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Object, Error> {
+        let src = std::fs::read_to_string(&path)?;
+        let program = self.parse(&src)?;
+
+        let result = match &mut self.engine {
+            Engine::Evaluator(evaluator) => {
+                let result = evaluator.eval_file(&program, path.as_ref());
+                result_of(evaluator, result)
+            }
+            Engine::Vm(vm) => vm.eval_file(&program, path.as_ref()),
+        };
+        result.map_err(Error::Runtime)
+    }
+
+    /// Parses `src` and resolves its names against the globals, without
+    /// running it. Used to check scripts for errors.
+    pub fn parse(&self, src: &str) -> Result<Program, Error> {
+        let mut program = parse(src)?;
+        let resolved = match &self.engine {
+            Engine::Evaluator(evaluator) => evaluator.resolve(&mut program),
+            Engine::Vm(vm) => vm.resolve(&mut program),
+        };
+        resolved.map_err(Error::Syntax)?;
+
+        Ok(program)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Object) {
+        match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator.set_global(name, value),
+            Engine::Vm(vm) => vm.set_global(name, value),
+        }
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        match &self.engine {
+            Engine::Evaluator(evaluator) => evaluator.get_global(name),
+            Engine::Vm(vm) => vm.get_global(name),
+        }
+    }
+
+    /// Calls the global function `name` with `args`
+    pub fn call_function(&mut self, name: &str, args: Vec<Object>) -> Result<Object, Error> {
+        let caller: &mut dyn Caller = match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator,
+            Engine::Vm(vm) => vm,
+        };
+        let function = caller
+            .lookup(name)
+            .ok_or(Error::Runtime(RuntimeError::new(&format!(
+                "Identifier not found: {}",
+                name
+            ))))?;
+
+        caller.call(&function, args).map_err(Error::Runtime)
+    }
+
+    /// Registers a native taking the raw arguments, see [`native`]
+    pub fn register_native<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&mut Ctx<'_>, Args) -> Result<Object, RuntimeError> + 'static,
+    {
+        match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator.register_native(name, func),
+            Engine::Vm(vm) => vm.register_native(name, func),
+        }
+    }
+
+    /// Registers a Rust function or closure with typed arguments, e.g. `|a: i64, b: String| ...`
+    pub fn register_fn<F, Marker>(&mut self, name: &str, func: F)
+    where
+        F: IntoNative<Marker>,
+    {
+        match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator.register_fn(name, func),
+            Engine::Vm(vm) => vm.register_fn(name, func),
+        }
+    }
+
+    /// Registers a group of natives reachable as `module.function(...)`
+    pub fn register_module(&mut self, module: NativeModule) {
+        match &mut self.engine {
+            Engine::Evaluator(evaluator) => evaluator.register_module(module),
+            Engine::Vm(vm) => vm.register_module(module),
+        }
+    }
+}
+
+// The evaluator reports errors as error objects
+fn result_of(evaluator: &mut Evaluator, result: Option<Object>) -> Result<Object, RuntimeError> {
+    match result {
+        Some(Object::Error(msg)) => Err(evaluator.take_error(msg)),
+        Some(obj) => Ok(obj),
+        None => Ok(Object::Null),
+    }
+}
+
+/// Lexes and parses `src` into its syntax tree, names are left unresolved
+pub fn parse(src: &str) -> Result<Program, Error> {
+    let mut lexer = Lexer::new(src.to_string());
+    let tokens = lexer.gen_tokens();
+
+    let mut parser = Parser::new(tokens);
+    match parser.parse_program() {
+        Some(program) => Ok(program),
+        None => Err(Error::Syntax(parser.errors)),
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
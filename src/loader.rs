@@ -0,0 +1,178 @@
+//! Finding, caching and cycle checking of imported modules, shared by the
+//! [`crate::eval::Evaluator`] and the [`crate::vm::Vm`]. Running a module's
+//! source is left to them.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast::{ImportPath, Program};
+use crate::env::Env;
+use crate::error::RuntimeError;
+use crate::lexer::Lexer;
+use crate::object::Object;
+use crate::parser::Parser;
+use crate::permissions::Permissions;
+use crate::resolver;
+use crate::stdlib;
+
+#[derive(Default)]
+pub(crate) struct Loader {
+    // Files being evaluated, innermost last, for relative imports and cycles
+    pub(crate) files: Vec<PathBuf>,
+    // Loaded modules by canonical path
+    modules: HashMap<PathBuf, Object>,
+    // Loaded std modules by name, without the std
+    std_modules: HashMap<String, Object>,
+}
+
+pub(crate) enum Import {
+    Loaded(Object),
+    Load(Load),
+}
+
+/// A module that still has to run, with `env` as its global scope
+pub(crate) struct Load {
+    pub(crate) env: Rc<RefCell<Env>>,
+    /// Canonical path of a file module, to be pushed while it runs
+    pub(crate) file: Option<PathBuf>,
+    src: String,
+    // As shown by `Object::Module`
+    path: String,
+    // As shown in syntax errors
+    name: String,
+}
+
+impl Load {
+    /// Parses and resolves the module's source
+    pub(crate) fn parse(&self, natives: &HashMap<String, Object>) -> Result<Program, RuntimeError> {
+        let mut lexer = Lexer::new(self.src.clone());
+        let mut parser = Parser::new(lexer.gen_tokens());
+        let mut program = match parser.parse_program() {
+            Some(program) => program,
+            None => return Err(self.syntax_error(&parser.errors)),
+        };
+
+        let globals = self
+            .env
+            .borrow()
+            .names()
+            .chain(natives.keys())
+            .cloned()
+            .collect();
+        resolver::resolve(&mut program, globals).map_err(|errors| self.syntax_error(&errors))?;
+
+        Ok(program)
+    }
+
+    fn syntax_error(&self, errors: &[String]) -> RuntimeError {
+        RuntimeError::new(&format!(
+            "Syntax error in {}: {}",
+            self.name,
+            errors.join(", ")
+        ))
+    }
+}
+
+impl Loader {
+    /// The module for `path`, or what is needed to load it
+    pub(crate) fn import(
+        &self,
+        path: &ImportPath,
+        permissions: &Permissions,
+    ) -> Result<Import, RuntimeError> {
+        match path {
+            ImportPath::File(path) => self.import_file(path, permissions),
+            ImportPath::Std(name) => self.import_std(name),
+        }
+    }
+
+    /// Caches the module once `load` ran successfully
+    pub(crate) fn finish(&mut self, load: Load) -> Object {
+        let module = Object::Module {
+            path: load.path.clone(),
+            env: load.env,
+        };
+
+        match load.file {
+            Some(file) => self.modules.insert(file, module.clone()),
+            None => self.std_modules.insert(
+                load.path.trim_start_matches("std.").to_string(),
+                module.clone(),
+            ),
+        };
+        module
+    }
+
+    fn import_std(&self, name: &str) -> Result<Import, RuntimeError> {
+        if let Some(module) = self.std_modules.get(name) {
+            return Ok(Import::Loaded(module.clone()));
+        }
+
+        let (src, natives) = match stdlib::module(name) {
+            Some(module) => module,
+            None => {
+                return Err(RuntimeError::new(&format!(
+                    "Unknown standard module `std.{}`",
+                    name
+                )))
+            }
+        };
+
+        let env = Rc::new(RefCell::new(Env::new()));
+        for (name, native) in natives.into_functions() {
+            env.borrow_mut().set(&name, native);
+            env.borrow_mut().export(&name);
+        }
+
+        Ok(Import::Load(Load {
+            env,
+            file: None,
+            src: src.to_string(),
+            path: format!("std.{}", name),
+            name: format!("std.{}", name),
+        }))
+    }
+
+    fn import_file(&self, path: &str, permissions: &Permissions) -> Result<Import, RuntimeError> {
+        let cannot_import = |err: std::io::Error| {
+            RuntimeError::new(&format!("Cannot import \"{}\": {}", path, err))
+        };
+
+        let relative = Path::new(path);
+        let resolved = match self.files.last().and_then(|file| file.parent()) {
+            Some(dir) => dir.join(relative),
+            None => relative.to_path_buf(),
+        };
+        permissions.check_read(&resolved)?;
+        let canonical = resolved.canonicalize().map_err(cannot_import)?;
+
+        if let Some(module) = self.modules.get(&canonical) {
+            return Ok(Import::Loaded(module.clone()));
+        }
+
+        if let Some(start) = self.files.iter().position(|file| *file == canonical) {
+            let cycle: Vec<String> = self.files[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|file| file.display().to_string())
+                .collect();
+            return Err(RuntimeError::new(&format!(
+                "Import cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+
+        let src = std::fs::read_to_string(&canonical).map_err(cannot_import)?;
+
+        // Modules get a fresh global scope, natives stay visible
+        Ok(Import::Load(Load {
+            env: Rc::new(RefCell::new(Env::new())),
+            file: Some(canonical),
+            src,
+            path: path.to_string(),
+            name: format!("\"{}\"", path),
+        }))
+    }
+}
@@ -1,84 +1,397 @@
-use std::io::{stdout, Write};
+use std::io::{self, stdout, Write};
 
-use crate::{ast::Program, eval::Evaluator};
+use monki::ast::Program;
+use monki::eval::{MAX_DEPTH, STACK_PER_CALL};
+use monki::lexer::Lexer;
+use monki::object::Object;
+use monki::printer;
+use monki::{dump, json};
+use monki::{Error, Interpreter, Permissions, RuntimeError};
 
-mod ast;
-mod builtin;
-mod env;
-mod eval;
-mod lexer;
-mod object;
-mod parser;
+// Native stack for parsing, compiling and running besides the nested calls.
+// Passes over the tree recurse as deep as it goes, the parser's
+// `MAX_NESTING` keeps that well within it.
+const STACK_SIZE: usize = 16 << 20;
+
+// Exit status after stdout was closed, like for a process that writing to a
+// closed pipe killed
+const CLOSED_PIPE: i32 = 141;
+
+const USAGE: &str = "\
+Usage: monki [flags] [run] <file> [args...]  Run a script, args go to its `args`
+       monki [flags] -e <code> [args...]     Run code given on the command line
+       monki [flags] [repl]                  Start an interactive session
+       monki check <file>                    Report syntax and name errors
+       monki tokens [--json] <file>          Print the tokens of a script
+       monki ast [--json] <file>             Print the syntax tree of a script
+       monki fmt <file>                      Print a script in canonical layout
+
+Flags:
+  --vm                 Run on the bytecode VM
+  --max-depth=N        Limit nested function calls, 1000 by default
+  --sandbox            Only allow the standard streams
+  --allow-read=DIRS    Allow reading files under the comma separated DIRS
+  --allow-write=DIRS   Allow writing files under the comma separated DIRS
+  --allow-env          Allow reading environment variables
+  --allow-clock        Allow reading the clock and random numbers
+";
+
+const SUBCOMMANDS: &[&str] = &["run", "repl", "check", "tokens", "ast", "fmt", "-e"];
+
+enum Command {
+    Run(String),
+    Eval(String),
+    Repl,
+    Check(String),
+    Tokens(String),
+    Ast(String),
+    Fmt(String),
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    // Flags come before the file or code, everything after it is for the script
+    let mut args = std::env::args().skip(1);
+    let mut flags = Vec::new();
+    let mut subcommand = None;
+    let mut target = None;
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            std::process::exit(output(USAGE));
+        } else if arg.starts_with("--") {
+            flags.push(arg);
+        } else if subcommand.is_none() && SUBCOMMANDS.contains(&arg.as_str()) {
+            subcommand = Some(arg);
+            // The code after `-e` may start with `--` as well
+            if subcommand.as_deref() == Some("-e") {
+                target = args.next();
+                break;
+            }
+        } else {
+            target = Some(arg);
+            break;
+        }
+    }
+    let args: Vec<String> = args.collect();
 
-    if args.len() > 1 {
-        run_file(&args[1]);
-    } else {
-        repl();
+    let command = match (subcommand.as_deref(), target) {
+        (None | Some("repl"), None) => Command::Repl,
+        (None | Some("run"), Some(file)) => Command::Run(file),
+        (Some("-e"), Some(code)) => Command::Eval(code),
+        (Some("check"), Some(file)) => Command::Check(file),
+        (Some("tokens"), Some(file)) => Command::Tokens(file),
+        (Some("ast"), Some(file)) => Command::Ast(file),
+        (Some("fmt"), Some(file)) => Command::Fmt(file),
+        _ => {
+            eprint!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    if !flags.iter().all(|flag| is_known_flag(flag)) {
+        eprint!("{}", USAGE);
+        std::process::exit(2);
     }
+
+    // The tools only read the script, their flags may also follow the file
+    let json = flags.iter().chain(&args).any(|arg| arg == "--json");
+    let code = match command {
+        Command::Check(file) => spawn(STACK_SIZE, move || check(&file)),
+        Command::Tokens(file) => spawn(STACK_SIZE, move || tokens(&file, json)),
+        Command::Ast(file) => spawn(STACK_SIZE, move || ast(&file, json)),
+        Command::Fmt(file) => spawn(STACK_SIZE, move || fmt(&file)),
+        command => run(command, &flags, args),
+    };
+
+    // Scripts may leave output without a line ending in the buffer
+    let code = match stdout().flush() {
+        Ok(()) => code,
+        Err(err) => write_error(err),
+    };
+    std::process::exit(code);
 }
 
-fn run_file(file: &String) {
-    let input = std::fs::read_to_string(file).expect("SOmething went wrong when opning the file");
+/// Runs a script, inline code or the REPL and returns the exit status
+fn run(command: Command, flags: &[String], args: Vec<String>) -> i32 {
+    // `--vm` runs scripts on the bytecode VM instead of the tree-walking evaluator
+    let vm = flags.iter().any(|arg| arg == "--vm");
 
-    let mut l = lexer::Lexer::new(input);
-    let tokens = l.gen_tokens();
+    // `--max-depth=N` limits nested function calls
+    let max_depth = match flags
+        .iter()
+        .find_map(|arg| arg.strip_prefix("--max-depth="))
+    {
+        Some(depth) => match depth.parse::<usize>() {
+            Ok(depth) => depth,
+            Err(_) => {
+                eprintln!("Error: --max-depth must be a number, got {:?}", depth);
+                return 2;
+            }
+        },
+        None => MAX_DEPTH,
+    };
 
-    let mut parser = parser::Parser::new(tokens);
-    let program: Option<Program> = parser.parse_program();
+    // `--sandbox` and the `--allow-*` flags only let scripts use the
+    // standard streams and do what is allowed
+    let permissions = sandbox(flags);
 
-    if let Some(program) = program {
-        let mut evaluator = Evaluator::new();
+    // The evaluator nests calls on the native stack. The VM keeps script
+    // calls on its own stack, it only nests the passes over the tree, which
+    // the parser keeps within `MAX_NESTING`, and calls made by natives,
+    // which are limited whatever the depth.
+    let stack_size = match vm {
+        true => STACK_SIZE,
+        false => max_depth
+            .saturating_add(1)
+            .saturating_mul(STACK_PER_CALL)
+            .saturating_add(STACK_SIZE),
+    };
+    spawn(stack_size, move || {
+        let mut interpreter = if vm {
+            Interpreter::with_vm()
+        } else {
+            Interpreter::new()
+        };
+        interpreter.set_max_depth(max_depth);
+        if let Some(permissions) = permissions {
+            interpreter.set_permissions(permissions);
+        }
+        let args = args.into_iter().map(Object::String).collect();
+        interpreter.set_global("args", Object::Array(args));
+
+        match command {
+            Command::Run(file) => match interpreter.eval_file(&file) {
+                Err(Error::Io(err)) => {
+                    eprintln!("Error: cannot read {}: {}", file, err);
+                    1
+                }
+                result => exit_status(result),
+            },
+            Command::Eval(code) => exit_status(interpreter.eval_str(&code)),
+            _ => repl(interpreter),
+        }
+    })
+}
 
-        if let Some(result) = evaluator.eval(&program) {
-            match result {
-                object::Object::Null => (),
-                object::Object::Error(msg) => println!("Error: {}", msg),
-                _ => (),
-            };
+/// Runs `f` on a thread with `stack_size` bytes of native stack and returns
+/// its exit status
+fn spawn(stack_size: usize, f: impl FnOnce() -> i32 + Send + 'static) -> i32 {
+    let thread = std::thread::Builder::new().stack_size(stack_size).spawn(f);
+    match thread {
+        Ok(thread) => thread.join().unwrap_or(101),
+        Err(err) => {
+            eprintln!("Error: cannot start the interpreter: {}", err);
+            2
         }
     }
 }
 
-fn repl() {
-    loop {
-        print!(">> ");
-        let mut input = String::new();
-        stdout().flush().unwrap();
+fn sandbox(args: &[String]) -> Option<Permissions> {
+    let mut permissions = Permissions::none()
+        .allow_stdin()
+        .allow_stdout()
+        .allow_stderr();
+    let mut sandbox = false;
 
-        std::io::stdin().read_line(&mut input).unwrap();
-        if input == "exit\n" {
-            break;
+    for arg in args.iter().filter(|arg| is_sandbox_flag(arg)) {
+        sandbox = true;
+        permissions = match arg.split_once('=') {
+            // Directories are separated by commas
+            Some(("--allow-read", dirs)) => {
+                dirs.split(',').fold(permissions, Permissions::allow_read)
+            }
+            Some(("--allow-write", dirs)) => {
+                dirs.split(',').fold(permissions, Permissions::allow_write)
+            }
+            _ if arg == "--allow-env" => permissions.allow_env(),
+            _ if arg == "--allow-clock" => permissions.allow_clock(),
+            _ => permissions,
+        };
+    }
+
+    sandbox.then_some(permissions)
+}
+
+fn is_known_flag(arg: &str) -> bool {
+    matches!(arg, "--vm" | "--json") || arg.starts_with("--max-depth=") || is_sandbox_flag(arg)
+}
+
+fn is_sandbox_flag(arg: &str) -> bool {
+    matches!(arg, "--sandbox" | "--allow-env" | "--allow-clock")
+        || arg.starts_with("--allow-read=")
+        || arg.starts_with("--allow-write=")
+}
+
+/// The exit status for the result of a script
+fn exit_status(result: Result<Object, Error>) -> i32 {
+    match result {
+        Ok(_) => 0,
+        Err(Error::Runtime(RuntimeError::Exit(code))) => code,
+        Err(Error::Runtime(RuntimeError::StreamClosed(_))) => CLOSED_PIPE,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            1
+        }
+    }
+}
+
+/// Reports the syntax errors and undefined names in `file`, without running it
+fn check(file: &str) -> i32 {
+    let src = match read(file) {
+        Ok(src) => src,
+        Err(code) => return code,
+    };
+
+    // Scripts can use the `args` the command line gives them
+    let mut interpreter = Interpreter::new();
+    interpreter.set_global("args", Object::Array(Vec::new()));
+    match interpreter.parse(&src) {
+        Ok(_) => 0,
+        Err(err) => report(file, err),
+    }
+}
+
+/// Prints the tokens of `file`, one per line or as a JSON array
+fn tokens(file: &str, json: bool) -> i32 {
+    let src = match read(file) {
+        Ok(src) => src,
+        Err(code) => return code,
+    };
+
+    let tokens = Lexer::new(src).gen_tokens();
+    if json {
+        return print_json(&dump::tokens(&tokens));
+    }
+    let lines: String = tokens
+        .iter()
+        .map(|token| format!("{:?}\n", token))
+        .collect();
+    output(&lines)
+}
+
+/// Prints the syntax tree of `file`, one top level statement per line or
+/// as a JSON array of statements
+fn ast(file: &str, json: bool) -> i32 {
+    let program = match read(file).and_then(|src| parse(file, &src)) {
+        Ok(program) => program,
+        Err(code) => return code,
+    };
+
+    if json {
+        return print_json(&dump::program(&program));
+    }
+    let lines: String = program
+        .iter()
+        .map(|statement| format!("{:?}\n", statement))
+        .collect();
+    output(&lines)
+}
+
+fn print_json(obj: &Object) -> i32 {
+    match json::stringify(obj, None) {
+        Ok(json) => output(&(json + "\n")),
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            1
         }
+    }
+}
 
-        let mut l = lexer::Lexer::new(input);
-        let tokens = l.gen_tokens();
+/// Prints `file` in the canonical layout of [`monki::printer`]
+fn fmt(file: &str) -> i32 {
+    let src = match read(file) {
+        Ok(src) => src,
+        Err(code) => return code,
+    };
+    let program = match parse(file, &src) {
+        Ok(program) => program,
+        Err(code) => return code,
+    };
 
-        // Lexer output
-        // for token in &tokens {
-        //     println!("{:?}", token);
-        // }
+    // The lexer skips a `#!` line, keep it
+    let mut formatted = String::new();
+    if let Some(shebang) = src.lines().next().filter(|line| line.starts_with("#!")) {
+        formatted.push_str(shebang);
+        formatted.push('\n');
+    }
+    formatted.push_str(&printer::print_source(&program, &src));
+    output(&formatted)
+}
 
-        let mut parser = parser::Parser::new(tokens);
-        let program: Option<Program> = parser.parse_program();
+/// Writes `text` to stdout and returns the exit status
+fn output(text: &str) -> i32 {
+    let mut stdout = stdout().lock();
+    match stdout
+        .write_all(text.as_bytes())
+        .and_then(|_| stdout.flush())
+    {
+        Ok(()) => 0,
+        Err(err) => write_error(err),
+    }
+}
 
-        if let Some(program) = program {
-            // Parser output
-            // for stmt in &program {
-            //     println!("AST {:?}", stmt);
-            // }
+/// Reports a failed write to stdout and returns the exit status for it,
+/// output stops quietly once stdout is closed
+fn write_error(err: io::Error) -> i32 {
+    if err.kind() == io::ErrorKind::BrokenPipe {
+        return CLOSED_PIPE;
+    }
 
-            let mut evaluator = Evaluator::new();
+    eprintln!("Error: cannot write to stdout: {}", err);
+    1
+}
 
-            if let Some(result) = evaluator.eval(&program) {
-                match result {
-                    object::Object::Null => println!("null"),
-                    object::Object::Error(msg) => println!("Error: {}", msg),
-                    _ => (),
-                };
+fn read(file: &str) -> Result<String, i32> {
+    std::fs::read_to_string(file).map_err(|err| {
+        eprintln!("Error: cannot read {}: {}", file, err);
+        1
+    })
+}
+
+fn parse(file: &str, src: &str) -> Result<Program, i32> {
+    monki::parse(src).map_err(|err| report(file, err))
+}
+
+/// Prints `err` to stderr, syntax errors as `file:line:col: message` like
+/// compilers do, and returns the exit status for it
+fn report(file: &str, err: Error) -> i32 {
+    match err {
+        Error::Syntax(errors) => {
+            for error in errors {
+                eprintln!("{}:{}", file, error);
             }
         }
+        err => eprintln!("Error: {}", err),
+    }
+    1
+}
+
+fn repl(mut interpreter: Interpreter) -> i32 {
+    let mut stdout = stdout();
+    loop {
+        if let Err(err) = write!(stdout, ">> ").and_then(|_| stdout.flush()) {
+            return write_error(err);
+        }
+
+        // Stop at end of input as well
+        let mut input = String::new();
+        match io::stdin().read_line(&mut input) {
+            Ok(0) => return 0,
+            Ok(_) if input == "exit\n" => return 0,
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("Error: cannot read from stdin: {}", err);
+                return 1;
+            }
+        }
+
+        let written = match interpreter.eval_str(&input) {
+            Ok(Object::Null) => writeln!(stdout, "null"),
+            Ok(_) => Ok(()),
+            Err(Error::Runtime(RuntimeError::Exit(code))) => return code,
+            Err(Error::Runtime(RuntimeError::StreamClosed(_))) => return CLOSED_PIPE,
+            Err(err) => writeln!(stdout, "Error: {}", err),
+        };
+        if let Err(err) = written {
+            return write_error(err);
+        }
     }
 }
@@ -0,0 +1,356 @@
+//! Functions implemented in Rust and callable from scripts.
+//!
+//! A native receives a [`Ctx`] for calling back into the evaluator and the
+//! evaluated [`Args`]. Plain Rust functions whose arguments implement
+//! [`FromObject`] and whose result implements [`ToObject`] can be turned
+//! into natives directly with [`IntoNative`], argument count and type
+//! mismatches are then reported as [`RuntimeError`]s.
+//!
+//! Host values that scripts should see as opaque handles implement
+//! [`NativeObject`] and are wrapped in [`Object::Native`].
+
+use std::any::Any;
+use std::rc::Rc;
+
+use crate::budget::Budget;
+use crate::convert::{FromObject, ToObject};
+use crate::error::RuntimeError;
+use crate::object::Object;
+use crate::permissions::Permissions;
+
+pub type NativeFn = dyn Fn(&mut Ctx<'_>, Args) -> Result<Object, RuntimeError>;
+
+pub struct NativeFunction {
+    pub name: String,
+    func: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<F>(name: &str, func: F) -> Self
+    where
+        F: Fn(&mut Ctx<'_>, Args) -> Result<Object, RuntimeError> + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            func: Box::new(func),
+        }
+    }
+
+    pub fn call(&self, ctx: &mut Ctx<'_>, args: Vec<Object>) -> Result<Object, RuntimeError> {
+        (self.func)(ctx, Args::new(self.name.clone(), args))
+    }
+}
+
+// Natives are only equal to themselves
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for NativeFunction {}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+/// Wraps a raw native into a function object
+pub fn native<F>(name: &str, func: F) -> Object
+where
+    F: Fn(&mut Ctx<'_>, Args) -> Result<Object, RuntimeError> + 'static,
+{
+    Object::BuiltinFunction(Rc::new(NativeFunction::new(name, func)))
+}
+
+/// Wraps a typed Rust function into a function object
+pub fn function<F, Marker>(name: &str, func: F) -> Object
+where
+    F: IntoNative<Marker>,
+{
+    Object::BuiltinFunction(Rc::new(func.into_native(name)))
+}
+
+/// Whatever runs scripts, the [`crate::eval::Evaluator`] or the [`crate::vm::Vm`]
+pub(crate) trait Caller {
+    fn call(&mut self, function: &Object, args: Vec<Object>) -> Result<Object, RuntimeError>;
+
+    /// Global variable or native called `name`
+    fn lookup(&self, name: &str) -> Option<Object>;
+
+    fn budget(&self) -> &Budget;
+
+    fn permissions(&self) -> &Permissions;
+}
+
+/// Access to the evaluator from inside a native
+pub struct Ctx<'a> {
+    pub(crate) caller: &'a mut dyn Caller,
+}
+
+impl Ctx<'_> {
+    /// Calls a script or native function object
+    pub fn call(&mut self, function: &Object, args: Vec<Object>) -> Result<Object, RuntimeError> {
+        self.caller.call(function, args)
+    }
+
+    /// Fails with [`RuntimeError::ResourceExhausted`] if the host does not
+    /// allow a value of `bytes`, for natives to check before building one
+    pub fn reserve(&self, bytes: usize) -> Result<(), RuntimeError> {
+        self.caller.budget().reserve(bytes)
+    }
+
+    /// What the script is allowed to do, natives with effects on the host
+    /// check it first
+    pub fn permissions(&self) -> &Permissions {
+        self.caller.permissions()
+    }
+}
+
+/// The evaluated arguments of a native call
+pub struct Args {
+    function: String,
+    values: Vec<Object>,
+}
+
+impl Args {
+    pub(crate) fn new(function: String, values: Vec<Object>) -> Self {
+        Self { function, values }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Object> {
+        self.values.get(index)
+    }
+
+    pub fn expect_len(&self, expected: usize) -> Result<(), RuntimeError> {
+        if self.values.len() != expected {
+            return Err(self.arity_error(&expected.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn expect_min_len(&self, expected: usize) -> Result<(), RuntimeError> {
+        if self.values.len() < expected {
+            return Err(self.arity_error(&format!("{} or more", expected)));
+        }
+        Ok(())
+    }
+
+    pub fn arity_error(&self, expected: &str) -> RuntimeError {
+        RuntimeError::Arity {
+            function: self.function.clone(),
+            expected: expected.to_string(),
+            got: self.values.len(),
+        }
+    }
+
+    pub fn type_error(&self, index: usize, expected: &str) -> RuntimeError {
+        RuntimeError::Type {
+            function: self.function.clone(),
+            position: index + 1,
+            expected: expected.to_string(),
+            got: self.values.get(index).map_or("Null", Object::type_name),
+        }
+    }
+
+    /// Converts the argument at `index`
+    pub fn arg<T: FromObject>(&self, index: usize) -> Result<T, RuntimeError> {
+        let value = self.values.get(index).cloned().unwrap_or(Object::Null);
+
+        T::from_object(value).map_err(|err| {
+            if err.path.is_empty() {
+                self.type_error(index, &err.expected)
+            } else {
+                RuntimeError::new(&format!(
+                    "Argument {} to `{}`: {}",
+                    index + 1,
+                    self.function,
+                    err
+                ))
+            }
+        })
+    }
+
+    pub fn into_vec(self) -> Vec<Object> {
+        self.values
+    }
+}
+
+impl std::ops::Index<usize> for Args {
+    type Output = Object;
+
+    fn index(&self, index: usize) -> &Object {
+        &self.values[index]
+    }
+}
+
+/// Return values of typed natives, either a value or a `Result` of one
+pub trait NativeResult {
+    fn into_result(self) -> Result<Object, RuntimeError>;
+}
+
+impl<T: ToObject> NativeResult for T {
+    fn into_result(self) -> Result<Object, RuntimeError> {
+        Ok(self.to_object())
+    }
+}
+
+impl<T: ToObject> NativeResult for Result<T, RuntimeError> {
+    fn into_result(self) -> Result<Object, RuntimeError> {
+        self.map(|value| value.to_object())
+    }
+}
+
+/// Rust functions that can be registered as natives, `Marker` is the tuple of argument types
+pub trait IntoNative<Marker> {
+    fn into_native(self, name: &str) -> NativeFunction;
+}
+
+macro_rules! impl_into_native {
+    ($count:expr $(, $arg:ident $index:tt)*) => {
+        impl<F, R, $($arg,)*> IntoNative<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: NativeResult,
+            $($arg: FromObject,)*
+        {
+            #[allow(unused_variables)]
+            fn into_native(self, name: &str) -> NativeFunction {
+                NativeFunction::new(name, move |_ctx, args| {
+                    args.expect_len($count)?;
+                    (self)($(args.arg::<$arg>($index)?),*).into_result()
+                })
+            }
+        }
+    };
+}
+
+impl_into_native!(0);
+impl_into_native!(1, A 0);
+impl_into_native!(2, A 0, B 1);
+impl_into_native!(3, A 0, B 1, C 2);
+impl_into_native!(4, A 0, B 1, C 2, D 3);
+impl_into_native!(5, A 0, B 1, C 2, D 3, E 4);
+
+/// A host value exposed to scripts, e.g. `row.name`, `row.name = "x"`
+/// and `row.save()`.
+///
+/// Objects are shared behind an `Rc`, so `set` and `call_method` take
+/// `&self` and implementations use interior mutability for state.
+pub trait NativeObject: std::fmt::Debug + std::fmt::Display + Any {
+    /// Name used for the object in error messages
+    fn type_name(&self) -> &'static str {
+        "Native"
+    }
+
+    /// Value of the property `name`, `None` if there is no such property
+    fn get(&self, name: &str) -> Option<Object> {
+        let _ = name;
+        None
+    }
+
+    fn set(&self, name: &str, value: Object) -> Result<(), RuntimeError> {
+        let _ = value;
+        Err(RuntimeError::new(&format!(
+            "Cannot set property `{}` on {}",
+            name,
+            self.type_name()
+        )))
+    }
+
+    /// Calls the method `name`, `args` reports errors as `Type.name`
+    fn call_method(
+        &self,
+        ctx: &mut Ctx<'_>,
+        name: &str,
+        args: Args,
+    ) -> Result<Object, RuntimeError> {
+        let _ = (ctx, args);
+        Err(RuntimeError::new(&format!(
+            "No method `{}` on {}",
+            name,
+            self.type_name()
+        )))
+    }
+
+    /// Script equality, by default objects are only equal to themselves
+    fn equals(&self, other: &dyn NativeObject) -> bool {
+        std::ptr::addr_eq(self as *const Self, other as *const dyn NativeObject)
+    }
+}
+
+impl dyn NativeObject {
+    pub fn downcast_ref<T: NativeObject>(&self) -> Option<&T> {
+        (self as &dyn Any).downcast_ref()
+    }
+}
+
+impl PartialEq for dyn NativeObject {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other)
+    }
+}
+
+impl Eq for dyn NativeObject {}
+
+/// A named group of natives, registered as a hash of functions
+pub struct NativeModule {
+    name: String,
+    functions: Vec<(String, Object)>,
+}
+
+impl NativeModule {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            functions: Vec::new(),
+        }
+    }
+
+    pub fn native<F>(mut self, name: &str, func: F) -> Self
+    where
+        F: Fn(&mut Ctx<'_>, Args) -> Result<Object, RuntimeError> + 'static,
+    {
+        let qualified = format!("{}.{}", self.name, name);
+        self.functions
+            .push((name.to_string(), native(&qualified, func)));
+        self
+    }
+
+    pub fn function<F, Marker>(mut self, name: &str, func: F) -> Self
+    where
+        F: IntoNative<Marker>,
+    {
+        let qualified = format!("{}.{}", self.name, name);
+        self.functions
+            .push((name.to_string(), function(&qualified, func)));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn into_object(self) -> Object {
+        Object::Hash(
+            self.functions
+                .into_iter()
+                .map(|(name, function)| (Object::String(name), function))
+                .collect(),
+        )
+    }
+
+    /// The functions with their unqualified names
+    pub fn into_functions(self) -> Vec<(String, Object)> {
+        self.functions
+    }
+}
@@ -3,6 +3,8 @@ use std::rc::Rc;
 
 use crate::ast::{BlockStatement, Identifier};
 use crate::env::Env;
+use crate::native::{NativeFunction, NativeObject};
+use crate::vm::Closure;
 
 #[derive(PartialEq, Debug, Clone, Eq)]
 pub enum Object {
@@ -14,13 +16,56 @@ pub enum Object {
     Empty,
     Function {
         parameters: Vec<Identifier>,
-        body: BlockStatement,
+        body: Rc<BlockStatement>, // shared by all copies of the function
         env: Rc<RefCell<Env>>,
+        locals: usize, // see `Expression::FunctionLiteral`
     },
     String(String),
-    BuiltinFunction(fn(Vec<Object>) -> Object),
+    BuiltinFunction(Rc<NativeFunction>),
     Array(Vec<Object>),
     Hash(Vec<(Object, Object)>),
+    Native(Rc<dyn NativeObject>),
+    Module {
+        path: String, // as written in the import
+        env: Rc<RefCell<Env>>,
+    },
+    /// A function compiled for the [`crate::vm::Vm`]
+    Closure(Rc<Closure>),
+}
+
+impl Object {
+    /// Name of the variant, used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) => "Integer",
+            Object::Boolean(_) => "Boolean",
+            Object::Null => "Null",
+            Object::Return(obj) => obj.type_name(),
+            Object::Error(_) => "Error",
+            Object::Empty => "Empty",
+            Object::Function { .. } => "Function",
+            Object::String(_) => "String",
+            Object::BuiltinFunction(_) => "BuiltinFunction",
+            Object::Array(_) => "Array",
+            Object::Hash(_) => "Hash",
+            Object::Native(native) => native.type_name(),
+            Object::Module { .. } => "Module",
+            Object::Closure(_) => "Function",
+        }
+    }
+
+    /// Approximate bytes taken by the value, nested values included
+    pub fn size(&self) -> usize {
+        let nested = match self {
+            Object::Return(obj) => obj.size(),
+            Object::Error(msg) => msg.len(),
+            Object::String(string) => string.len(),
+            Object::Array(array) => array.iter().map(Object::size).sum(),
+            Object::Hash(pairs) => pairs.iter().map(|(k, v)| k.size() + v.size()).sum(),
+            _ => 0,
+        };
+        std::mem::size_of::<Object>() + nested
+    }
 }
 
 impl std::fmt::Display for Object {
@@ -33,9 +78,7 @@ impl std::fmt::Display for Object {
             Object::Error(msg) => write!(f, "{}", msg),
             Object::Empty => write!(f, ""),
             Object::Function {
-                parameters,
-                body,
-                env: _,
+                parameters, body, ..
             } => {
                 let mut params = String::new();
                 for param in parameters {
@@ -46,7 +89,7 @@ impl std::fmt::Display for Object {
             Object::String(string) => {
                 write!(f, "{}", string)
             }
-            Object::BuiltinFunction(_) => write!(f, "builtin function"),
+            Object::BuiltinFunction(native) => write!(f, "builtin function {}", native.name),
             Object::Array(array) => {
                 write!(f, "[")?;
                 for (i, obj) in array.iter().enumerate() {
@@ -69,6 +112,9 @@ impl std::fmt::Display for Object {
                 }
                 write!(f, "}}")
             }
+            Object::Native(native) => write!(f, "{}", native),
+            Object::Module { path, .. } => write!(f, "module \"{}\"", path),
+            Object::Closure(closure) => write!(f, "{}", closure.function.display),
         }
     }
 }
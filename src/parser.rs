@@ -1,12 +1,16 @@
-use crate::ast::{BlockStatement, Expression, Identifier, Literal, Program, Statement};
-use crate::lexer::{KeywordType, Token, TokenType};
+use crate::ast::{BlockStatement, Expression, Identifier, ImportPath, Literal, Program, Statement};
+use crate::lexer::{self, KeywordType, StringPart, Token, TokenType};
 
 // Partial ord allows for < >, etc comparisons
 #[derive(PartialOrd, PartialEq)]
-enum Precedence {
+pub(crate) enum Precedence {
     Lowest,
     Equals,      // ==
     LessGreater, // > or <
+    BitOr,       // |
+    BitXor,      // ^
+    BitAnd,      // &
+    Shift,       // << or >>
     Sum,         // +
     Product,     // *
     Prefix,      // -X or !X
@@ -15,20 +19,31 @@ enum Precedence {
     Dot,         // x.y
 }
 
+/// How deeply expressions may nest, including chains of operators. Every
+/// pass over the tree recurses as deep as it goes, so deeper source is a
+/// syntax error rather than a native stack overflow later on.
+pub const MAX_NESTING: usize = 1000;
+
 pub struct Parser {
     pub current_token: Token,
     pub peek_token: Token,
     pub tokens: Vec<Token>,
     pub index: usize,
+    pub errors: Vec<String>,
+    // Expressions being parsed, see `MAX_NESTING`
+    depth: usize,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
         Self {
             current_token: tokens[0].clone(),
-            peek_token: tokens[1].clone(),
+            // An empty source only has the Eof token
+            peek_token: tokens.get(1).unwrap_or(&tokens[0]).clone(),
             tokens,
             index: 0,
+            errors: Vec::new(),
+            depth: 0,
         }
     }
 
@@ -44,6 +59,10 @@ impl Parser {
             self.next_token();
         }
 
+        if !self.errors.is_empty() {
+            return None;
+        }
+
         Some(program)
     }
 
@@ -51,6 +70,8 @@ impl Parser {
         match self.current_token.ttype {
             TokenType::Keyword(KeywordType::Let) => self.parse_let_statement(),
             TokenType::Keyword(KeywordType::Return) => self.parse_return_statement(),
+            TokenType::Keyword(KeywordType::Import) => self.parse_import_statement(),
+            TokenType::Keyword(KeywordType::Export) => self.parse_export_statement(),
             TokenType::Ident => {
                 if self.peek_token.ttype == TokenType::Assign {
                     self.parse_reassign_statement()
@@ -58,42 +79,69 @@ impl Parser {
                     self.parse_expression_statement()
                 }
             }
+            // An empty statement
+            TokenType::Semicolon => None,
             _ => self.parse_expression_statement(),
         }
     }
 
     fn parse_reassign_statement(&mut self) -> Option<Statement> {
-        let name = Identifier {
-            token: self.current_token.clone(),
-            value: self.current_token.literal.clone(),
-        };
+        let name = Identifier::new(self.current_token.clone());
 
         if !self.expect_peek(TokenType::Assign) {
             return None;
         }
+        let token = self.current_token.clone();
 
         self.next_token();
 
-        let value = self.parse_expression(Precedence::Lowest).unwrap();
+        let value = self.parse_expression(Precedence::Lowest)?;
 
         if self.peek_token.ttype == TokenType::Semicolon {
             self.next_token();
         }
 
-        Some(Statement::ReAssign {
-            token: self.current_token.clone(),
-            name,
-            value,
-        })
+        Some(Statement::ReAssign { token, name, value })
     }
 
     fn parse_expression(&mut self, precedence: Precedence) -> Option<Expression> {
+        let depth = self.depth;
+        let expr = self.parse_nested_expression(precedence);
+        self.depth = depth;
+        expr
+    }
+
+    // Counts one level of nesting, failing past `MAX_NESTING`
+    fn nest(&mut self) -> bool {
+        self.depth += 1;
+        if self.depth <= MAX_NESTING {
+            return true;
+        }
+
+        self.errors.push(format!(
+            "{}: Expression nested too deeply, more than {} levels",
+            self.current_token.position, MAX_NESTING
+        ));
+
+        // The rest would nest as deeply again, stop on the Eof token
+        self.index = self.tokens.len() - 1;
+        self.current_token = self.tokens[self.index].clone();
+        self.peek_token = self.current_token.clone();
+        false
+    }
+
+    fn parse_nested_expression(&mut self, precedence: Precedence) -> Option<Expression> {
+        if !self.nest() {
+            return None;
+        }
+
         // Prefix
         let mut left = match self.current_token.ttype {
             TokenType::Ident => self.parse_identifier(),
             TokenType::String => self.parse_string_literal(),
+            TokenType::Interpolated(_) => self.parse_interpolated_string(),
             TokenType::Number => self.parse_integer_literal(),
-            TokenType::Bang | TokenType::Sub => self.parse_prefix_expression(),
+            TokenType::Bang | TokenType::Sub | TokenType::Tilde => self.parse_prefix_expression(),
             TokenType::Keyword(KeywordType::True) | TokenType::Keyword(KeywordType::False) => {
                 self.parse_boolean()
             }
@@ -102,11 +150,25 @@ impl Parser {
             TokenType::LBracket => self.parse_array_literal(),
             TokenType::Keyword(KeywordType::If) => self.parse_if_expr(),
             TokenType::Keyword(KeywordType::Fn) => self.parse_fn_literal(),
-            _ => return None,
+            TokenType::Illegal => {
+                self.errors.push(format!(
+                    "{}: {}",
+                    self.current_token.position, self.current_token.literal
+                ));
+                return None;
+            }
+            _ => {
+                self.no_expression_error();
+                return None;
+            }
         };
 
         // Infix
         while self.peek_token.ttype != TokenType::Semicolon && precedence < self.peek_precedence() {
+            // Each operator puts the expression so far one level deeper
+            if !self.nest() {
+                return None;
+            }
             self.next_token();
 
             left = match self.current_token.ttype {
@@ -118,10 +180,15 @@ impl Parser {
                 | TokenType::Mul
                 | TokenType::NotEq
                 | TokenType::Eq
-                | TokenType::Sub => self.parse_infix_expression(left.unwrap()),
-                TokenType::LParen => self.parse_fn_call(left.unwrap()),
-                TokenType::LBracket => self.parse_index_expression(left.unwrap()),
-                TokenType::Period => self.parse_dot_notation(left.unwrap()),
+                | TokenType::BitAnd
+                | TokenType::BitOr
+                | TokenType::BitXor
+                | TokenType::ShiftLeft
+                | TokenType::ShiftRight
+                | TokenType::Sub => self.parse_infix_expression(left?),
+                TokenType::LParen => self.parse_fn_call(left?),
+                TokenType::LBracket => self.parse_index_expression(left?),
+                TokenType::Period => self.parse_dot_notation(left?),
                 _ => return left,
             };
         }
@@ -130,26 +197,24 @@ impl Parser {
     }
 
     fn parse_dot_notation(&mut self, left: Expression) -> Option<Expression> {
+        let token = self.current_token.clone();
+
         self.next_token();
 
-        let right = self.parse_expression(Precedence::Dot);
+        let right = self.parse_expression(Precedence::Dot)?;
 
-        if let Some(right) = right {
-            Some(Expression::DotNotation {
-                token: self.current_token.clone(),
-                left: Box::new(left),
-                right: Box::new(right),
-            })
-        } else {
-            None
-        }
+        Some(Expression::DotNotation {
+            token,
+            left: Box::new(left),
+            right: Box::new(right),
+        })
     }
 
     fn parse_fn_call(&mut self, function: Expression) -> Option<Expression> {
         Some(Expression::FunctionCall {
             token: self.current_token.clone(),
             function: Box::new(function),
-            arguments: self.parse_fn_arguments(),
+            arguments: self.parse_fn_arguments()?,
         })
     }
 
@@ -159,75 +224,78 @@ impl Parser {
         while self.peek_token.ttype != TokenType::RBrace {
             self.next_token();
 
-            let key = self.parse_expression(Precedence::Lowest).unwrap();
+            let key = self.parse_expression(Precedence::Lowest)?;
 
             if !self.expect_peek(TokenType::Colon) {
+                self.peek_error("`:` after the key");
                 return None;
             }
 
             self.next_token();
-            let value = self.parse_expression(Precedence::Lowest).unwrap();
+            let value = self.parse_expression(Precedence::Lowest)?;
 
             pairs.push((key, value));
 
             if self.peek_token.ttype != TokenType::RBrace && !self.expect_peek(TokenType::Comma) {
+                self.peek_error("`,` or `}` in hash literal");
                 return None;
             }
         }
-
-        if !self.expect_peek(TokenType::RBrace) {
-            return None;
-        }
+        self.next_token();
 
         Some(Expression::Literal(Literal::Hash(pairs)))
     }
 
     fn parse_index_expression(&mut self, left: Expression) -> Option<Expression> {
+        let token = self.current_token.clone();
+
         self.next_token();
 
-        let index = self.parse_expression(Precedence::Lowest);
+        let index = self.parse_expression(Precedence::Lowest)?;
 
         if !self.expect_peek(TokenType::RBracket) {
+            self.peek_error("`]` after the index");
             return None;
         }
 
         Some(Expression::IndexExpression {
-            token: self.current_token.clone(),
+            token,
             left: Box::new(left),
-            index: Box::new(index.unwrap()),
+            index: Box::new(index),
         })
     }
 
     fn parse_array_literal(&mut self) -> Option<Expression> {
         Some(Expression::Literal(Literal::Array(
-            self.parse_array_elements(),
+            self.parse_array_elements()?,
         )))
     }
 
-    fn parse_array_elements(&mut self) -> Vec<Expression> {
+    fn parse_array_elements(&mut self) -> Option<Vec<Expression>> {
         let mut elements = Vec::new();
 
         if self.peek_token.ttype == TokenType::RBracket {
             self.next_token();
-            return elements;
+            return Some(elements);
         }
 
         self.next_token();
 
-        elements.push(self.parse_expression(Precedence::Lowest).unwrap());
+        elements.push(self.parse_expression(Precedence::Lowest)?);
 
         while self.peek_token.ttype == TokenType::Comma {
             self.next_token();
             self.next_token();
 
-            elements.push(self.parse_expression(Precedence::Lowest).unwrap());
+            elements.push(self.parse_expression(Precedence::Lowest)?);
         }
 
         if !self.expect_peek(TokenType::RBracket) {
-            return Vec::new();
+            self.peek_error("`,` or `]` in array literal");
+            return None;
         }
 
-        elements
+        Some(elements)
     }
 
     fn parse_string_literal(&mut self) -> Option<Expression> {
@@ -236,42 +304,87 @@ impl Parser {
         )))
     }
 
-    fn parse_fn_arguments(&mut self) -> Vec<Expression> {
+    fn parse_interpolated_string(&mut self) -> Option<Expression> {
+        let TokenType::Interpolated(parts) = self.current_token.ttype.clone() else {
+            return None;
+        };
+
+        let mut expressions = Vec::new();
+
+        for part in parts {
+            match part {
+                StringPart::Literal(string) => {
+                    expressions.push(Expression::Literal(Literal::String(string)))
+                }
+                StringPart::Code(tokens) => {
+                    // `${}` has nothing to parse, only its end of input
+                    if tokens.len() < 2 {
+                        self.errors.push(format!(
+                            "{}: Empty interpolation `${{}}`",
+                            tokens[0].position
+                        ));
+                        return None;
+                    }
+
+                    let mut parser = Parser::new(tokens);
+                    let expr = parser.parse_expression(Precedence::Lowest);
+                    if expr.is_some() && !parser.expect_peek(TokenType::Eof) {
+                        parser.peek_error("`}` after the interpolated expression");
+                    }
+                    self.errors.append(&mut parser.errors);
+
+                    expressions.push(expr?);
+                }
+            }
+        }
+
+        Some(Expression::Literal(Literal::Interpolated(expressions)))
+    }
+
+    fn parse_fn_arguments(&mut self) -> Option<Vec<Expression>> {
         let mut args = Vec::new();
 
         if self.peek_token.ttype == TokenType::RParen {
             self.next_token();
-            return args;
+            return Some(args);
         }
 
         self.next_token();
 
-        args.push(self.parse_expression(Precedence::Lowest).unwrap());
+        args.push(self.parse_expression(Precedence::Lowest)?);
 
         while self.peek_token.ttype == TokenType::Comma {
             self.next_token();
             self.next_token();
 
-            args.push(self.parse_expression(Precedence::Lowest).unwrap());
+            args.push(self.parse_expression(Precedence::Lowest)?);
         }
 
         if !self.expect_peek(TokenType::RParen) {
-            return Vec::new();
+            self.peek_error("`,` or `)` in arguments");
+            return None;
         }
 
-        args
+        Some(args)
     }
 
     fn parse_fn_literal(&mut self) -> Option<Expression> {
         let token = self.current_token.clone();
 
+        self.parse_fn_rest(token)
+    }
+
+    // Parameters and body of a function, the current token is the one before `(`
+    fn parse_fn_rest(&mut self, token: Token) -> Option<Expression> {
         if !self.expect_peek(TokenType::LParen) {
+            self.peek_error("`(` before the parameters");
             return None;
         }
 
-        let parameters = self.parse_fn_parameters();
+        let parameters = self.parse_fn_parameters()?;
 
         if !self.expect_peek(TokenType::LBrace) {
+            self.peek_error("`{` before the function body");
             return None;
         }
 
@@ -281,52 +394,49 @@ impl Parser {
             token,
             parameters,
             body: Box::new(body),
+            locals: 0,
         })
     }
 
-    fn parse_fn_parameters(&mut self) -> Vec<Identifier> {
+    fn parse_fn_parameters(&mut self) -> Option<Vec<Identifier>> {
         let mut identifiers = Vec::new();
 
         if self.peek_token.ttype == TokenType::RParen {
             self.next_token();
-            return identifiers;
+            return Some(identifiers);
         }
 
-        self.next_token();
-
-        let ident = Identifier {
-            token: self.current_token.clone(),
-            value: self.current_token.literal.clone(),
-        };
+        loop {
+            if !self.expect_peek(TokenType::Ident) {
+                self.peek_error("a parameter name");
+                return None;
+            }
 
-        identifiers.push(ident);
-
-        while self.peek_token.ttype == TokenType::Comma {
-            self.next_token();
-            self.next_token();
-
-            let ident = Identifier {
-                token: self.current_token.clone(),
-                value: self.current_token.literal.clone(),
-            };
+            let ident = Identifier::new(self.current_token.clone());
 
             identifiers.push(ident);
+
+            if !self.expect_peek(TokenType::Comma) {
+                break;
+            }
         }
 
         if !self.expect_peek(TokenType::RParen) {
-            return Vec::new();
+            self.peek_error("`,` or `)` in parameters");
+            return None;
         }
 
-        identifiers
+        Some(identifiers)
     }
 
     fn parse_if_expr(&mut self) -> Option<Expression> {
         let token = self.current_token.clone();
 
         self.next_token();
-        let condition = self.parse_expression(Precedence::Lowest);
+        let condition = self.parse_expression(Precedence::Lowest)?;
 
         if !self.expect_peek(TokenType::LBrace) {
+            self.peek_error("`{` after the condition");
             return None;
         }
 
@@ -338,6 +448,7 @@ impl Parser {
             self.next_token();
 
             if !self.expect_peek(TokenType::LBrace) {
+                self.peek_error("`{` after `else`");
                 return None;
             }
 
@@ -346,7 +457,7 @@ impl Parser {
 
         Some(Expression::If {
             token,
-            condition: Box::new(condition.unwrap()),
+            condition: Box::new(condition),
             consequence: Box::new(consequence),
             alternative: alternative.map(Box::new),
         })
@@ -368,45 +479,52 @@ impl Parser {
             self.next_token();
         }
 
+        // The Eof token is also the peek token here
+        if self.current_token.ttype == TokenType::Eof {
+            self.peek_error("`}` at the end of the block");
+        }
+
         block
     }
 
     fn parse_group_expr(&mut self) -> Option<Expression> {
         self.next_token();
 
-        let expr = self.parse_expression(Precedence::Lowest);
+        let expr = self.parse_expression(Precedence::Lowest)?;
 
         if !self.expect_peek(TokenType::RParen) {
+            self.peek_error("`)`");
             return None;
         }
 
-        expr
+        Some(expr)
     }
 
     fn parse_infix_expression(&mut self, left: Expression) -> Option<Expression> {
+        let token = self.current_token.clone();
         let operator = self.current_token.literal.clone();
         let precedence = self.cur_precedence();
 
         self.next_token();
 
-        let right = self.parse_expression(precedence);
+        let right = self.parse_expression(precedence)?;
 
-        if let Some(right) = right {
-            Some(Expression::Infix {
-                token: self.current_token.clone(),
-                left: Box::new(left),
-                operator,
-                right: Box::new(right),
-            })
-        } else {
-            None
-        }
+        Some(Expression::Infix {
+            token,
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        })
     }
 
     fn token_precedence(&mut self, ttype: TokenType) -> Precedence {
         match ttype {
             TokenType::Assign | TokenType::NotEq | TokenType::Eq => Precedence::Equals,
             TokenType::Lt | TokenType::Gt => Precedence::LessGreater,
+            TokenType::BitOr => Precedence::BitOr,
+            TokenType::BitXor => Precedence::BitXor,
+            TokenType::BitAnd => Precedence::BitAnd,
+            TokenType::ShiftLeft | TokenType::ShiftRight => Precedence::Shift,
             TokenType::Add | TokenType::Sub => Precedence::Sum,
             TokenType::Div | TokenType::Mul => Precedence::Product,
             TokenType::LParen => Precedence::Call,
@@ -425,21 +543,18 @@ impl Parser {
     }
 
     fn parse_prefix_expression(&mut self) -> Option<Expression> {
+        let token = self.current_token.clone();
         let operator = self.current_token.literal.clone();
 
         self.next_token();
 
-        let right = self.parse_expression(Precedence::Prefix);
+        let right = self.parse_expression(Precedence::Prefix)?;
 
-        if let Some(right) = right {
-            Some(Expression::Prefix {
-                token: self.current_token.clone(),
-                operator,
-                right: Box::new(right),
-            })
-        } else {
-            None
-        }
+        Some(Expression::Prefix {
+            token,
+            operator,
+            right: Box::new(right),
+        })
     }
 
     fn parse_boolean(&mut self) -> Option<Expression> {
@@ -449,34 +564,28 @@ impl Parser {
     }
 
     fn parse_integer_literal(&mut self) -> Option<Expression> {
-        let int = self.current_token.literal.parse::<i64>().unwrap();
+        // The lexer only emits number tokens for literals in range
+        let int = lexer::parse_integer(&self.current_token.literal).ok()?;
         let lit = Expression::Literal(Literal::Integer(int));
 
         Some(lit)
     }
 
     fn parse_identifier(&mut self) -> Option<Expression> {
-        Some(Expression::Identifier(Identifier {
-            token: self.current_token.clone(),
-            value: self.current_token.literal.clone(),
-        }))
+        Some(Expression::Identifier(Identifier::new(
+            self.current_token.clone(),
+        )))
     }
 
     fn parse_expression_statement(&mut self) -> Option<Statement> {
+        let token = self.current_token.clone();
         let expr = self.parse_expression(Precedence::Lowest);
 
         if self.peek_token.ttype == TokenType::Semicolon {
             self.next_token();
         }
 
-        if let Some(expr) = expr {
-            Some(Statement::Expression {
-                token: self.current_token.clone(),
-                value: expr,
-            })
-        } else {
-            None
-        }
+        expr.map(|value| Statement::Expression { token, value })
     }
 
     fn parse_return_statement(&mut self) -> Option<Statement> {
@@ -484,7 +593,7 @@ impl Parser {
 
         self.next_token();
 
-        let value = self.parse_expression(Precedence::Lowest).unwrap();
+        let value = self.parse_expression(Precedence::Lowest)?;
 
         if self.peek_token.ttype == TokenType::Semicolon {
             self.next_token();
@@ -494,34 +603,212 @@ impl Parser {
     }
 
     fn parse_let_statement(&mut self) -> Option<Statement> {
+        let token = self.current_token.clone();
+
         if !self.expect_peek(TokenType::Ident) {
+            self.peek_error("a name after `let`");
             return None;
         }
 
-        let name = Identifier {
-            token: self.current_token.clone(),
-            value: self.current_token.literal.clone(),
-        };
+        let name = Identifier::new(self.current_token.clone());
 
         if !self.expect_peek(TokenType::Assign) {
+            self.peek_error("`=` after the name");
             return None;
         }
 
         self.next_token();
 
-        let value = self.parse_expression(Precedence::Lowest).unwrap();
+        let value = self.parse_expression(Precedence::Lowest)?;
 
         if self.peek_token.ttype == TokenType::Semicolon {
             self.next_token();
         }
 
-        Some(Statement::Let {
-            token: self.current_token.clone(),
-            name,
-            value,
+        Some(Statement::Let { token, name, value })
+    }
+
+    // import "path" as name / import std.name (as name)
+    fn parse_import_statement(&mut self) -> Option<Statement> {
+        let token = self.current_token.clone();
+
+        if self.peek_token.ttype == TokenType::LBrace {
+            return self.parse_import_names(token);
+        }
+
+        let path = self.parse_import_path("`import`")?;
+
+        let alias = if self.expect_peek(TokenType::Keyword(KeywordType::As)) {
+            if !self.expect_peek(TokenType::Ident) {
+                self.peek_error("a module name after `as`");
+                return None;
+            }
+            Identifier::new(self.current_token.clone())
+        } else if let ImportPath::Std(name) = &path {
+            // std modules are bound to their last name, std.math as math
+            Identifier {
+                token: self.current_token.clone(),
+                value: name.rsplit('.').next().unwrap_or(name).to_string(),
+                slot: None,
+            }
+        } else {
+            self.peek_error("`as` after the import path");
+            return None;
+        };
+
+        if self.peek_token.ttype == TokenType::Semicolon {
+            self.next_token();
+        }
+
+        Some(Statement::Import { token, path, alias })
+    }
+
+    // "path" or std.name, the current token ends up on the last part
+    fn parse_import_path(&mut self, after: &str) -> Option<ImportPath> {
+        if self.expect_peek(TokenType::String) {
+            return Some(ImportPath::File(self.current_token.literal.clone()));
+        }
+
+        if self.peek_token.ttype != TokenType::Ident || self.peek_token.literal != "std" {
+            self.peek_error(&format!("a string path or std module after {}", after));
+            return None;
+        }
+        self.next_token();
+
+        let mut names = Vec::new();
+        while self.expect_peek(TokenType::Period) {
+            if !self.expect_peek(TokenType::Ident) {
+                self.peek_error("a module name after `.`");
+                return None;
+            }
+            names.push(self.current_token.literal.clone());
+        }
+
+        if names.is_empty() {
+            self.peek_error("a module name after `std`");
+            return None;
+        }
+
+        Some(ImportPath::Std(names.join(".")))
+    }
+
+    // import { a, b as c } from "path"
+    fn parse_import_names(&mut self, token: Token) -> Option<Statement> {
+        self.next_token();
+        let mut names = Vec::new();
+
+        while self.peek_token.ttype != TokenType::RBrace {
+            if !self.expect_peek(TokenType::Ident) {
+                self.peek_error("a name to import");
+                return None;
+            }
+            let name = Identifier::new(self.current_token.clone());
+
+            let alias = if self.expect_peek(TokenType::Keyword(KeywordType::As)) {
+                if !self.expect_peek(TokenType::Ident) {
+                    self.peek_error("a name after `as`");
+                    return None;
+                }
+                Identifier::new(self.current_token.clone())
+            } else {
+                name.clone()
+            };
+            names.push((name, alias));
+
+            if self.peek_token.ttype != TokenType::RBrace && !self.expect_peek(TokenType::Comma) {
+                self.peek_error("`,` or `}` in import list");
+                return None;
+            }
+        }
+        self.next_token();
+
+        if !self.expect_peek(TokenType::Keyword(KeywordType::From)) {
+            self.peek_error("`from` after the import list");
+            return None;
+        }
+
+        let path = self.parse_import_path("`from`")?;
+
+        if self.peek_token.ttype == TokenType::Semicolon {
+            self.next_token();
+        }
+
+        Some(Statement::ImportNames { token, path, names })
+    }
+
+    // export let name = value / export fn name(params) { body }
+    fn parse_export_statement(&mut self) -> Option<Statement> {
+        let token = self.current_token.clone();
+
+        let statement = match self.peek_token.ttype {
+            TokenType::Keyword(KeywordType::Let) => {
+                self.next_token();
+                self.parse_let_statement()?
+            }
+            TokenType::Keyword(KeywordType::Fn) => {
+                self.next_token();
+                self.parse_fn_declaration()?
+            }
+            _ => {
+                self.peek_error("`let` or `fn` after `export`");
+                return None;
+            }
+        };
+
+        Some(Statement::Export {
+            token,
+            statement: Box::new(statement),
         })
     }
 
+    // fn name(params) { body }, the same as let name = fn(params) { body }
+    fn parse_fn_declaration(&mut self) -> Option<Statement> {
+        let token = self.current_token.clone();
+
+        if !self.expect_peek(TokenType::Ident) {
+            self.peek_error("a function name after `fn`");
+            return None;
+        }
+        let name = Identifier::new(self.current_token.clone());
+
+        let value = self.parse_fn_rest(token.clone())?;
+
+        if self.peek_token.ttype == TokenType::Semicolon {
+            self.next_token();
+        }
+
+        Some(Statement::Let { token, name, value })
+    }
+
+    fn no_expression_error(&mut self) {
+        // After another error this is usually the rest of the broken statement
+        if !self.errors.is_empty() {
+            return;
+        }
+
+        let found = match self.current_token.ttype {
+            TokenType::Eof => "end of input".to_string(),
+            _ => format!("`{}`", self.current_token.literal),
+        };
+
+        self.errors.push(format!(
+            "{}: Expected an expression, got {}",
+            self.current_token.position, found
+        ));
+    }
+
+    fn peek_error(&mut self, expected: &str) {
+        let found = match self.peek_token.ttype {
+            TokenType::Eof => "end of input".to_string(),
+            _ => format!("`{}`", self.peek_token.literal),
+        };
+
+        self.errors.push(format!(
+            "{}: Expected {}, got {}",
+            self.peek_token.position, expected, found
+        ));
+    }
+
     fn expect_peek(&mut self, ttype: TokenType) -> bool {
         if self.peek_token.ttype == ttype {
             self.next_token();
@@ -532,6 +819,11 @@ impl Parser {
     }
 
     fn next_token(&mut self) {
+        // Stay on the Eof token, an incomplete expression may end on it
+        if self.index + 1 >= self.tokens.len() {
+            return;
+        }
+
         self.index += 1;
         self.current_token = self.tokens[self.index].clone();
         if self.index + 1 < self.tokens.len() {
@@ -711,6 +1003,171 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_interpolated_string() {
+        let input = String::from(r#""Hello ${name}, you are ${age + 1}";"#);
+
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        let mut p = Parser::new(tokens);
+        let program = p.parse_program();
+        if let Some(program) = program {
+            if program.len() != 1 {
+                panic!(
+                    "Program does not contain 1 statement, got {}",
+                    program.len()
+                );
+            }
+
+            let stmt = &program[0];
+            match stmt {
+                Statement::Expression { value, .. } => {
+                    if value.to_string() != "Hello ${name}, you are ${(age + 1)}" {
+                        panic!(
+                            "Expected value to be Hello ${{name}}, you are ${{(age + 1)}}, got {}",
+                            value
+                        );
+                    }
+                }
+                _ => {
+                    panic!("Expected statement to be expression, got {:?}", stmt);
+                }
+            }
+        } else {
+            panic!("Parse program returned None");
+        }
+    }
+
+    #[test]
+    fn test_interpolation_errors() {
+        let tests = vec![
+            (r#"let s = "a${}b";"#, "1:13: Empty interpolation `${}`"),
+            (
+                r#"let s = "${1 2}";"#,
+                "1:14: Expected `}` after the interpolated expression, got `2`",
+            ),
+            (
+                r#""${1 +}""#,
+                "1:7: Expected an expression, got end of input",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let mut p = Parser::new(l.gen_tokens());
+
+            if p.parse_program().is_some() {
+                panic!("Expected parse program to fail for {}", input);
+            }
+            assert_eq!(p.errors, vec![expected.to_string()], "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_lex_error() {
+        let input = String::from("let a = 1;\nlet s = \"\\q\";");
+
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        let mut p = Parser::new(tokens);
+        let program = p.parse_program();
+
+        if program.is_some() {
+            panic!("Expected parse program to fail");
+        }
+        assert_eq!(
+            p.errors,
+            vec![String::from("2:9: Unknown escape character: q")]
+        );
+    }
+
+    #[test]
+    fn test_missing_expression() {
+        let tests = vec![
+            ("let a = ;", "1:9: Expected an expression, got `;`"),
+            ("let a = 1;\nf(1, )", "2:6: Expected an expression, got `)`"),
+            ("1 +", "1:4: Expected an expression, got end of input"),
+            ("let = 1", "1:5: Expected a name after `let`, got `=`"),
+            ("let a 1", "1:7: Expected `=` after the name, got `1`"),
+            ("let a = 1 @ 2", "1:11: Unknown character '@'"),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let mut p = Parser::new(l.gen_tokens());
+
+            if p.parse_program().is_some() {
+                panic!("Expected parse program to fail for {}", input);
+            }
+            assert_eq!(p.errors, vec![expected.to_string()], "input: {}", input);
+        }
+
+        // Empty statements are fine
+        let mut l = Lexer::new(String::from(";;1;;"));
+        let mut p = Parser::new(l.gen_tokens());
+        assert_eq!(p.parse_program().map(|program| program.len()), Some(1));
+    }
+
+    #[test]
+    fn test_imports_and_exports() {
+        let input = String::from(
+            r#"import "lib.joe" as lib;
+import { a, b as c } from "lib.joe";
+import std.math;
+import { split } from std.strings;
+export let x = 1;
+export fn add(a, b) { a + b }"#,
+        );
+
+        let mut l = Lexer::new(input);
+        let tokens = l.gen_tokens();
+
+        let mut p = Parser::new(tokens);
+        let program = p.parse_program();
+        if let Some(program) = program {
+            let stmts: Vec<String> = program.iter().map(|stmt| format!("{:?}", stmt)).collect();
+            assert_eq!(
+                stmts,
+                vec![
+                    "import \"lib.joe\" as lib",
+                    "import { a as a, b as c } from \"lib.joe\"",
+                    "import std.math as math",
+                    "import { split as split } from std.strings",
+                    "export let x = 1) ",
+                    "export let add = fn(a, b) {[(a + b)]}) ",
+                ]
+            );
+        } else {
+            panic!("Parse program returned None: {:?}", p.errors);
+        }
+
+        let tests = vec![
+            (
+                "export 1",
+                "1:8: Expected `let` or `fn` after `export`, got `1`",
+            ),
+            (
+                "import { a } \"lib\"",
+                "1:14: Expected `from` after the import list, got `lib`",
+            ),
+            (
+                "import { a b } from \"lib\"",
+                "1:12: Expected `,` or `}` in import list, got `b`",
+            ),
+        ];
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let mut p = Parser::new(l.gen_tokens());
+
+            if p.parse_program().is_some() {
+                panic!("Expected parse program to fail for {}", input);
+            }
+            assert_eq!(p.errors, vec![expected.to_string()]);
+        }
+    }
+
     #[test]
     fn eq_test() {
         let input = String::from("5 == 5;");
@@ -892,6 +1349,42 @@ mod test {
         }
     }
 
+    #[test]
+    fn bitwise_precedence() {
+        let tests = vec![
+            (
+                "1 | 2 ^ 3 & 4 << 5 + 6;",
+                "(1 | (2 ^ (3 & (4 << (5 + 6)))))",
+            ),
+            ("a & b == c;", "((a & b) == c)"),
+            ("~x >> 1;", "((~x) >> 1)"),
+            ("0xFF & 0b1_0000;", "(255 & 16)"),
+        ];
+
+        for (input, expected) in tests {
+            let mut l = Lexer::new(input.to_string());
+            let tokens = l.gen_tokens();
+
+            let mut p = Parser::new(tokens);
+            let program = p.parse_program();
+
+            if let Some(program) = program {
+                match &program[0] {
+                    Statement::Expression { value, .. } => {
+                        if value.to_string() != expected {
+                            panic!("Expected value to be {}, got {}", expected, value);
+                        }
+                    }
+                    stmt => {
+                        panic!("Expected statement to be expression, got {:?}", stmt);
+                    }
+                }
+            } else {
+                panic!("Parse program returned None");
+            }
+        }
+    }
+
     #[test]
     fn boolean_expr() {
         let input = String::from("true;");
@@ -1125,7 +1618,7 @@ mod test {
                 );
             }
 
-            let tests = vec!["x", "y", "foobar"];
+            let tests = ["x", "y", "foobar"];
 
             for (i, tt) in tests.iter().enumerate() {
                 let stmt = &program[i];
@@ -1144,4 +1637,34 @@ mod test {
             panic!("Parse program returned None");
         }
     }
+    #[test]
+    fn test_nesting_limit() {
+        // Parsing near the limit needs more than the stack of a test thread
+        let thread = std::thread::Builder::new().stack_size(16 << 20).spawn(|| {
+            let parse = |src: String| {
+                let mut p = Parser::new(Lexer::new(src).gen_tokens());
+                p.parse_program();
+                p.errors
+            };
+
+            let depth = super::MAX_NESTING - 1;
+            assert!(parse(format!("{}1{}", "(".repeat(depth), ")".repeat(depth))).is_empty());
+            assert!(parse(vec!["1"; depth].join("+")).is_empty());
+
+            let depth = super::MAX_NESTING + 1;
+            for src in [
+                format!("{}1{}", "[".repeat(depth), "]".repeat(depth)),
+                format!("{}1", "!".repeat(depth)),
+                vec!["1"; depth + 1].join(" * "),
+                format!("a{}", ".b".repeat(depth)),
+            ] {
+                let errors = parse(src);
+                assert_eq!(errors.len(), 1);
+                assert!(
+                    errors[0].ends_with(": Expression nested too deeply, more than 1000 levels")
+                );
+            }
+        });
+        thread.unwrap().join().unwrap();
+    }
 }
@@ -0,0 +1,221 @@
+//! Which effects on the host scripts may have.
+//!
+//! Natives with side effects check the [`Permissions`] of the interpreter
+//! running them through [`crate::native::Ctx::permissions`], a denied call
+//! fails with [`RuntimeError::PermissionDenied`].
+//!
+//! ```
+//! use monki::{Interpreter, Permissions};
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.set_permissions(Permissions::none().allow_read("scripts"));
+//!
+//! let err = interpreter.eval_str(r#"print("hi")"#).unwrap_err();
+//! assert_eq!(err.to_string(), "Permission denied: writing to stdout");
+//! ```
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::RuntimeError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permissions {
+    stdin: bool,
+    stdout: bool,
+    stderr: bool,
+    read: Paths,
+    write: Paths,
+    env: bool,
+    clock: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Paths {
+    Any,
+    /// Only files under one of these directories
+    Under(Vec<PathBuf>),
+}
+
+impl Permissions {
+    /// Everything is allowed, the default
+    pub fn all() -> Self {
+        Self {
+            stdin: true,
+            stdout: true,
+            stderr: true,
+            read: Paths::Any,
+            write: Paths::Any,
+            env: true,
+            clock: true,
+        }
+    }
+
+    /// Nothing is allowed, for adding to with the `allow_*` methods
+    pub fn none() -> Self {
+        Self {
+            stdin: false,
+            stdout: false,
+            stderr: false,
+            read: Paths::Under(Vec::new()),
+            write: Paths::Under(Vec::new()),
+            env: false,
+            clock: false,
+        }
+    }
+
+    pub fn allow_stdin(mut self) -> Self {
+        self.stdin = true;
+        self
+    }
+
+    pub fn allow_stdout(mut self) -> Self {
+        self.stdout = true;
+        self
+    }
+
+    pub fn allow_stderr(mut self) -> Self {
+        self.stderr = true;
+        self
+    }
+
+    /// Allows reading files anywhere under `root`
+    pub fn allow_read(mut self, root: impl Into<PathBuf>) -> Self {
+        self.read.add(root.into());
+        self
+    }
+
+    /// Allows creating and writing files anywhere under `root`
+    pub fn allow_write(mut self, root: impl Into<PathBuf>) -> Self {
+        self.write.add(root.into());
+        self
+    }
+
+    /// Allows reading environment variables
+    pub fn allow_env(mut self) -> Self {
+        self.env = true;
+        self
+    }
+
+    /// Allows reading the clock and random numbers
+    pub fn allow_clock(mut self) -> Self {
+        self.clock = true;
+        self
+    }
+
+    pub fn check_stdin(&self) -> Result<(), RuntimeError> {
+        check(self.stdin, || "reading from stdin".to_string())
+    }
+
+    pub fn check_stdout(&self) -> Result<(), RuntimeError> {
+        check(self.stdout, || "writing to stdout".to_string())
+    }
+
+    pub fn check_stderr(&self) -> Result<(), RuntimeError> {
+        check(self.stderr, || "writing to stderr".to_string())
+    }
+
+    pub fn check_read(&self, path: &Path) -> Result<(), RuntimeError> {
+        check(self.read.contains(path), || {
+            format!("reading {:?}", path.display().to_string())
+        })
+    }
+
+    pub fn check_write(&self, path: &Path) -> Result<(), RuntimeError> {
+        check(self.write.contains(path), || {
+            format!("writing {:?}", path.display().to_string())
+        })
+    }
+
+    pub fn check_env(&self, name: &str) -> Result<(), RuntimeError> {
+        check(self.env, || {
+            format!("reading environment variable {:?}", name)
+        })
+    }
+
+    pub fn check_clock(&self) -> Result<(), RuntimeError> {
+        check(self.clock, || {
+            "reading the clock or random numbers".to_string()
+        })
+    }
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+fn check(allowed: bool, effect: impl FnOnce() -> String) -> Result<(), RuntimeError> {
+    match allowed {
+        true => Ok(()),
+        false => Err(RuntimeError::PermissionDenied(effect())),
+    }
+}
+
+impl Paths {
+    fn add(&mut self, root: PathBuf) {
+        if let Paths::Under(roots) = self {
+            roots.push(root);
+        }
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        match self {
+            Paths::Any => true,
+            Paths::Under(roots) => {
+                let path = absolute(path);
+                roots.iter().any(|root| path.starts_with(absolute(root)))
+            }
+        }
+    }
+}
+
+// `path` with symlinks and `..` resolved, as far as it exists, so that a
+// path cannot leave a root by going up or through a link
+fn absolute(path: &Path) -> PathBuf {
+    let mut result = std::env::current_dir().unwrap_or_default();
+
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                result.pop();
+            }
+            Component::CurDir => {}
+            component => {
+                result.push(component);
+                if let Ok(real) = result.canonicalize() {
+                    result = real;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::Permissions;
+
+    #[test]
+    fn test_paths() {
+        let dir = std::env::temp_dir().join("monki_permissions_test");
+        std::fs::create_dir_all(dir.join("inner")).unwrap();
+
+        let permissions = Permissions::none().allow_read(dir.join("inner"));
+        assert!(permissions.check_read(&dir.join("inner/a.txt")).is_ok());
+        assert!(permissions.check_read(&dir.join("inner/new/b.txt")).is_ok());
+        assert!(permissions.check_read(&dir.join("a.txt")).is_err());
+        assert!(permissions.check_read(&dir.join("inner/../a.txt")).is_err());
+        assert!(permissions
+            .check_read(&dir.join("inner/new/../../a.txt"))
+            .is_err());
+        assert!(permissions.check_write(&dir.join("inner/a.txt")).is_err());
+
+        assert!(Permissions::all().check_write(&dir).is_ok());
+        assert!(Permissions::none().check_stdout().is_err());
+        assert!(Permissions::none().allow_env().check_env("HOME").is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
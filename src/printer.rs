@@ -0,0 +1,459 @@
+//! Prints a syntax tree back as source code in one canonical layout, used
+//! by `monki fmt`.
+//!
+//! Blocks are indented by four spaces with one statement per line and
+//! statements end in `;`, except for `if` expressions and the value at the
+//! end of a block. Parentheses are only written where precedence needs
+//! them. [`print`] writes integers and strings in their plain form, so
+//! `0xFF` becomes `255` and raw strings become escaped ones, while
+//! [`print_source`] keeps them as they are spelled in the source.
+
+use std::collections::VecDeque;
+
+use crate::ast::{BlockStatement, Expression, ImportPath, Literal, Program, Statement};
+use crate::lexer::{self, KeywordType, Lexer, Token, TokenType};
+use crate::parser::Precedence;
+
+const INDENT: &str = "    ";
+
+pub fn print(program: &Program) -> String {
+    Printer::default().statements(program, 0, false)
+}
+
+/// Like [`print`] for a `program` parsed from `src`, with its integer and
+/// string literals spelled as in `src`
+pub fn print_source(program: &Program, src: &str) -> String {
+    let chars: Vec<char> = src.chars().collect();
+    let tokens = Lexer::new(src.to_string()).gen_tokens();
+
+    // Only whitespace separates tokens, a literal's spelling reaches up to
+    // the next one
+    let spellings = tokens
+        .windows(2)
+        .filter(|pair| {
+            matches!(
+                pair[0].ttype,
+                TokenType::Number | TokenType::String | TokenType::Interpolated(_)
+            )
+        })
+        .map(|pair| {
+            let spelling: String = chars[pair[0].position.index()..pair[1].position.index()]
+                .iter()
+                .collect();
+            (pair[0].clone(), spelling.trim_end().to_string())
+        })
+        .collect();
+
+    Printer { spellings }.statements(program, 0, false)
+}
+
+#[derive(Default)]
+struct Printer {
+    // Literal tokens not printed yet with their spelling, in source order
+    spellings: VecDeque<(Token, String)>,
+}
+
+impl Printer {
+    // One line per statement, `nested` blocks give the value of their last one
+    fn statements(&mut self, block: &BlockStatement, indent: usize, nested: bool) -> String {
+        let lines: Vec<String> = block
+            .iter()
+            .enumerate()
+            .map(|(i, stmt)| self.statement(stmt, indent, nested && i == block.len() - 1))
+            .collect();
+
+        let mut out = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            out.push_str(&INDENT.repeat(indent));
+            out.push_str(line);
+
+            // A statement starting with one of these would continue an `if` as a
+            // call, an index or a subtraction
+            let next = lines.get(i + 1).and_then(|line| line.chars().next());
+            if !line.ends_with(';') && matches!(next, Some('(' | '[' | '-')) {
+                out.push(';');
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    fn statement(&mut self, stmt: &Statement, indent: usize, last: bool) -> String {
+        match stmt {
+            Statement::Let { token, name, value } => match (&token.ttype, value) {
+                // `export fn name(params) { body }`
+                (
+                    TokenType::Keyword(KeywordType::Fn),
+                    Expression::FunctionLiteral {
+                        parameters, body, ..
+                    },
+                ) => {
+                    let parameters: Vec<&str> =
+                        parameters.iter().map(|p| p.value.as_str()).collect();
+                    format!(
+                        "fn {}({}) {}",
+                        name,
+                        parameters.join(", "),
+                        self.block(body, indent)
+                    )
+                }
+                _ => format!("let {} = {};", name, self.expression(value, indent)),
+            },
+            Statement::ReAssign { name, value, .. } => {
+                format!("{} = {};", name, self.expression(value, indent))
+            }
+            Statement::Return { value, .. } => {
+                format!("return {};", self.expression(value, indent))
+            }
+            Statement::Expression { value, .. } => {
+                let expr = self.expression(value, indent);
+                match value {
+                    _ if last => expr,
+                    Expression::If { .. } => expr,
+                    _ => expr + ";",
+                }
+            }
+            Statement::Import { path, alias, .. } => match path {
+                // std modules are bound to their last name by default
+                ImportPath::Std(name) if name.rsplit('.').next() == Some(alias.value.as_str()) => {
+                    format!("import {};", path)
+                }
+                _ => format!("import {} as {};", self.import_path(path), alias),
+            },
+            Statement::ImportNames { path, names, .. } => {
+                let names: Vec<String> = names
+                    .iter()
+                    .map(|(name, alias)| match name.value == alias.value {
+                        true => name.to_string(),
+                        false => format!("{} as {}", name, alias),
+                    })
+                    .collect();
+                format!(
+                    "import {{ {} }} from {};",
+                    names.join(", "),
+                    self.import_path(path)
+                )
+            }
+            Statement::Export {
+                statement: stmt, ..
+            } => {
+                format!("export {}", self.statement(stmt, indent, false))
+            }
+        }
+    }
+
+    fn import_path(&mut self, path: &ImportPath) -> String {
+        match path {
+            ImportPath::File(path) => self
+                .spelling(|token| token.ttype == TokenType::String && token.literal == *path)
+                .unwrap_or_else(|| quote(path)),
+            ImportPath::Std(_) => path.to_string(),
+        }
+    }
+
+    fn block(&mut self, block: &BlockStatement, indent: usize) -> String {
+        if block.is_empty() {
+            return String::from("{}");
+        }
+
+        format!(
+            "{{\n{}{}}}",
+            self.statements(block, indent + 1, true),
+            INDENT.repeat(indent)
+        )
+    }
+
+    fn expression(&mut self, expr: &Expression, indent: usize) -> String {
+        match expr {
+            Expression::Identifier(ident) => ident.value.clone(),
+            Expression::Literal(literal) => match literal {
+                Literal::Integer(int) => self
+                    .spelling(|token| {
+                        token.ttype == TokenType::Number
+                            && lexer::parse_integer(&token.literal) == Ok(*int)
+                    })
+                    .unwrap_or_else(|| int.to_string()),
+                Literal::Boolean(bool) => bool.to_string(),
+                Literal::String(string) => self
+                    .spelling(|token| token.ttype == TokenType::String && token.literal == *string)
+                    .unwrap_or_else(|| quote(string)),
+                Literal::Interpolated(parts) => {
+                    let spelling =
+                        self.spelling(|token| matches!(token.ttype, TokenType::Interpolated(_)));
+                    if let Some(spelling) = spelling {
+                        return spelling;
+                    }
+
+                    let mut out = String::from("\"");
+                    for part in parts {
+                        match part {
+                            Expression::Literal(Literal::String(string)) => {
+                                out.push_str(&escape(string))
+                            }
+                            _ => out.push_str(&format!("${{{}}}", self.expression(part, indent))),
+                        }
+                    }
+                    out + "\""
+                }
+                Literal::Array(elements) => format!("[{}]", self.list(elements, indent)),
+                Literal::Hash(pairs) => {
+                    let pairs: Vec<String> = pairs
+                        .iter()
+                        .map(|(key, value)| {
+                            format!(
+                                "{}: {}",
+                                self.expression(key, indent),
+                                self.expression(value, indent)
+                            )
+                        })
+                        .collect();
+                    format!("{{{}}}", pairs.join(", "))
+                }
+            },
+            Expression::Prefix {
+                operator, right, ..
+            } => format!(
+                "{}{}",
+                operator,
+                self.operand(right, indent, |p| p < Precedence::Prefix)
+            ),
+            Expression::Infix {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                // Operators of the same precedence group to the left
+                let precedence = infix_precedence(operator);
+                format!(
+                    "{} {} {}",
+                    self.operand(left, indent, |p| p < precedence),
+                    operator,
+                    self.operand(right, indent, |p| p <= precedence)
+                )
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                let mut out = format!(
+                    "if ({}) {}",
+                    self.expression(condition, indent),
+                    self.block(consequence, indent)
+                );
+                if let Some(alternative) = alternative {
+                    out.push_str(&format!(" else {}", self.block(alternative, indent)));
+                }
+                out
+            }
+            Expression::FunctionLiteral {
+                parameters, body, ..
+            } => {
+                let parameters: Vec<&str> = parameters.iter().map(|p| p.value.as_str()).collect();
+                format!("fn({}) {}", parameters.join(", "), self.block(body, indent))
+            }
+            Expression::FunctionCall {
+                function,
+                arguments,
+                ..
+            } => format!(
+                "{}({})",
+                self.operand(function, indent, |p| p < Precedence::Call),
+                self.list(arguments, indent)
+            ),
+            Expression::IndexExpression { left, index, .. } => format!(
+                "{}[{}]",
+                self.operand(left, indent, |p| p < Precedence::Call),
+                self.expression(index, indent)
+            ),
+            Expression::DotNotation { left, right, .. } => format!(
+                "{}.{}",
+                self.operand(left, indent, |p| p < Precedence::Call),
+                self.operand(right, indent, |p| p < Precedence::Dot)
+            ),
+        }
+    }
+
+    // `expr` in parentheses if `parens` says so for its precedence
+    fn operand(
+        &mut self,
+        expr: &Expression,
+        indent: usize,
+        parens: impl Fn(Precedence) -> bool,
+    ) -> String {
+        match parens(precedence(expr)) {
+            true => format!("({})", self.expression(expr, indent)),
+            false => self.expression(expr, indent),
+        }
+    }
+
+    // The spelling of the next literal token if `matches` accepts it for
+    // the literal being printed, the plain form is printed otherwise
+    fn spelling(&mut self, matches: impl Fn(&Token) -> bool) -> Option<String> {
+        let (token, _) = self.spellings.front()?;
+        if !matches(token) {
+            return None;
+        }
+
+        self.spellings.pop_front().map(|(_, spelling)| spelling)
+    }
+
+    fn list(&mut self, exprs: &[Expression], indent: usize) -> String {
+        let exprs: Vec<String> = exprs
+            .iter()
+            .map(|expr| self.expression(expr, indent))
+            .collect();
+        exprs.join(", ")
+    }
+}
+
+// How tightly `expr` binds, calls, indexes and properties all chain left
+// to right so they share one level
+fn precedence(expr: &Expression) -> Precedence {
+    match expr {
+        Expression::Infix { operator, .. } => infix_precedence(operator),
+        Expression::Prefix { .. } => Precedence::Prefix,
+        Expression::FunctionCall { .. }
+        | Expression::IndexExpression { .. }
+        | Expression::DotNotation { .. } => Precedence::Call,
+        _ => Precedence::Dot,
+    }
+}
+
+fn infix_precedence(operator: &str) -> Precedence {
+    match operator {
+        "=" | "==" | "!=" => Precedence::Equals,
+        "<" | ">" => Precedence::LessGreater,
+        "|" => Precedence::BitOr,
+        "^" => Precedence::BitXor,
+        "&" => Precedence::BitAnd,
+        "<<" | ">>" => Precedence::Shift,
+        "+" | "-" => Precedence::Sum,
+        "*" | "/" => Precedence::Product,
+        _ => Precedence::Lowest,
+    }
+}
+
+fn quote(string: &str) -> String {
+    format!("\"{}\"", escape(string))
+}
+
+// The inside of a string literal that lexes back to `string`
+fn escape(string: &str) -> String {
+    let mut out = String::new();
+    let mut chars = string.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{print, print_source};
+
+    fn format(src: &str) -> String {
+        print(&crate::parse(src).unwrap())
+    }
+
+    #[test]
+    fn test_print() {
+        let src = r#"let add = fn(a,b){a+b};
+let x = (1 + 2) * 3 - (4 - 5) - 6 ;
+x = -(1 + 2) << 1 | ~x & 0xF;
+if(x>1){print("big")}else{ if (x == 1) { 1 } }
+import std.math; import std.strings as s;
+import "lib/util.joe" as util
+import { map, range as r } from std.arrays
+export fn double(n) { return n * 2; }
+let h = {"a": [1, 2], "b\n": fn() {}};
+h["a"][0];
+(fn(x) { x })(1).len
+print("${x} \$${add(1, 2)} \"${"q"}\" \\ \u{1}")
+"#;
+        let expected = r#"let add = fn(a, b) {
+    a + b
+};
+let x = (1 + 2) * 3 - (4 - 5) - 6;
+x = -(1 + 2) << 1 | ~x & 15;
+if (x > 1) {
+    print("big")
+} else {
+    if (x == 1) {
+        1
+    }
+}
+import std.math;
+import std.strings as s;
+import "lib/util.joe" as util;
+import { map, range as r } from std.arrays;
+export fn double(n) {
+    return n * 2;
+}
+let h = {"a": [1, 2], "b\n": fn() {}};
+h["a"][0];
+fn(x) {
+    x
+}(1).len;
+print("${x} $${add(1, 2)} \"q\" \\ \u{1}");
+"#;
+        assert_eq!(format(src), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn test_print_keeps_meaning() {
+        let tests = [
+            "a - (b - c)",
+            "(a - b) - c",
+            "-(a[0])",
+            "(-a)[0]",
+            "(a + b).c(d)",
+            "!(a == b)",
+            "if (a) { 1 }; -1",
+            "if (a) { 1 }; [1]",
+            "\"$\" + \"${1}$\"",
+        ];
+
+        for src in tests {
+            // Tokens differ in their positions, the tree's debug output does not
+            let program = crate::parse(src).unwrap();
+            let printed = crate::parse(&print(&program)).unwrap();
+            assert_eq!(
+                format!("{:?}", printed),
+                format!("{:?}", program),
+                "src: {}",
+                src
+            );
+        }
+    }
+    #[test]
+    fn test_print_source() {
+        let tests = [
+            ("let x=0xFF+1_000", "let x = 0xFF + 1_000;\n"),
+            ("[0o17,0b101, 10]", "[0o17, 0b101, 10];\n"),
+            (r#"r"a\b" + """x"y""""#, "r\"a\\b\" + \"\"\"x\"y\"\"\";\n"),
+            (r#"{"k" : "v\u{41}"}"#, "{\"k\": \"v\\u{41}\"};\n"),
+            ("\"${0x1 +  2}\" + 0x3", "\"${0x1 +  2}\" + 0x3;\n"),
+            ("import \"lib.joe\" as lib", "import \"lib.joe\" as lib;\n"),
+        ];
+
+        for (src, expected) in tests {
+            let program = crate::parse(src).unwrap();
+            assert_eq!(print_source(&program, src), expected, "src: {}", src);
+        }
+    }
+}
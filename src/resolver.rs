@@ -0,0 +1,354 @@
+//! Static pass between parsing and evaluation that decides where every
+//! variable lives.
+//!
+//! Function parameters and `let`s inside functions become numbered slots
+//! of the call's environment, an identifier refers to them by how many
+//! functions out they were declared ([`Slot::Local`]). Everything else is a
+//! global of the file or a native and is still looked up by name
+//! ([`Slot::Global`]), so globals can be added between evaluations. A
+//! function's names are all declared before its body is resolved, like
+//! those of the top level, so local functions may call ones defined after
+//! them.
+//!
+//! Names that are neither are reported as errors before anything runs.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Expression, Identifier, Literal, Program, Slot, Statement};
+
+#[derive(Default)]
+struct Scope {
+    names: HashMap<String, usize>,
+    count: usize,
+}
+
+impl Scope {
+    fn declare(&mut self, name: &str) -> usize {
+        if let Some(index) = self.names.get(name) {
+            return *index;
+        }
+
+        let index = self.count;
+        self.names.insert(name.to_string(), index);
+        self.count += 1;
+        index
+    }
+}
+
+pub struct Resolver {
+    globals: HashSet<String>,
+    // One scope per function being resolved, innermost last
+    scopes: Vec<Scope>,
+    errors: Vec<String>,
+}
+
+/// Resolves `program` in place. `globals` are the names already defined
+/// when it runs, the program's own top level definitions are added to them.
+pub fn resolve(program: &mut Program, globals: HashSet<String>) -> Result<(), Vec<String>> {
+    let mut resolver = Resolver {
+        globals,
+        scopes: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    // Top level functions may refer to globals defined after them
+    resolver.declare_block(program);
+    resolver.resolve_block(program);
+
+    if !resolver.errors.is_empty() {
+        return Err(resolver.errors);
+    }
+    Ok(())
+}
+
+impl Resolver {
+    // Declares the names a block defines before resolving it, so functions
+    // may refer to names defined after them in the same scope
+    fn declare_block(&mut self, block: &Program) {
+        for stmt in block {
+            match stmt {
+                Statement::Let { name, .. } => self.declare_name(&name.value),
+                Statement::Export { statement, .. } => {
+                    if let Statement::Let { name, .. } = statement.as_ref() {
+                        self.declare_name(&name.value);
+                    }
+                }
+                Statement::Import { alias, .. } => self.declare_name(&alias.value),
+                Statement::ImportNames { names, .. } => {
+                    for (_, alias) in names {
+                        self.declare_name(&alias.value);
+                    }
+                }
+                // `let` in an if block still defines a name of the scope
+                Statement::Expression {
+                    value:
+                        Expression::If {
+                            consequence,
+                            alternative,
+                            ..
+                        },
+                    ..
+                } => {
+                    self.declare_block(consequence);
+                    if let Some(alternative) = alternative {
+                        self.declare_block(alternative);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn resolve_block(&mut self, block: &mut Program) {
+        for stmt in block {
+            self.resolve_statement(stmt);
+        }
+    }
+
+    fn resolve_statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::Let { name, value, .. } => {
+                self.resolve_expression(value);
+                self.declare(name);
+            }
+            Statement::ReAssign { name, value, .. } => {
+                self.resolve_expression(value);
+                self.lookup(name);
+            }
+            Statement::Return { value, .. } | Statement::Expression { value, .. } => {
+                self.resolve_expression(value)
+            }
+            Statement::Import { alias, .. } => self.declare(alias),
+            Statement::ImportNames { names, .. } => {
+                for (_, alias) in names {
+                    self.declare(alias);
+                }
+            }
+            Statement::Export { statement, .. } => self.resolve_statement(statement),
+        }
+    }
+
+    fn resolve_expression(&mut self, expr: &mut Expression) {
+        match expr {
+            Expression::Identifier(iden) => self.lookup(iden),
+            Expression::Literal(lit) => match lit {
+                Literal::Integer(_) | Literal::Boolean(_) | Literal::String(_) => {}
+                Literal::Interpolated(exprs) | Literal::Array(exprs) => {
+                    for expr in exprs {
+                        self.resolve_expression(expr);
+                    }
+                }
+                Literal::Hash(pairs) => {
+                    for (key, value) in pairs {
+                        self.resolve_expression(key);
+                        self.resolve_expression(value);
+                    }
+                }
+            },
+            Expression::Prefix { right, .. } => self.resolve_expression(right),
+            Expression::Infix { left, right, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.resolve_expression(condition);
+                self.resolve_block(consequence);
+                if let Some(alternative) = alternative {
+                    self.resolve_block(alternative);
+                }
+            }
+            Expression::FunctionLiteral {
+                parameters,
+                body,
+                locals,
+                ..
+            } => {
+                let mut scope = Scope::default();
+                for param in parameters {
+                    scope.declare(&param.value);
+                }
+
+                self.scopes.push(scope);
+                self.declare_block(body);
+                self.resolve_block(body);
+                *locals = self.scopes.pop().map_or(0, |scope| scope.count);
+            }
+            Expression::FunctionCall {
+                function,
+                arguments,
+                ..
+            } => {
+                self.resolve_expression(function);
+                for arg in arguments {
+                    self.resolve_expression(arg);
+                }
+            }
+            Expression::IndexExpression { left, index, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(index);
+            }
+            // The right side is a property name, not a variable
+            Expression::DotNotation { left, .. } => self.resolve_expression(left),
+        }
+    }
+
+    fn declare_name(&mut self, name: &str) {
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                scope.declare(name);
+            }
+            None => {
+                self.globals.insert(name.to_string());
+            }
+        }
+    }
+
+    fn declare(&mut self, iden: &mut Identifier) {
+        iden.slot = Some(match self.scopes.last_mut() {
+            Some(scope) => Slot::Local {
+                depth: 0,
+                index: scope.declare(&iden.value),
+            },
+            None => {
+                self.globals.insert(iden.value.clone());
+                Slot::Global
+            }
+        });
+    }
+
+    fn lookup(&mut self, iden: &mut Identifier) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(index) = scope.names.get(&iden.value) {
+                iden.slot = Some(Slot::Local {
+                    depth,
+                    index: *index,
+                });
+                return;
+            }
+        }
+
+        if self.globals.contains(&iden.value) {
+            iden.slot = Some(Slot::Global);
+            return;
+        }
+
+        self.errors.push(format!(
+            "{}: Undefined variable: {}",
+            iden.token.position, iden.value
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::resolve;
+    use crate::ast::{Expression, Slot, Statement};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_slots() {
+        let input = "let a = 1; let f = fn(x) { let y = x; fn() { y + a } };";
+
+        let mut l = Lexer::new(input.to_string());
+        let mut p = Parser::new(l.gen_tokens());
+        let mut program = p.parse_program().unwrap();
+
+        resolve(&mut program, HashSet::new()).unwrap();
+
+        let Statement::Let {
+            value: Expression::FunctionLiteral { body, locals, .. },
+            ..
+        } = &program[1]
+        else {
+            panic!("Expected function, got {:?}", program[1]);
+        };
+        assert_eq!(*locals, 2);
+
+        let Statement::Expression {
+            value: Expression::FunctionLiteral { body, locals, .. },
+            ..
+        } = &body[1]
+        else {
+            panic!("Expected function, got {:?}", body[1]);
+        };
+        assert_eq!(*locals, 0);
+
+        let Statement::Expression {
+            value: Expression::Infix { left, right, .. },
+            ..
+        } = &body[0]
+        else {
+            panic!("Expected infix, got {:?}", body[0]);
+        };
+        match (left.as_ref(), right.as_ref()) {
+            (Expression::Identifier(y), Expression::Identifier(a)) => {
+                assert_eq!(y.slot, Some(Slot::Local { depth: 1, index: 1 }));
+                assert_eq!(a.slot, Some(Slot::Global));
+            }
+            _ => panic!("Expected identifiers, got {} {}", left, right),
+        }
+    }
+
+    #[test]
+    fn test_later_locals() {
+        let input = "let h = fn() { let f = fn() { g }; let g = 1; f() };";
+
+        let mut l = Lexer::new(input.to_string());
+        let mut p = Parser::new(l.gen_tokens());
+        let mut program = p.parse_program().unwrap();
+
+        resolve(&mut program, HashSet::new()).unwrap();
+
+        let Statement::Let {
+            value: Expression::FunctionLiteral { body, locals, .. },
+            ..
+        } = &program[0]
+        else {
+            panic!("Expected function, got {:?}", program[0]);
+        };
+        assert_eq!(*locals, 2);
+
+        let Statement::Let {
+            value: Expression::FunctionLiteral { body, .. },
+            ..
+        } = &body[0]
+        else {
+            panic!("Expected function, got {:?}", body[0]);
+        };
+        match &body[0] {
+            Statement::Expression {
+                value: Expression::Identifier(g),
+                ..
+            } => assert_eq!(g.slot, Some(Slot::Local { depth: 1, index: 1 })),
+            stmt => panic!("Expected identifier, got {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn test_undefined_variables() {
+        let input = "let f = fn(x) { x + y };\nlet g = fn() { f(later) };\nlet later = 1;\nz";
+
+        let mut l = Lexer::new(input.to_string());
+        let mut p = Parser::new(l.gen_tokens());
+        let mut program = p.parse_program().unwrap();
+
+        let mut globals = HashSet::new();
+        globals.insert("len".to_string());
+
+        assert_eq!(
+            resolve(&mut program, globals),
+            Err(vec![
+                "1:21: Undefined variable: y".to_string(),
+                "4:1: Undefined variable: z".to_string(),
+            ])
+        );
+    }
+}
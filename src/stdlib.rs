@@ -0,0 +1,127 @@
+//! The `std` modules, imported with `import std.math` or
+//! `import { split } from std.strings`.
+//!
+//! Each module is monki source from `src/std` embedded in the binary plus
+//! natives defined here. The natives are bound in the module scope before
+//! the source runs, so the source can build on them, and are exported
+//! along with the source's own exports.
+//!
+//! - `std.math`: `abs`, `sign`, `min`, `max`, `clamp`, `pow`, `gcd` and the
+//!   natives `sqrt` (rounded down) and `rem`
+//! - `std.strings`: `lines`, `is_blank`, `pad_left`, `pad_right` and the
+//!   natives `split`, `join`, `trim`, `upper`, `lower`, `contains`,
+//!   `replace`, `starts_with`, `ends_with` and `repeat`
+//! - `std.arrays`: `reduce`, `map`, `filter`, `sum`, `contains` and the
+//!   natives `range`, `reverse`, `sort`, `slice` and `index_of`
+
+use crate::convert::{FromObject, ToObject};
+use crate::error::RuntimeError;
+use crate::native::NativeModule;
+use crate::object::Object;
+
+/// Source and natives of the module `std.{name}`
+pub fn module(name: &str) -> Option<(&'static str, NativeModule)> {
+    match name {
+        "math" => Some((include_str!("std/math.joe"), math())),
+        "strings" => Some((include_str!("std/strings.joe"), strings())),
+        "arrays" => Some((include_str!("std/arrays.joe"), arrays())),
+        _ => None,
+    }
+}
+
+fn math() -> NativeModule {
+    NativeModule::new("std.math")
+        .function("sqrt", |n: i64| {
+            if n < 0 {
+                return Err(RuntimeError::new(&format!(
+                    "Square root of negative number: {}",
+                    n
+                )));
+            }
+            Ok(n.isqrt())
+        })
+        .function("rem", |a: i64, b: i64| {
+            if b == 0 {
+                return Err(RuntimeError::new("Division by zero"));
+            }
+            // Only `i64::MIN % -1` wraps, to its right answer of 0
+            Ok(a.wrapping_rem(b))
+        })
+}
+
+fn strings() -> NativeModule {
+    NativeModule::new("std.strings")
+        .function("split", |s: String, sep: String| {
+            if sep.is_empty() {
+                return s.chars().map(String::from).collect();
+            }
+            s.split(&sep).map(String::from).collect::<Vec<_>>()
+        })
+        .function("join", |parts: Vec<String>, sep: String| parts.join(&sep))
+        .function("trim", |s: String| s.trim().to_string())
+        .function("upper", |s: String| s.to_uppercase())
+        .function("lower", |s: String| s.to_lowercase())
+        .function("contains", |s: String, sub: String| s.contains(&sub))
+        .function("replace", |s: String, from: String, to: String| {
+            s.replace(&from, &to)
+        })
+        .function("starts_with", |s: String, prefix: String| {
+            s.starts_with(&prefix)
+        })
+        .function("ends_with", |s: String, suffix: String| {
+            s.ends_with(&suffix)
+        })
+        .native("repeat", |ctx, args| {
+            args.expect_len(2)?;
+            let s: String = args.arg(0)?;
+            let n: usize = args.arg(1)?;
+
+            ctx.reserve(s.len().saturating_mul(n))?;
+            Ok(Object::String(s.repeat(n)))
+        })
+}
+
+fn arrays() -> NativeModule {
+    NativeModule::new("std.arrays")
+        .native("range", |ctx, args| {
+            args.expect_len(2)?;
+            let start: i64 = args.arg(0)?;
+            let end: i64 = args.arg(1)?;
+
+            let len = end.saturating_sub(start).max(0) as usize;
+            ctx.reserve(len.saturating_mul(std::mem::size_of::<Object>()))?;
+            Ok((start..end).collect::<Vec<_>>().to_object())
+        })
+        .function("reverse", |mut arr: Vec<Object>| {
+            arr.reverse();
+            arr
+        })
+        .native("sort", |_, args| {
+            args.expect_len(1)?;
+            let arr: Vec<Object> = args.arg(0)?;
+
+            if let Ok(mut ints) = Vec::<i64>::from_object(Object::Array(arr.clone())) {
+                ints.sort();
+                return Ok(ints.to_object());
+            }
+            if let Ok(mut strings) = Vec::<String>::from_object(Object::Array(arr)) {
+                strings.sort();
+                return Ok(strings.to_object());
+            }
+
+            Err(args.type_error(0, "Array of only Integers or only Strings"))
+        })
+        .function("slice", |arr: Vec<Object>, start: i64, end: i64| {
+            // Out of range bounds are clamped to the array
+            let clamp = |i: i64| i.clamp(0, arr.len() as i64) as usize;
+            let (start, end) = (clamp(start), clamp(end));
+
+            arr[start..end.max(start)].to_vec()
+        })
+        .function("index_of", |arr: Vec<Object>, item: Object| {
+            match arr.iter().position(|other| *other == item) {
+                Some(index) => index as i64,
+                None => -1,
+            }
+        })
+}
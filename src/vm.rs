@@ -0,0 +1,726 @@
+//! Stack machine running the bytecode of the [`crate::compiler`], an
+//! alternative to the tree-walking [`crate::eval::Evaluator`] with the same
+//! results.
+//!
+//! Values are computed on an operand stack, the locals of all active calls
+//! live in one vector of slots with each call frame owning a window of it.
+//! A closure keeps the locals of enclosing calls it uses as upvalues, which
+//! point into that vector while the call is active and hold the value once
+//! it returned.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::ast::{ImportPath, Program};
+use crate::budget::Budget;
+use crate::compiler::{self, Capture, Function, Op};
+use crate::env::Env;
+use crate::error::{RuntimeError, TRACE_LEN};
+use crate::eval::{index, infix, member_error, prefix, property, set_property, MAX_DEPTH};
+use crate::loader::{Import, Loader};
+use crate::native::{self, Args, Caller, Ctx, IntoNative, NativeModule, NativeObject};
+use crate::object::Object;
+use crate::permissions::Permissions;
+use crate::{builtin, resolver};
+
+/// How many closures called by natives may run at once whatever the depth
+/// limit, unlike other calls they nest on the native stack. Each takes
+/// about 18 KiB of it in a debug build.
+const MAX_CALLBACKS: usize = 200;
+
+/// A compiled function together with what it captured
+pub struct Closure {
+    pub function: Rc<Function>,
+    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // Global scope of the file the function was defined in
+    globals: Rc<RefCell<Env>>,
+}
+
+impl std::fmt::Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Closure({})", self.function.display)
+    }
+}
+
+/// Closures are only equal to themselves
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Closure {}
+
+#[derive(Debug)]
+enum Upvalue {
+    /// Index into the locals of a call that is still active
+    Open(usize),
+    Closed(Option<Object>),
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // Start of the frame's locals
+    base: usize,
+    // Height of the operand stack below the call
+    stack: usize,
+    // Script function calls active with this frame, the frames of scripts,
+    // modules and of calls made by natives do not count
+    calls: usize,
+}
+
+pub struct Vm {
+    globals: Rc<RefCell<Env>>,
+    // Builtins and host registered natives, looked up after the globals
+    natives: HashMap<String, Object>,
+    loader: Loader,
+    stack: Vec<Object>,
+    // `None` for locals not assigned yet
+    locals: Vec<Option<Object>>,
+    frames: Vec<Frame>,
+    // Upvalues still pointing into `locals`
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    max_depth: usize,
+    // Closures called by natives, each runs nested on the native stack
+    callbacks: usize,
+    budget: Budget,
+    permissions: Permissions,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self {
+            globals: Rc::new(RefCell::new(Env::new())),
+            natives: builtin::builtins(),
+            loader: Loader::default(),
+            stack: Vec::new(),
+            locals: Vec::new(),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            max_depth: MAX_DEPTH,
+            callbacks: 0,
+            budget: Budget::default(),
+            permissions: Permissions::default(),
+        }
+    }
+
+    /// Runs the [`crate::resolver`] on `program` against the current globals
+    pub fn resolve(&self, program: &mut Program) -> Result<(), Vec<String>> {
+        let globals = self
+            .globals
+            .borrow()
+            .names()
+            .chain(self.natives.keys())
+            .cloned()
+            .collect();
+
+        resolver::resolve(program, globals)
+    }
+
+    /// Resolves, compiles and runs `program`
+    pub fn eval(&mut self, program: &Program) -> Result<Object, RuntimeError> {
+        let mut program = program.clone();
+        if let Err(errors) = self.resolve(&mut program) {
+            return Err(RuntimeError::new(&errors.join("\n")));
+        }
+
+        self.eval_resolved(&program)
+    }
+
+    /// Compiles and runs a program that went through [`Vm::resolve`]
+    pub fn eval_resolved(&mut self, program: &Program) -> Result<Object, RuntimeError> {
+        let function = compiler::compile(program);
+        self.run_function(function, Rc::clone(&self.globals))
+    }
+
+    /// Runs the resolved `program` as the contents of the file at `path`,
+    /// imports in it are resolved relative to the file
+    pub fn eval_file(&mut self, program: &Program, path: &Path) -> Result<Object, RuntimeError> {
+        let path = path.canonicalize().unwrap_or(path.to_path_buf());
+
+        self.loader.files.push(path);
+        let result = self.eval_resolved(program);
+        self.loader.files.pop();
+
+        result
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        self.globals.borrow().get(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Object) {
+        self.globals.borrow_mut().set(name, value);
+    }
+
+    /// Limits how many script function calls may be active at once, going
+    /// over it raises [`RuntimeError::StackOverflow`]
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Limits how many more instructions scripts may run, see
+    /// [`crate::Interpreter::set_fuel`]
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.budget.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.budget.fuel
+    }
+
+    /// Stops scripts still running at `deadline`
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.budget.set_deadline(deadline);
+    }
+
+    /// Limits the bytes any single array, string or hash may take, see
+    /// [`crate::Interpreter::set_value_size_limit`]
+    pub fn set_value_size_limit(&mut self, bytes: Option<usize>) {
+        self.budget.value_size = bytes;
+    }
+
+    /// Sets which effects on the host scripts may have
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.permissions = permissions;
+    }
+
+    /// Global variable or native called `name`
+    pub fn lookup(&self, name: &str) -> Option<Object> {
+        self.get_global(name)
+            .or_else(|| self.natives.get(name).cloned())
+    }
+
+    pub fn register_native<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&mut Ctx<'_>, Args) -> Result<Object, RuntimeError> + 'static,
+    {
+        self.natives
+            .insert(name.to_string(), native::native(name, func));
+    }
+
+    pub fn register_fn<F, Marker>(&mut self, name: &str, func: F)
+    where
+        F: IntoNative<Marker>,
+    {
+        self.natives
+            .insert(name.to_string(), native::function(name, func));
+    }
+
+    pub fn register_module(&mut self, module: NativeModule) {
+        self.natives
+            .insert(module.name().to_string(), module.into_object());
+    }
+
+    fn run_function(
+        &mut self,
+        function: Rc<Function>,
+        globals: Rc<RefCell<Env>>,
+    ) -> Result<Object, RuntimeError> {
+        let closure = Closure {
+            function,
+            upvalues: Vec::new(),
+            globals,
+        };
+
+        let depth = self.frames.len();
+        self.frames.push(Frame {
+            closure: Rc::new(closure),
+            ip: 0,
+            base: self.locals.len(),
+            stack: self.stack.len(),
+            calls: self.calls(),
+        });
+
+        self.run(depth)
+    }
+
+    /// Runs until the frame at `depth` returns, unwinding back to it on errors
+    fn run(&mut self, depth: usize) -> Result<Object, RuntimeError> {
+        let stack = self.frames[depth].stack;
+
+        self.execute(depth).inspect_err(|_| {
+            let base = self.frames[depth].base;
+            self.close_upvalues(base);
+            self.locals.truncate(base);
+            self.frames.truncate(depth);
+            self.stack.truncate(stack);
+        })
+    }
+
+    fn execute(&mut self, depth: usize) -> Result<Object, RuntimeError> {
+        loop {
+            self.budget.step()?;
+            let frame = self.frames.last_mut().expect("no frame to run");
+            let op = frame.closure.function.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Constant(i) => {
+                    let value = self.function().constants[i].clone();
+                    self.stack.push(value);
+                }
+                Op::Null => self.stack.push(Object::Null),
+                Op::Empty => self.stack.push(Object::Empty),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::DefineLocal(i) => {
+                    let value = self.pop();
+                    let base = self.frame().base;
+                    self.locals[base + i] = Some(value);
+                }
+                Op::GetLocal(i) => {
+                    let base = self.frame().base;
+                    let value = match self.locals[base + i].clone() {
+                        Some(value) => value,
+                        None => self.unset(self.local_name(i))?,
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetLocal(i) => {
+                    let value = self.pop();
+                    let slot = self.frame().base + i;
+                    if self.locals[slot].is_none() {
+                        return Err(not_found(self.local_name(i)));
+                    }
+                    self.locals[slot] = Some(value);
+                }
+                Op::GetUpvalue(i) => {
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[i]);
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.locals[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    let value = match value {
+                        Some(value) => value,
+                        None => self.unset(&self.function().upvalues[i].1)?,
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetUpvalue(i) => {
+                    let value = self.pop();
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[i]);
+                    let mut upvalue = upvalue.borrow_mut();
+                    let slot = match &mut *upvalue {
+                        Upvalue::Open(slot) => &mut self.locals[*slot],
+                        Upvalue::Closed(value) => value,
+                    };
+                    if slot.is_none() {
+                        let name =
+                            &self.frames[self.frames.len() - 1].closure.function.upvalues[i].1;
+                        return Err(not_found(name));
+                    }
+                    *slot = Some(value);
+                }
+                Op::DefineGlobal(i) => {
+                    let value = self.pop();
+                    let name = &self.function().names[i];
+                    self.frame().closure.globals.borrow_mut().set(name, value);
+                }
+                Op::GetGlobal(i) => {
+                    let name = &self.function().names[i];
+                    let value = self.frame().closure.globals.borrow().get(name);
+                    let value = match value {
+                        Some(value) => value,
+                        None => self.unset(name)?,
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetGlobal(i) => {
+                    let value = self.pop();
+                    let name = &self.function().names[i];
+                    let mut globals = self.frame().closure.globals.borrow_mut();
+                    if globals.get(name).is_none() {
+                        return Err(not_found(name));
+                    }
+                    globals.set(name, value);
+                }
+                Op::Export(i) => {
+                    let name = &self.function().names[i];
+                    self.frame().closure.globals.borrow_mut().export(name);
+                }
+                Op::Prefix(operator) => {
+                    let right = self.pop();
+                    self.stack.push(prefix(operator, right)?);
+                }
+                Op::Infix(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = infix(&self.budget, operator, left, right)?;
+                    self.stack.push(value);
+                }
+                Op::Array(n) => {
+                    let items = self.stack.split_off(self.stack.len() - n);
+                    self.stack.push(self.budget.check(Object::Array(items))?);
+                }
+                Op::Hash(n) => {
+                    let mut items = self.stack.split_off(self.stack.len() - 2 * n).into_iter();
+                    let mut hash = Vec::with_capacity(n);
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        hash.push((key, value));
+                    }
+                    self.stack.push(self.budget.check(Object::Hash(hash))?);
+                }
+                Op::HashKey => {
+                    if !matches!(self.stack.last(), Some(Object::String(_))) {
+                        return Err(RuntimeError::new("Hash keys must be strings"));
+                    }
+                }
+                Op::Interpolate(n) => {
+                    let parts = self.stack.split_off(self.stack.len() - n);
+                    let string = parts.iter().map(|part| part.to_string()).collect();
+                    self.stack.push(self.budget.check(Object::String(string))?);
+                }
+                Op::Index => {
+                    let i = self.pop();
+                    let left = self.pop();
+                    self.stack.push(index(left, i)?);
+                }
+                Op::GetProperty(i) => {
+                    let left = self.pop();
+                    let value = property(left, &self.function().names[i])?;
+                    self.stack.push(self.budget.check(value)?);
+                }
+                Op::SetProperty(i) => {
+                    let value = self.pop();
+                    let left = self.pop();
+                    set_property(left, &self.function().names[i], value)?;
+                    self.stack.push(Object::Empty);
+                }
+                Op::GetMethod(i) => {
+                    let left = self.pop();
+                    let name = self.function().names[i].clone();
+                    let value = match left {
+                        Object::Native(native) => bind_method(native, name),
+                        left => property(left, &name)?,
+                    };
+                    self.stack.push(self.budget.check(value)?);
+                }
+                Op::Call(argc) => {
+                    let calls = self.calls() + 1;
+                    let callee = self.stack.len() - argc - 1;
+                    if calls > self.max_depth && matches!(self.stack[callee], Object::Closure(_)) {
+                        return Err(self.stack_overflow(calls));
+                    }
+                    self.call_value(argc, calls)?;
+                }
+                Op::TailCall(argc) => {
+                    if self.reuse_frame(argc) {
+                        continue;
+                    }
+
+                    // Anything else is a native, or fails
+                    self.call_value(argc, self.calls())?;
+                    if let Some(value) = self.return_value(depth) {
+                        return Ok(value);
+                    }
+                }
+                Op::Closure(i) => {
+                    let closure = self.closure(i);
+                    self.stack.push(Object::Closure(Rc::new(closure)));
+                }
+                Op::Jump(to) => self.frames.last_mut().expect("no frame").ip = to,
+                Op::JumpIfFalse(to) => match self.pop() {
+                    Object::Boolean(true) => {}
+                    Object::Boolean(false) => self.frames.last_mut().expect("no frame").ip = to,
+                    _ => return Err(RuntimeError::new("Use if conditionals on booleans")),
+                },
+                Op::Return => {
+                    if let Some(value) = self.return_value(depth) {
+                        return Ok(value);
+                    }
+                }
+                Op::Import(i) => {
+                    let path = self.function().imports[i].clone();
+                    let module = self.import(&path)?;
+                    self.stack.push(module);
+                }
+                Op::ImportMember(i) => {
+                    let name = &self.function().names[i];
+                    let value = match self.stack.last() {
+                        Some(Object::Module { path, env }) => env
+                            .borrow()
+                            .get_public(name)
+                            .map_err(|err| member_error(err, path, name))?,
+                        _ => return Err(RuntimeError::new("Not a module")),
+                    };
+                    self.stack.push(value);
+                }
+                Op::Fail(i) => {
+                    return Err(RuntimeError::new(&self.function().names[i]));
+                }
+            }
+        }
+    }
+
+    /// Returns the value on top of the stack from the current frame, to
+    /// the caller of `run` once the frame at `depth` is done
+    fn return_value(&mut self, depth: usize) -> Option<Object> {
+        let value = self.pop();
+        let frame = self.frames.pop().expect("no frame to return from");
+        self.close_upvalues(frame.base);
+        self.locals.truncate(frame.base);
+        self.stack.truncate(frame.stack);
+
+        if self.frames.len() == depth {
+            return Some(value);
+        }
+        self.stack.push(value);
+        None
+    }
+
+    /// Replaces the current frame with a call of the closure below the
+    /// top `argc` values, `false` if that is not a closure taking `argc`
+    /// arguments
+    fn reuse_frame(&mut self, argc: usize) -> bool {
+        let callee = self.stack.len() - argc - 1;
+        let closure = match &self.stack[callee] {
+            Object::Closure(closure) if closure.function.arity == argc => Rc::clone(closure),
+            _ => return false,
+        };
+
+        let base = self.frame().base;
+        self.close_upvalues(base);
+        self.locals.truncate(base);
+        self.locals.extend(self.stack.drain(callee + 1..).map(Some));
+        self.locals
+            .resize(base + closure.function.locals.max(argc), None);
+
+        let frame = self.frames.last_mut().expect("no frame");
+        self.stack.truncate(frame.stack);
+        frame.closure = closure;
+        frame.ip = 0;
+        true
+    }
+
+    fn calls(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.calls)
+    }
+
+    /// The error for the call about to be made by the current frame, with
+    /// `calls` script function calls active
+    fn stack_overflow(&self, calls: usize) -> RuntimeError {
+        // Each call is made at the instruction before where its caller
+        // continues
+        let call_site = |frame: &Frame| {
+            let sites = &frame.closure.function.call_sites;
+            sites
+                .binary_search_by_key(&(frame.ip - 1), |(ip, _)| *ip)
+                .map_or(String::new(), |i| sites[i].1.clone())
+        };
+
+        let mut trace = vec![call_site(self.frame())];
+        for pair in self.frames.windows(2).rev() {
+            if trace.len() == TRACE_LEN {
+                break;
+            }
+            if pair[1].calls > pair[0].calls {
+                trace.push(call_site(&pair[0]));
+            }
+        }
+
+        RuntimeError::StackOverflow {
+            limit: self.max_depth,
+            calls,
+            trace,
+        }
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("no frame")
+    }
+
+    fn function(&self) -> &Function {
+        &self.frame().closure.function
+    }
+
+    fn local_name(&self, i: usize) -> &str {
+        self.function().local_names.get(i).map_or("", |name| name)
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("operand stack underflow")
+    }
+
+    // A variable without a value yet may still name a native
+    fn unset(&self, name: &str) -> Result<Object, RuntimeError> {
+        self.natives.get(name).cloned().ok_or(not_found(name))
+    }
+
+    fn closure(&mut self, i: usize) -> Closure {
+        let frame = self.frame();
+        let function = Rc::clone(&frame.closure.function.functions[i]);
+        let globals = Rc::clone(&frame.closure.globals);
+        let base = frame.base;
+
+        let mut upvalues = Vec::with_capacity(function.upvalues.len());
+        for (capture, _) in &function.upvalues {
+            upvalues.push(match capture {
+                Capture::Local(index) => self.capture(base + index),
+                Capture::Upvalue(index) => Rc::clone(&self.frame().closure.upvalues[*index]),
+            });
+        }
+
+        Closure {
+            function,
+            upvalues,
+            globals,
+        }
+    }
+
+    // Closures capturing the same local share its upvalue
+    fn capture(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let open = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(other) if other == slot));
+        if let Some(upvalue) = open {
+            return Rc::clone(upvalue);
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Moves the values of upvalues pointing at locals from `base` on into them
+    fn close_upvalues(&mut self, base: usize) {
+        let locals = &self.locals;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= base => slot,
+                _ => return true,
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(locals[slot].clone());
+            false
+        });
+    }
+
+    /// Calls the function below the top `argc` values of the stack, a
+    /// closure gets a new frame with `calls` active calls, a native runs
+    /// right away
+    fn call_value(&mut self, argc: usize, calls: usize) -> Result<(), RuntimeError> {
+        let callee = self.stack.len() - argc - 1;
+
+        match self.stack[callee].clone() {
+            Object::Closure(closure) => {
+                if argc != closure.function.arity {
+                    return Err(RuntimeError::Arity {
+                        function: "fn".to_string(),
+                        expected: closure.function.arity.to_string(),
+                        got: argc,
+                    });
+                }
+
+                let base = self.locals.len();
+                self.locals.extend(self.stack.drain(callee + 1..).map(Some));
+                self.locals
+                    .resize(base + closure.function.locals.max(argc), None);
+                self.stack.truncate(callee);
+
+                self.frames.push(Frame {
+                    closure,
+                    ip: 0,
+                    base,
+                    stack: callee,
+                    calls,
+                });
+            }
+            Object::BuiltinFunction(func) => {
+                let args = self.stack.split_off(callee + 1);
+                self.stack.truncate(callee);
+
+                match func.call(&mut Ctx { caller: self }, args)? {
+                    Object::Error(msg) => return Err(RuntimeError::Message(msg)),
+                    value => self.stack.push(self.budget.check(value)?),
+                }
+            }
+            function => return Err(RuntimeError::new(&format!("Not a function: {}", function))),
+        }
+
+        Ok(())
+    }
+
+    fn import(&mut self, path: &ImportPath) -> Result<Object, RuntimeError> {
+        let load = match self.loader.import(path, &self.permissions)? {
+            Import::Loaded(module) => return Ok(module),
+            Import::Load(load) => load,
+        };
+        let program = load.parse(&self.natives)?;
+        let function = compiler::compile(&program);
+
+        if let Some(file) = &load.file {
+            self.loader.files.push(file.clone());
+        }
+        let result = self.run_function(function, Rc::clone(&load.env));
+        if load.file.is_some() {
+            self.loader.files.pop();
+        }
+
+        result?;
+        Ok(self.loader.finish(load))
+    }
+}
+
+impl Caller for Vm {
+    fn call(&mut self, function: &Object, args: Vec<Object>) -> Result<Object, RuntimeError> {
+        if self.callbacks == MAX_CALLBACKS && matches!(function, Object::Closure(_)) {
+            return Err(self.stack_overflow(self.calls()));
+        }
+
+        let depth = self.frames.len();
+        let stack = self.stack.len();
+        let argc = args.len();
+
+        self.stack.push(function.clone());
+        self.stack.extend(args);
+        if let Err(error) = self.call_value(argc, self.calls()) {
+            self.stack.truncate(stack);
+            return Err(error);
+        }
+
+        // Natives are done already, closures still have to run
+        if self.frames.len() == depth {
+            return Ok(self.pop());
+        }
+        self.callbacks += 1;
+        let result = self.run(depth);
+        self.callbacks -= 1;
+        result
+    }
+
+    fn lookup(&self, name: &str) -> Option<Object> {
+        Vm::lookup(self, name)
+    }
+
+    fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    fn permissions(&self) -> &Permissions {
+        &self.permissions
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A native object's method as a function, argument errors name it `Type.method`
+fn bind_method(native: Rc<dyn NativeObject>, name: String) -> Object {
+    let function = format!("{}.{}", native.type_name(), name);
+    native::native(&function, move |ctx, args| {
+        native.call_method(ctx, &name, args)
+    })
+}
+
+fn not_found(name: &str) -> RuntimeError {
+    RuntimeError::new(&format!("Identifier not found: {}", name))
+}
@@ -0,0 +1,359 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+use monki::object::Object;
+
+/// `src` written to a script file under the system temp dir
+fn script(name: &str, src: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("monki-cli-{}-{}.joe", name, std::process::id()));
+    std::fs::write(&path, src).unwrap();
+    path
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_monki"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn script_args_and_exit_status() {
+    let path = script(
+        "args",
+        "print(len(args), args[0]);\nif (args[1] == \"fail\") { exit(3) }\nprint(\"done\")",
+    );
+
+    for engine in [None, Some("--vm")] {
+        let file = path.to_str().unwrap();
+        let mut args: Vec<&str> = engine.into_iter().collect();
+        args.extend([file, "--not-a-flag", "ok"]);
+
+        let output = run(&args);
+        assert_eq!(stdout(&output), "2 --not-a-flag \ndone \n");
+        assert_eq!(output.status.code(), Some(0));
+
+        args.pop();
+        args.push("fail");
+        let output = run(&args);
+        assert_eq!(stdout(&output), "2 --not-a-flag \n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn errors_exit_with_failure() {
+    let path = script("error", "print(\"before\");\nlen(1);\nprint(\"after\")");
+
+    let output = run(&[path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "before \n");
+    assert_eq!(
+        stderr(&output),
+        "Error: Argument to `len` not supported, got Integer\n"
+    );
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = run(&["missing-script.joe"]);
+    assert!(stderr(&output).starts_with("Error: cannot read missing-script.joe: "));
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = run(&["--vn", path.to_str().unwrap()]);
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).starts_with("Usage: monki"));
+    assert_eq!(output.status.code(), Some(2));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+fn run_with_stdin(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_monki"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn stdin_and_stderr() {
+    let path = script(
+        "stdin",
+        "let name = input(\"name? \");\nlet next = read_line();\neprint(\"got\", name, next);\nlet rest = lines();\nprint(len(rest), rest[1], read_line(), read_all())",
+    );
+
+    for engine in [None, Some("--vm")] {
+        let mut args: Vec<&str> = engine.into_iter().collect();
+        args.push(path.to_str().unwrap());
+
+        let output = run_with_stdin(&args, "monki\r\nsecond\nthree\nfour\n");
+        assert_eq!(stdout(&output), "name? 2 four null null \n");
+        assert_eq!(stderr(&output), "got monki second \n");
+        assert_eq!(output.status.code(), Some(0));
+    }
+
+    let path_all = script("read-all", "print(read_all())");
+    let output = run_with_stdin(&[path_all.to_str().unwrap()], "a\nb");
+    assert_eq!(stdout(&output), "a\nb \n");
+
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(path_all).unwrap();
+}
+
+/// Runs `monki args`, reads the first line of its stdout and closes it
+fn run_closing_stdout(args: &[&str]) -> (String, Output) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_monki"))
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut line = String::new();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    stdout.read_line(&mut line).unwrap();
+    drop(stdout);
+    (line, child.wait_with_output().unwrap())
+}
+
+#[test]
+fn closed_stdout_stops_quietly() {
+    let endless = "let f = fn(n) { print(n); f(n + 1) }; f(0)";
+    for engine in [None, Some("--vm")] {
+        let mut args: Vec<&str> = engine.into_iter().collect();
+        args.extend(["-e", endless]);
+
+        let (line, output) = run_closing_stdout(&args);
+        assert_eq!(line, "0 \n");
+        assert_eq!(stderr(&output), "");
+        assert_eq!(output.status.code(), Some(141));
+    }
+
+    let path = script("closed-stdout", &"print(1)\n".repeat(100000));
+    let (line, output) = run_closing_stdout(&["fmt", path.to_str().unwrap()]);
+    assert_eq!(line, "print(1);\n");
+    assert_eq!(stderr(&output), "");
+    assert_eq!(output.status.code(), Some(141));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn deep_nesting_and_depth_limits() {
+    // Expressions nested inside every call overflow before the native stack
+    let nested = "let f = fn(n) { if (n == 0) { 0 } else { \
+        let r = {\"a\": [[[[[[[[(1 + (1 + (1 + f(n - 1))))]]]]]]]]}; \
+        r[\"a\"][0][0][0][0][0][0][0][0] } }; print(f(990))";
+    let output = run(&["-e", nested]);
+    assert!(stderr(&output).starts_with("Error: Stack overflow: nested too deeply\n"));
+    assert_eq!(output.status.code(), Some(1));
+
+    let sum = "let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } }; print(sum(999))";
+    let output = run(&["-e", sum]);
+    assert_eq!(stdout(&output), "499500 \n");
+
+    // The VM's stack does not grow with the limit
+    let output = run(&["--vm", "--max-depth=1000000", "-e", sum]);
+    assert_eq!(stdout(&output), "499500 \n");
+
+    // Passes over the tree recurse as deep as it goes, the parser limits it
+    let chain = script("chain", &format!("print({})", vec!["1"; 100000].join("+")));
+    let parens = script(
+        "parens",
+        &format!("{}1{}", "(".repeat(50000), ")".repeat(50000)),
+    );
+    let prefix = script("prefix", &format!("{}1", "-".repeat(200000)));
+    for (path, col) in [(&chain, 2001), (&parens, 1001), (&prefix, 1001)] {
+        let file = path.to_str().unwrap();
+        let error = format!(
+            "1:{}: Expression nested too deeply, more than 1000 levels",
+            col
+        );
+        for tool in ["check", "ast", "fmt"] {
+            let output = run(&[tool, file]);
+            assert_eq!(stderr(&output), format!("{}:{}\n", file, error));
+            assert_eq!(output.status.code(), Some(1));
+        }
+        for engine in ["--max-depth=1000", "--vm"] {
+            let output = run(&[engine, file]);
+            assert_eq!(stderr(&output), format!("Error: {}\n", error));
+            assert_eq!(output.status.code(), Some(1));
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    let output = run(&["--max-depth=100000000000000", "-e", "print(1)"]);
+    assert!(stderr(&output).starts_with("Error: cannot start the interpreter"));
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn shebang_and_inline_code() {
+    let path = script("shebang", "#!/usr/bin/env monki\nprint(\"hi\", args)");
+
+    for args in [
+        vec!["run", path.to_str().unwrap(), "a"],
+        vec!["--vm", path.to_str().unwrap(), "a"],
+    ] {
+        let output = run(&args);
+        assert_eq!(stdout(&output), "hi [a] \n");
+        assert_eq!(output.status.code(), Some(0));
+    }
+
+    let output = run(&["-e", "print(1 + 2, args); exit(4)", "x"]);
+    assert_eq!(stdout(&output), "3 [x] \n");
+    assert_eq!(output.status.code(), Some(4));
+
+    let output = run(&["-e"]);
+    assert!(stderr(&output).starts_with("Usage: monki"));
+    assert_eq!(output.status.code(), Some(2));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn check_tokens_ast_and_fmt() {
+    let path = script(
+        "tools",
+        "#!/usr/bin/env monki\nlet x=1+2*3;if(x>3){print(args)}",
+    );
+    let file = path.to_str().unwrap();
+
+    let output = run(&["check", file]);
+    assert_eq!(stderr(&output), "");
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run(&["tokens", file]);
+    let tokens = stdout(&output);
+    assert_eq!(tokens.lines().count(), 22);
+    assert_eq!(
+        tokens.lines().next(),
+        Some("[Type: Keyword(Let), Literal: let, Position: (line: 1, col: 0, index: 21)]")
+    );
+
+    let output = run(&["ast", file]);
+    assert_eq!(
+        stdout(&output),
+        "let x = (1 + (2 * 3))) \n((x > 3) {[print(args)]}\n"
+    );
+
+    let output = run(&["fmt", file]);
+    assert_eq!(
+        stdout(&output),
+        "#!/usr/bin/env monki\nlet x = 1 + 2 * 3;\nif (x > 3) {\n    print(args)\n}\n"
+    );
+
+    let literals = script(
+        "literals",
+        "let x = [0xFF, 1_000, 0o17, 0b101, r\"a\\b\", \"\"\"x\ny\"\"\", \"${1+0x1}\"];",
+    );
+    let output = run(&["fmt", literals.to_str().unwrap()]);
+    assert_eq!(
+        stdout(&output),
+        "let x = [0xFF, 1_000, 0o17, 0b101, r\"a\\b\", \"\"\"x\ny\"\"\", \"${1+0x1}\"];\n"
+    );
+    std::fs::remove_file(literals).unwrap();
+
+    let broken = script("broken", "let x = ;\nprint(y)");
+    let broken_file = broken.to_str().unwrap();
+    for tool in ["check", "ast", "fmt"] {
+        let output = run(&[tool, broken_file]);
+        assert_eq!(
+            stderr(&output),
+            format!("{}:1:9: Expected an expression, got `;`\n", broken_file)
+        );
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    for (src, error) in [
+        (
+            "print(1",
+            "1:8: Expected `,` or `)` in arguments, got end of input",
+        ),
+        (
+            "[1, 2",
+            "1:6: Expected `,` or `]` in array literal, got end of input",
+        ),
+        (
+            "{\"a\": 1",
+            "1:8: Expected `,` or `}` in hash literal, got end of input",
+        ),
+        (
+            "if (x) { 1",
+            "1:11: Expected `}` at the end of the block, got end of input",
+        ),
+    ] {
+        std::fs::write(&broken, src).unwrap();
+        let output = run(&["check", broken_file]);
+        assert_eq!(stderr(&output), format!("{}:{}\n", broken_file, error));
+        assert_eq!(output.status.code(), Some(1));
+
+        let output = run(&["run", broken_file]);
+        assert_eq!(stdout(&output), "");
+        assert_eq!(stderr(&output), format!("Error: {}\n", error));
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    std::fs::write(&broken, "print(y)").unwrap();
+    let output = run(&["check", broken_file]);
+    assert_eq!(
+        stderr(&output),
+        format!("{}:1:7: Undefined variable: y\n", broken_file)
+    );
+    assert_eq!(output.status.code(), Some(1));
+
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(broken).unwrap();
+}
+
+#[test]
+fn json_dumps() {
+    let path = script("json", "#!/usr/bin/env monki\nprint(1 == 2)");
+    let file = path.to_str().unwrap();
+
+    // The flag can go before or after the file
+    let output = run(&["tokens", file, "--json"]);
+    assert_eq!(output.status.code(), Some(0));
+    let tokens = monki::json::parse(&stdout(&output)).unwrap();
+    let Object::Array(tokens) = tokens else {
+        panic!("Expected an array of tokens, got {:?}", tokens);
+    };
+    assert_eq!(tokens.len(), 7);
+    assert_eq!(
+        monki::json::stringify(&tokens[3], None).unwrap(),
+        r#"{"type":"Eq","literal":"==","position":{"line":1,"col":8,"index":29}}"#
+    );
+
+    let output = run(&["ast", "--json", file]);
+    assert_eq!(
+        stdout(&output),
+        concat!(
+            r#"[{"kind":"Expression","position":{"line":1,"col":0,"index":21},"#,
+            r#""value":{"kind":"FunctionCall","position":{"line":1,"col":5,"index":26},"#,
+            r#""function":{"kind":"Identifier","position":{"line":1,"col":0,"index":21},"name":"print"},"#,
+            r#""arguments":[{"kind":"Infix","position":{"line":1,"col":8,"index":29},"operator":"==","#,
+            r#""left":{"kind":"Integer","value":1},"right":{"kind":"Integer","value":2}}]}}]"#,
+            "\n"
+        )
+    );
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,146 @@
+use std::path::PathBuf;
+
+use monki::object::Object;
+use monki::{Error, Interpreter, Permissions};
+
+/// A fresh, empty directory under the system temp dir
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("monki-files-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Every test runs on the tree-walking evaluator and on the VM
+fn interpreters() -> [Interpreter; 2] {
+    [Interpreter::new(), Interpreter::with_vm()]
+}
+
+fn runtime_error(result: Result<Object, Error>) -> String {
+    match result {
+        Err(Error::Runtime(err)) => err.to_string(),
+        result => panic!("Expected runtime error, got {:?}", result),
+    }
+}
+
+fn string(s: &str) -> Object {
+    Object::String(s.to_string())
+}
+
+#[test]
+fn read_and_write_files() {
+    for mut interpreter in interpreters() {
+        let dir = temp_dir("read-write");
+        interpreter.set_global("dir", string(dir.to_str().unwrap()));
+
+        let result = interpreter.eval_str(
+            r#"
+            write_file("${dir}/notes.txt", "one\n");
+            append_file("${dir}/notes.txt", "two\n");
+            append_file("${dir}/log.txt", "new");
+            read_file("${dir}/notes.txt")
+            "#,
+        );
+        assert_eq!(result.unwrap(), string("one\ntwo\n"));
+        assert_eq!(std::fs::read_to_string(dir.join("log.txt")).unwrap(), "new");
+
+        // Writing replaces the contents
+        interpreter
+            .eval_str(r#"write_file("${dir}/notes.txt", "three")"#)
+            .unwrap();
+        assert_eq!(
+            interpreter
+                .eval_str(r#"read_file("${dir}/notes.txt")"#)
+                .unwrap(),
+            string("three")
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn list_dir_and_exists() {
+    for mut interpreter in interpreters() {
+        let dir = temp_dir("list");
+        std::fs::write(dir.join("b.txt"), "").unwrap();
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        interpreter.set_global("dir", string(dir.to_str().unwrap()));
+
+        assert_eq!(
+            interpreter.eval_str(r#"list_dir(dir)"#).unwrap(),
+            Object::Array(vec![string("a.txt"), string("b.txt"), string("sub")])
+        );
+        assert_eq!(
+            interpreter.eval_str(r#"list_dir("${dir}/sub")"#).unwrap(),
+            Object::Array(vec![])
+        );
+        assert_eq!(
+            interpreter.eval_str(r#"exists("${dir}/a.txt")"#).unwrap(),
+            Object::Boolean(true)
+        );
+        assert_eq!(
+            interpreter.eval_str(r#"exists("${dir}/c.txt")"#).unwrap(),
+            Object::Boolean(false)
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn file_errors() {
+    for mut interpreter in interpreters() {
+        let dir = temp_dir("errors");
+        std::fs::write(dir.join("file.txt"), "").unwrap();
+        interpreter.set_global("dir", string(dir.to_str().unwrap()));
+        let path = |name: &str| format!("{:?}", dir.join(name).display().to_string());
+
+        let tests = vec![
+            (
+                r#"read_file("${dir}/missing.txt")"#,
+                format!(
+                    "Cannot read {}: No such file or directory (os error 2)",
+                    path("missing.txt")
+                ),
+            ),
+            (
+                r#"write_file("${dir}/missing/file.txt", "")"#,
+                format!(
+                    "Cannot write {}: No such file or directory (os error 2)",
+                    path("missing/file.txt")
+                ),
+            ),
+            (
+                r#"list_dir("${dir}/file.txt")"#,
+                format!(
+                    "Cannot list {}: Not a directory (os error 20)",
+                    path("file.txt")
+                ),
+            ),
+            (
+                "read_file(1)",
+                "Argument 1 to `read_file` must be String, got Integer".to_string(),
+            ),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(runtime_error(interpreter.eval_str(input)), expected);
+        }
+
+        // Only the directories allowed can be touched
+        interpreter.set_permissions(Permissions::none().allow_read(&dir));
+        assert_eq!(
+            interpreter
+                .eval_str(r#"exists("${dir}/file.txt")"#)
+                .unwrap(),
+            Object::Boolean(true)
+        );
+        assert_eq!(
+            runtime_error(interpreter.eval_str(r#"write_file("${dir}/file.txt", "")"#)),
+            format!("Permission denied: writing {}", path("file.txt"))
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,702 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use monki::convert::{FromObject, ToObject};
+use monki::native::{Args, Ctx, NativeModule, NativeObject};
+use monki::object::Object;
+use monki::{Error, Interpreter, Permissions, Resource, RuntimeError};
+
+/// Every test runs on the tree-walking evaluator and on the VM
+fn interpreters() -> [Interpreter; 2] {
+    [Interpreter::new(), Interpreter::with_vm()]
+}
+
+#[test]
+fn eval_str_keeps_globals() {
+    for mut interpreter in interpreters() {
+        interpreter.eval_str("let x = 10;").unwrap();
+        let result = interpreter.eval_str("x * 2").unwrap();
+
+        assert_eq!(result, Object::Integer(20));
+        assert_eq!(interpreter.get_global("x"), Some(Object::Integer(10)));
+        assert_eq!(interpreter.eval_str("").unwrap(), Object::Null);
+    }
+}
+
+#[test]
+fn set_global_is_visible_to_scripts() {
+    for mut interpreter in interpreters() {
+        interpreter.set_global("name", Object::String("monki".to_string()));
+
+        let result = interpreter.eval_str(r#""hello ${name}""#).unwrap();
+
+        assert_eq!(result, Object::String("hello monki".to_string()));
+    }
+}
+
+#[test]
+fn call_function() {
+    for mut interpreter in interpreters() {
+        interpreter
+            .eval_str("let add = fn(a, b) { return a + b; };")
+            .unwrap();
+
+        let result = interpreter.call_function("add", vec![Object::Integer(1), Object::Integer(2)]);
+        assert_eq!(result.unwrap(), Object::Integer(3));
+
+        let result = interpreter.call_function("len", vec![Object::String("abc".to_string())]);
+        assert_eq!(result.unwrap(), Object::Integer(3));
+
+        match interpreter.call_function("add", vec![]) {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(
+                    err.to_string(),
+                    "Wrong number of arguments. Got 0, expected 2"
+                )
+            }
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+
+        match interpreter.call_function("missing", vec![]) {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(err.to_string(), "Identifier not found: missing")
+            }
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn return_only_leaves_the_called_function() {
+    for mut interpreter in interpreters() {
+        let result = interpreter
+            .eval_str("let one = fn() { return 1; }; let two = one() + 1; two * 10")
+            .unwrap();
+
+        assert_eq!(result, Object::Integer(20));
+    }
+}
+
+#[test]
+fn errors() {
+    for mut interpreter in interpreters() {
+        match interpreter.eval_str("let s = \"\\q\";") {
+            Err(Error::Syntax(errors)) => {
+                assert_eq!(errors, vec!["1:9: Unknown escape character: q".to_string()])
+            }
+            result => panic!("Expected syntax error, got {:?}", result),
+        }
+
+        // Runtime errors stop evaluation
+        match interpreter.eval_str("let a = 1; let b = len(1); let c = 3;") {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(
+                    err.to_string(),
+                    "Argument to `len` not supported, got Integer"
+                )
+            }
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+        assert_eq!(interpreter.get_global("a"), Some(Object::Integer(1)));
+        assert_eq!(interpreter.get_global("c"), None);
+    }
+}
+
+#[test]
+fn max_depth() {
+    for mut interpreter in interpreters() {
+        interpreter.set_max_depth(20);
+        interpreter
+            .eval_str("let down = fn(n) { if (n == 0) { 0 } else { 1 + down(n - 1) } };")
+            .unwrap();
+
+        assert_eq!(
+            interpreter.eval_str("down(19)").unwrap(),
+            Object::Integer(19)
+        );
+        match interpreter.eval_str("down(20)") {
+            Err(Error::Runtime(RuntimeError::StackOverflow { limit, trace, .. })) => {
+                assert_eq!(limit, 20);
+                assert_eq!(trace, vec!["down at 1:53".to_string(); 10]);
+            }
+            result => panic!("Expected stack overflow, got {:?}", result),
+        }
+
+        // The interpreter is still usable afterwards
+        assert_eq!(
+            interpreter.eval_str("down(19)").unwrap(),
+            Object::Integer(19)
+        );
+    }
+}
+
+#[test]
+fn nesting_limit() {
+    // Calls made by natives nest on the native stack even in tail position
+    let thread = std::thread::Builder::new()
+        .stack_size(64 << 20)
+        .spawn(|| {
+            for mut interpreter in interpreters() {
+                interpreter.set_max_depth(20);
+                interpreter.register_native("apply", |ctx, args| {
+                    args.expect_len(2)?;
+                    ctx.call(&args[0], vec![args[1].clone()])
+                });
+
+                let nested = format!("{}1{}", "[".repeat(200), "]".repeat(200));
+                match interpreter.eval_str(&nested) {
+                    Err(Error::Runtime(error @ RuntimeError::StackOverflow { .. })) => {
+                        assert_eq!(error.to_string(), "Stack overflow: nested too deeply")
+                    }
+                    // The VM keeps nested values on its own stack
+                    Ok(_) => {}
+                    result => panic!("Expected stack overflow, got {:?}", result),
+                }
+
+                let endless = "let f = fn(n) { apply(fn(x) { f(x) }, n) }; f(1)";
+                match interpreter.eval_str(endless) {
+                    Err(Error::Runtime(RuntimeError::StackOverflow { limit, calls, .. })) => {
+                        assert_eq!(limit, 20);
+                        assert!(calls <= limit);
+                    }
+                    result => panic!("Expected stack overflow, got {:?}", result),
+                }
+                assert_eq!(
+                    interpreter.eval_str("[[1]][0][0]").unwrap(),
+                    Object::Integer(1)
+                );
+            }
+        })
+        .unwrap();
+    thread.join().unwrap();
+}
+
+#[test]
+fn fuel_and_deadline() {
+    for mut interpreter in interpreters() {
+        interpreter
+            .eval_str("let spin = fn(n) { spin(n + 1) }; let one = fn() { 1 };")
+            .unwrap();
+
+        interpreter.set_fuel(Some(100));
+        assert_eq!(interpreter.eval_str("one()").unwrap(), Object::Integer(1));
+        assert!(interpreter.fuel().unwrap() < 100);
+
+        match interpreter.eval_str("spin(0)") {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(err, RuntimeError::ResourceExhausted(Resource::Fuel));
+                assert_eq!(err.to_string(), "Out of fuel");
+            }
+            result => panic!("Expected to run out of fuel, got {:?}", result),
+        }
+        assert_eq!(interpreter.fuel(), Some(0));
+
+        interpreter.set_fuel(None);
+        interpreter.set_deadline(Some(Instant::now() + Duration::from_millis(50)));
+        match interpreter.eval_str("spin(0)") {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(err, RuntimeError::ResourceExhausted(Resource::Time));
+                assert_eq!(err.to_string(), "Deadline exceeded");
+            }
+            result => panic!("Expected to time out, got {:?}", result),
+        }
+
+        interpreter.set_deadline(None);
+        assert_eq!(interpreter.eval_str("one()").unwrap(), Object::Integer(1));
+    }
+}
+
+#[test]
+fn value_size_limit() {
+    for mut interpreter in interpreters() {
+        interpreter
+            .eval_str(
+                "import std.strings
+                let double = fn(s, n) { if (n == 0) { len(s) } else { double(s + s, n - 1) } };
+                let grow = fn(a, n) { if (n == 0) { len(a) } else { grow(push(a, n), n - 1) } };
+                let nest = fn(a, n) { if (n == 0) { a } else { nest([a], n - 1) } };",
+            )
+            .unwrap();
+        interpreter.set_value_size_limit(Some(10_000));
+
+        assert_eq!(
+            interpreter.eval_str("double(\"ab\", 5)").unwrap(),
+            Object::Integer(64)
+        );
+        assert_eq!(
+            interpreter.eval_str("grow([], 10)").unwrap(),
+            Object::Integer(10)
+        );
+
+        for src in [
+            "double(\"ab\", 20)",
+            "grow([], 1000)",
+            "push([], strings.repeat(\"a\", 9960))",
+            "strings.repeat(\"a\", 9000) + strings.repeat(\"b\", 1000)",
+            "strings.repeat(\"ab\", 1000000000000000)",
+            "format(\"{:65535}\", 1)",
+            "json_stringify(nest([], 50), 10)",
+        ] {
+            match interpreter.eval_str(src) {
+                Err(Error::Runtime(err)) => {
+                    assert_eq!(err, RuntimeError::ResourceExhausted(Resource::ValueSize));
+                    assert_eq!(err.to_string(), "Value too large");
+                }
+                result => panic!("Expected a value too large in {}, got {:?}", src, result),
+            }
+        }
+
+        interpreter.set_value_size_limit(None);
+        assert_eq!(
+            interpreter.eval_str("double(\"ab\", 12)").unwrap(),
+            Object::Integer(8192)
+        );
+    }
+}
+
+#[test]
+fn permissions() {
+    for mut interpreter in interpreters() {
+        interpreter.set_permissions(Permissions::none());
+
+        for src in ["print(1)", "printf(\"{}\", 1)"] {
+            match interpreter.eval_str(src) {
+                Err(Error::Runtime(err)) => {
+                    assert_eq!(
+                        err,
+                        RuntimeError::PermissionDenied("writing to stdout".to_string())
+                    );
+                    assert_eq!(err.to_string(), "Permission denied: writing to stdout");
+                }
+                result => panic!("Expected permission error in {}, got {:?}", src, result),
+            }
+        }
+
+        assert_eq!(
+            interpreter.eval_str("lines()").unwrap_err().to_string(),
+            "Permission denied: reading from stdin"
+        );
+        assert_eq!(
+            interpreter.eval_str("eprint(1)").unwrap_err().to_string(),
+            "Permission denied: writing to stderr"
+        );
+        for src in ["time()", "random(6)"] {
+            assert_eq!(
+                interpreter.eval_str(src).unwrap_err().to_string(),
+                "Permission denied: reading the clock or random numbers"
+            );
+        }
+
+        // Natives of the host can check the same permissions
+        interpreter.register_native("home", |ctx, args| {
+            args.expect_len(0)?;
+            ctx.permissions().check_env("HOME")?;
+            Ok(Object::String("/home/monki".to_string()))
+        });
+        assert_eq!(
+            interpreter.eval_str("home()").unwrap_err().to_string(),
+            "Permission denied: reading environment variable \"HOME\""
+        );
+
+        interpreter.set_permissions(Permissions::none().allow_stdout().allow_env());
+        assert_eq!(interpreter.eval_str("print(1)").unwrap(), Object::Empty);
+        assert_eq!(
+            interpreter.eval_str("home()").unwrap(),
+            Object::String("/home/monki".to_string())
+        );
+
+        interpreter.set_permissions(Permissions::none().allow_clock());
+        assert_eq!(
+            interpreter.eval_str("time() > 0").unwrap(),
+            Object::Boolean(true)
+        );
+    }
+}
+
+#[test]
+fn exit_and_env_var() {
+    for mut interpreter in interpreters() {
+        interpreter.set_global("x", Object::Integer(1));
+
+        // `exit` stops the script and leaves what it means to the host
+        for (src, code) in [("exit(3); x = 2;", 3), ("exit()", 0)] {
+            match interpreter.eval_str(src) {
+                Err(Error::Runtime(RuntimeError::Exit(got))) => assert_eq!(got, code),
+                result => panic!("Expected exit, got {:?}", result),
+            }
+        }
+        assert_eq!(interpreter.get_global("x"), Some(Object::Integer(1)));
+        assert_eq!(
+            interpreter
+                .eval_str("exit(10000000000)")
+                .unwrap_err()
+                .to_string(),
+            "Invalid exit status: 10000000000"
+        );
+
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(
+            interpreter.eval_str(r#"env_var("PATH")"#).unwrap(),
+            Object::String(path)
+        );
+        assert_eq!(
+            interpreter
+                .eval_str(r#"env_var("MONKI_SURELY_UNSET")"#)
+                .unwrap(),
+            Object::Null
+        );
+    }
+}
+
+#[test]
+fn time_and_random() {
+    for mut interpreter in interpreters() {
+        let before = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        match interpreter.eval_str("time()").unwrap() {
+            Object::Integer(millis) => assert!(millis >= before && millis - before < 60_000),
+            result => panic!("Expected integer, got {:?}", result),
+        }
+
+        // Every side comes up in enough rolls of a die, and nothing else
+        let rolls = "import std.strings; \
+            let roll = fn(n, seen) { if (n == 0) { seen } else { roll(n - 1, seen + \"${random(6)}\") } }; \
+            let seen = roll(1000, \"\"); \
+            [strings.contains(seen, \"0\"), strings.contains(seen, \"5\"), strings.contains(seen, \"6\")]";
+        assert_eq!(
+            interpreter.eval_str(rolls).unwrap().to_string(),
+            "[true, true, false]"
+        );
+        assert_eq!(
+            interpreter.eval_str("random(0)").unwrap_err().to_string(),
+            "Argument to `random` must be positive, got 0"
+        );
+    }
+}
+
+#[test]
+fn variables_are_resolved_before_running() {
+    for mut interpreter in interpreters() {
+        // Nothing runs when a name is undefined
+        match interpreter.eval_str("let a = 1;\nlet f = fn(x) { x + y };") {
+            Err(Error::Syntax(errors)) => {
+                assert_eq!(errors, vec!["2:21: Undefined variable: y".to_string()])
+            }
+            result => panic!("Expected syntax error, got {:?}", result),
+        }
+        assert_eq!(interpreter.get_global("a"), None);
+
+        let input = "
+let counter = fn() {
+    let count = 0;
+    fn() { count = count + 1; count }
+};
+let next = counter();
+next(); next();
+let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } };
+sum(100) + next()";
+        assert_eq!(interpreter.eval_str(input).unwrap(), Object::Integer(5053));
+
+        // Globals of earlier evaluations stay visible
+        assert_eq!(interpreter.eval_str("next()").unwrap(), Object::Integer(4));
+    }
+}
+
+#[test]
+fn local_functions_see_later_lets() {
+    for mut interpreter in interpreters() {
+        let input = "
+let h = fn() { let f = fn() { g() }; let g = fn() { 1 }; f() };
+let parity = fn(n) {
+    let even = fn(n) { if (n == 0) { true } else { odd(n - 1) } };
+    let odd = fn(n) { if (n == 0) { false } else { even(n - 1) } };
+    [even(n), odd(n)]
+};
+[h(), parity(7)]";
+        assert_eq!(
+            interpreter.eval_str(input).unwrap().to_string(),
+            "[1, [false, true]]"
+        );
+
+        // A local shadows the outer name in all of its function
+        match interpreter.eval_str("let x = 1; let f = fn() { let y = x; let x = 2; y }; f()") {
+            Err(Error::Runtime(error)) => {
+                assert_eq!(error.to_string(), "Identifier not found: x")
+            }
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn closures_assign_captured_variables() {
+    for mut interpreter in interpreters() {
+        // Assigning a captured variable changes it for every closure
+        // sharing it instead of defining a new one in the call
+        let input = "
+let counter = fn() {
+    let count = 0;
+    [fn() { count = count + 1; count }, fn() { count }]
+};
+let c = counter();
+c[0](); c[0]();
+[c[0](), c[1]()]";
+        assert_eq!(interpreter.eval_str(input).unwrap().to_string(), "[3, 3]");
+    }
+}
+
+#[test]
+fn eval_file() {
+    let path = std::env::temp_dir().join(format!("monki-eval-file-{}.joe", std::process::id()));
+    std::fs::write(&path, "let double = fn(x) { x * 2 };\ndouble(21)").unwrap();
+
+    for mut interpreter in interpreters() {
+        let result = interpreter.eval_file(&path);
+
+        assert_eq!(result.unwrap(), Object::Integer(42));
+        assert!(matches!(
+            interpreter.eval_file("/does/not/exist.joe"),
+            Err(Error::Io(_))
+        ));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn register_typed_functions() {
+    for mut interpreter in interpreters() {
+        interpreter.register_fn("longer_than", |n: i64, s: String| {
+            s.chars().count() as i64 > n
+        });
+        interpreter.register_fn("repeat", |s: String, n: i64| s.repeat(n as usize));
+
+        assert_eq!(
+            interpreter.eval_str(r#"longer_than(2, "abc")"#).unwrap(),
+            Object::Boolean(true)
+        );
+        assert_eq!(
+            interpreter.eval_str(r#"repeat("ab", 3)"#).unwrap(),
+            Object::String("ababab".to_string())
+        );
+
+        match interpreter.eval_str(r#"longer_than("abc", 2)"#) {
+            Err(Error::Runtime(err)) => assert_eq!(
+                err,
+                RuntimeError::Type {
+                    function: "longer_than".to_string(),
+                    position: 1,
+                    expected: "Integer".to_string(),
+                    got: "String",
+                }
+            ),
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+
+        match interpreter.eval_str("repeat(\"ab\")") {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(
+                    err.to_string(),
+                    "Wrong number of arguments. Got 1, expected 2"
+                )
+            }
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+
+        match interpreter.eval_str("push(1, 2)") {
+            Err(Error::Runtime(err)) => assert_eq!(
+                err.to_string(),
+                "Argument 1 to `push` must be Array, got Integer"
+            ),
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn register_natives_and_modules() {
+    for mut interpreter in interpreters() {
+        interpreter.register_native("apply_twice", |ctx, args| {
+            args.expect_len(2)?;
+            let once = ctx.call(&args[0], vec![args[1].clone()])?;
+            ctx.call(&args[0], vec![once])
+        });
+        interpreter.register_module(
+            NativeModule::new("math")
+                .function("abs", |n: i64| n.abs())
+                .function("checked_div", |a: i64, b: i64| {
+                    a.checked_div(b)
+                        .ok_or(RuntimeError::new("Division by zero"))
+                }),
+        );
+
+        assert_eq!(
+            interpreter
+                .eval_str("apply_twice(fn(x) { x * 3 }, 2)")
+                .unwrap(),
+            Object::Integer(18)
+        );
+        assert_eq!(
+            interpreter
+                .eval_str("math.abs(-4) + math.checked_div(9, 3)")
+                .unwrap(),
+            Object::Integer(7)
+        );
+
+        // Errors raised by callbacks and natives propagate as they are
+        match interpreter.eval_str("apply_twice(fn(x) { x(1) }, 2)") {
+            Err(Error::Runtime(err)) => assert_eq!(err.to_string(), "Not a function: 2"),
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+        match interpreter.eval_str("math.checked_div(1, 0)") {
+            Err(Error::Runtime(err)) => assert_eq!(err, RuntimeError::new("Division by zero")),
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Row {
+    id: i64,
+    name: RefCell<String>,
+}
+
+impl std::fmt::Display for Row {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Row({}, {})", self.id, self.name.borrow())
+    }
+}
+
+impl NativeObject for Row {
+    fn type_name(&self) -> &'static str {
+        "Row"
+    }
+
+    fn get(&self, name: &str) -> Option<Object> {
+        match name {
+            "id" => Some(Object::Integer(self.id)),
+            "name" => Some(Object::String(self.name.borrow().clone())),
+            _ => None,
+        }
+    }
+
+    fn set(&self, name: &str, value: Object) -> Result<(), RuntimeError> {
+        match (name, value) {
+            ("name", Object::String(value)) => {
+                *self.name.borrow_mut() = value;
+                Ok(())
+            }
+            (name, _) => Err(RuntimeError::new(&format!("Cannot set `{}`", name))),
+        }
+    }
+
+    fn call_method(&self, _: &mut Ctx<'_>, name: &str, args: Args) -> Result<Object, RuntimeError> {
+        match name {
+            "greet" => {
+                args.expect_len(1)?;
+                let greeting: String = args.arg(0)?;
+                Ok(Object::String(format!(
+                    "{}, {}",
+                    greeting,
+                    self.name.borrow()
+                )))
+            }
+            _ => Err(RuntimeError::new(&format!("No method `{}` on Row", name))),
+        }
+    }
+
+    fn equals(&self, other: &dyn NativeObject) -> bool {
+        other
+            .downcast_ref::<Row>()
+            .is_some_and(|other| other.id == self.id)
+    }
+}
+
+#[test]
+fn native_objects() {
+    let row = Rc::new(Row {
+        id: 1,
+        name: RefCell::new("ann".to_string()),
+    });
+    for mut interpreter in interpreters() {
+        interpreter.set_global("row", Object::Native(row.clone()));
+        interpreter.set_global(
+            "same",
+            Object::Native(Rc::new(Row {
+                id: 1,
+                name: RefCell::new("other".to_string()),
+            })),
+        );
+
+        assert_eq!(
+            interpreter.eval_str("row.id + 1").unwrap(),
+            Object::Integer(2)
+        );
+        interpreter.eval_str(r#"row.name = "bob";"#).unwrap();
+        assert_eq!(*row.name.borrow(), "bob");
+        assert_eq!(
+            interpreter.eval_str(r#"row.greet("hi")"#).unwrap(),
+            Object::String("hi, bob".to_string())
+        );
+        assert_eq!(
+            interpreter.eval_str(r#""${row}""#).unwrap(),
+            Object::String("Row(1, bob)".to_string())
+        );
+        assert_eq!(
+            interpreter.eval_str("row == same").unwrap(),
+            Object::Boolean(true)
+        );
+
+        let tests = vec![
+            ("row.missing", "No property `missing` on Row"),
+            ("row.id = 2", "Cannot set `id`"),
+            (
+                "row.greet(1)",
+                "Argument 1 to `Row.greet` must be String, got Integer",
+            ),
+            ("row.save()", "No method `save` on Row"),
+        ];
+        for (input, expected) in tests {
+            match interpreter.eval_str(input) {
+                Err(Error::Runtime(err)) => assert_eq!(err.to_string(), expected),
+                result => panic!("Expected runtime error, got {:?}", result),
+            }
+        }
+    }
+}
+
+#[test]
+fn convert_structured_data() {
+    let mut limits = BTreeMap::new();
+    limits.insert("cpu".to_string(), 2);
+    limits.insert("memory".to_string(), 512);
+
+    for mut interpreter in interpreters() {
+        interpreter.set_global("limits", limits.to_object());
+        interpreter.register_fn("total", |ports: Vec<HashMap<String, i64>>| {
+            ports.iter().flat_map(|port| port.values()).sum::<i64>()
+        });
+
+        let result = interpreter
+            .eval_str("[limits.cpu * 2, limits.memory]")
+            .unwrap();
+        assert_eq!(Vec::<u32>::from_object(result), Ok(vec![4, 512]));
+        assert_eq!(
+            interpreter
+                .eval_str(r#"total([{"a": 1}, {"b": 2, "c": 3}])"#)
+                .unwrap(),
+            Object::Integer(6)
+        );
+
+        match interpreter.eval_str(r#"total([{"a": 1}, {"b": "2"}])"#) {
+            Err(Error::Runtime(err)) => assert_eq!(
+                err.to_string(),
+                "Argument 1 to `total`: Expected Integer at [1][\"b\"], got String"
+            ),
+            result => panic!("Expected runtime error, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::path::PathBuf;
+
+use monki::object::Object;
+use monki::{Error, Interpreter, Permissions};
+
+/// A fresh directory under the system temp dir holding `files`
+fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("monki-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    for (path, src) in files {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, src).unwrap();
+    }
+
+    dir
+}
+
+/// Every test runs on the tree-walking evaluator and on the VM
+fn interpreters() -> [Interpreter; 2] {
+    [Interpreter::new(), Interpreter::with_vm()]
+}
+
+fn runtime_error(result: Result<Object, Error>) -> String {
+    match result {
+        Err(Error::Runtime(err)) => err.to_string(),
+        result => panic!("Expected runtime error, got {:?}", result),
+    }
+}
+
+#[test]
+fn import_as() {
+    for mut interpreter in interpreters() {
+        let dir = write_files(
+        "import-as",
+        &[
+            (
+                "main.joe",
+                "import \"lib/math.joe\" as math;\nmath.square(math.base)",
+            ),
+            (
+                "lib/math.joe",
+                "import \"./helpers.joe\" as helpers;\nlet base = helpers.three;\nlet square = fn(x) { x * x };",
+            ),
+            ("lib/helpers.joe", "let three = 3;"),
+        ],
+    );
+
+        let result = interpreter.eval_file(dir.join("main.joe"));
+        assert_eq!(result.unwrap(), Object::Integer(9));
+
+        // Module bindings stay out of the importing scope
+        assert_eq!(interpreter.get_global("square"), None);
+        assert!(matches!(
+            interpreter.get_global("math"),
+            Some(Object::Module { .. })
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn modules_are_cached() {
+    for mut interpreter in interpreters() {
+        let dir = write_files(
+        "module-cache",
+        &[
+            (
+                "main.joe",
+                "import \"counter.joe\" as a;\na.count = a.count + 1;\nimport \"./counter.joe\" as b;\nb.count",
+            ),
+            ("counter.joe", "let count = 0;"),
+        ],
+    );
+
+        let result = interpreter.eval_file(dir.join("main.joe"));
+        assert_eq!(result.unwrap(), Object::Integer(1));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn import_errors() {
+    for mut interpreter in interpreters() {
+        let dir = write_files(
+            "import-errors",
+            &[
+                ("a.joe", "import \"b.joe\" as b;"),
+                ("b.joe", "import \"a.joe\" as a;"),
+                ("broken.joe", "let = 1;\nlet x = \"\\q\";"),
+                ("lib.joe", "let x = 1;"),
+            ],
+        );
+        let canonical = dir.canonicalize().unwrap();
+
+        assert_eq!(
+            runtime_error(interpreter.eval_file(dir.join("a.joe"))),
+            format!(
+                "Import cycle: {a} -> {b} -> {a}",
+                a = canonical.join("a.joe").display(),
+                b = canonical.join("b.joe").display()
+            )
+        );
+
+        let tests = vec![
+            (
+                "import \"broken.joe\" as broken;",
+                "Syntax error in \"broken.joe\": 1:5: Expected a name after `let`, got `=`, 2:9: Unknown escape character: q",
+            ),
+            (
+                "import \"lib.joe\" as lib; lib.y",
+                "Module \"lib.joe\" has no member `y`",
+            ),
+        ];
+        for (input, expected) in tests {
+            let path = dir.join("main.joe");
+            std::fs::write(&path, input).unwrap();
+            assert_eq!(runtime_error(interpreter.eval_file(&path)), expected);
+        }
+
+        let err = runtime_error(interpreter.eval_str("import \"/does/not/exist.joe\" as x;"));
+        assert!(
+            err.starts_with("Cannot import \"/does/not/exist.joe\": "),
+            "{}",
+            err
+        );
+
+        match interpreter.eval_str("import lib as x;") {
+            Err(Error::Syntax(errors)) => assert_eq!(
+                errors,
+                vec![
+                    "1:8: Expected a string path or std module after `import`, got `lib`"
+                        .to_string()
+                ]
+            ),
+            result => panic!("Expected syntax error, got {:?}", result),
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn exports_and_selective_imports() {
+    for mut interpreter in interpreters() {
+        let dir = write_files(
+            "exports",
+            &[
+                (
+                    "main.joe",
+                    "import { area, unit as u } from \"shapes.joe\";\narea(3) + u",
+                ),
+                (
+                    "shapes.joe",
+                    "let pi = 3;\nexport let unit = 1;\nexport fn area(r) { pi * r * r }",
+                ),
+                ("open.joe", "let anything = 2;"),
+            ],
+        );
+
+        let result = interpreter.eval_file(dir.join("main.joe"));
+        assert_eq!(result.unwrap(), Object::Integer(28));
+        assert_eq!(interpreter.get_global("u"), Some(Object::Integer(1)));
+        assert_eq!(interpreter.get_global("unit"), None);
+        assert_eq!(interpreter.get_global("pi"), None);
+
+        let tests = vec![
+            (
+                "import { pi } from \"shapes.joe\";",
+                "`pi` is not exported by \"shapes.joe\"",
+            ),
+            (
+                "import \"shapes.joe\" as shapes; shapes.pi",
+                "`pi` is not exported by \"shapes.joe\"",
+            ),
+            (
+                "import { tau } from \"shapes.joe\";",
+                "Module \"shapes.joe\" has no member `tau`",
+            ),
+        ];
+        for (input, expected) in tests {
+            let path = dir.join("main.joe");
+            std::fs::write(&path, input).unwrap();
+            assert_eq!(runtime_error(interpreter.eval_file(&path)), expected);
+        }
+
+        // Modules without exports keep everything public
+        let path = dir.join("main.joe");
+        std::fs::write(&path, "import { anything } from \"open.joe\"; anything").unwrap();
+        assert_eq!(interpreter.eval_file(&path).unwrap(), Object::Integer(2));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[test]
+fn std_modules() {
+    for mut interpreter in interpreters() {
+        let tests = vec![
+        ("import std.math; math.pow(2, 10) + math.sqrt(17)", Object::Integer(1028)),
+        ("import std.math; math.rem(-9223372036854775807 - 1, -1)", Object::Integer(0)),
+        (
+            "import std.arrays as arrays; arrays.sum(arrays.map(arrays.range(1, 4), fn(x) { x * x }))",
+            Object::Integer(14),
+        ),
+        (
+            r#"import { split, join } from std.strings; join(split("a b c", " "), ",")"#,
+            Object::String("a,b,c".to_string()),
+        ),
+        (
+            r#"import std.strings; [strings.pad_left("7", 3, "0"), strings.pad_right("ab", 3, ".")]"#,
+            Object::Array(vec![Object::String("007".to_string()), Object::String("ab.".to_string())]),
+        ),
+        (
+            // Nothing to pad with
+            r#"import std.strings; strings.pad_left("ab", 5, "") + strings.pad_right("c", 5, "")"#,
+            Object::String("abc".to_string()),
+        ),
+    ];
+        for (input, expected) in tests {
+            assert_eq!(interpreter.eval_str(input).unwrap(), expected, "{}", input);
+        }
+
+        // Loaded once per interpreter, later imports share the module
+        interpreter
+            .eval_str("import std.math as first; first.abs = 1;")
+            .unwrap();
+        assert_eq!(
+            interpreter
+                .eval_str("import std.math as again; again.abs")
+                .unwrap(),
+            Object::Integer(1)
+        );
+        assert_eq!(
+            Interpreter::new()
+                .eval_str("import std.math; math.abs(-1)")
+                .unwrap(),
+            Object::Integer(1)
+        );
+
+        let tests = vec![
+            ("import std.nope;", "Unknown standard module `std.nope`"),
+            (
+                "import { fold } from std.arrays;",
+                "`fold` is not exported by \"std.arrays\"",
+            ),
+            (
+                "import std.math; math.sqrt(-4)",
+                "Square root of negative number: -4",
+            ),
+            ("import std.math; math.rem(1, 0)", "Division by zero"),
+            (
+                "import std.strings; strings.upper(1)",
+                "Argument 1 to `std.strings.upper` must be String, got Integer",
+            ),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(runtime_error(interpreter.eval_str(input)), expected);
+        }
+    }
+}
+
+#[test]
+fn imports_need_read_permission() {
+    for mut interpreter in interpreters() {
+        let dir = write_files(
+            "import-permission",
+            &[
+                (
+                    "main.joe",
+                    "import \"lib/a.joe\" as a;\nimport \"b.joe\" as b;\na.x",
+                ),
+                ("lib/a.joe", "let x = 1;"),
+                ("b.joe", "let y = 2;"),
+            ],
+        );
+
+        interpreter.set_permissions(Permissions::none().allow_read(dir.join("lib")));
+        assert_eq!(
+            runtime_error(interpreter.eval_file(dir.join("main.joe"))),
+            format!(
+                "Permission denied: reading {:?}",
+                dir.join("b.joe").display().to_string()
+            )
+        );
+
+        interpreter.set_permissions(Permissions::none().allow_read(&dir));
+        assert_eq!(
+            interpreter.eval_file(dir.join("main.joe")).unwrap(),
+            Object::Integer(1)
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}